@@ -1,5 +1,6 @@
 //! Client configuration
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Author {
@@ -74,6 +75,26 @@ impl Signing {
     }
 }
 
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Reducers {
+    /// Maximum execution time of a reducer per record (in milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Maximum heap size of reducers (in bytes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heap_limit: Option<usize>,
+}
+
+impl Reducers {
+    pub fn is_none(&self) -> bool {
+        self.timeout.is_none() && self.heap_limit.is_none()
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_millis)
+    }
+}
+
 #[derive(Serialize, Clone, Deserialize)]
 pub struct ExtensibleConfiguration<T> {
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -84,6 +105,8 @@ pub struct ExtensibleConfiguration<T> {
     pub records: JMESPathConfig,
     #[serde(default, skip_serializing_if = "Signing::is_none")]
     pub signing: Signing,
+    #[serde(default, skip_serializing_if = "Reducers::is_none")]
+    pub reducers: Reducers,
     #[serde(default, flatten)]
     pub extra: T,
 }
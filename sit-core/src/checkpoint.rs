@@ -0,0 +1,293 @@
+//! Reduction checkpoints
+//!
+//! Reducing a long history of records again and again is wasteful, as it mostly
+//! consists of reducing the same records. A checkpoint captures both the state
+//! and the private state of reducers (see [`StatefulReducer`]) after a number of
+//! record generations, so that subsequent reductions only have to reduce records
+//! added since then.
+//!
+//! A checkpoint is only resumed from if it was taken with the same reducers
+//! (identified by a fingerprint, see [`fingerprint`]) and if the records it
+//! covers are exactly the first generations of records being reduced. Adding a
+//! record that doesn't descend from covered records (e.g. a new root) therefore
+//! results in a complete reduction.
+//!
+//! Checkpoints are stored as `HASH.json` (where `HASH` is the hash of the last record
+//! covered) in a directory that is not meant to be shared (see [`Repository::checkpoints_path`]).
+//!
+//! [`StatefulReducer`]: ../reducers/trait.StatefulReducer.html
+//! [`fingerprint`]: fn.fingerprint.html
+//! [`Repository::checkpoints_path`]: ../repository/struct.Repository.html#method.checkpoints_path
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::{self, Map, Value as JsonValue};
+
+use hash::HashingAlgorithm;
+use record::{Record, RecordContainer, ReductionError};
+use reducers::StatefulReducer;
+
+/// Reduction checkpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Hash of the last record reduced
+    pub record: String,
+    /// Hashes of all records reduced
+    pub records: Vec<String>,
+    /// Reducers' fingerprint
+    pub fingerprint: String,
+    /// Reduced state
+    pub state: Map<String, JsonValue>,
+    /// Reducers' private state
+    pub reducer: JsonValue,
+}
+
+/// Result of a reduction with checkpoints
+#[derive(Debug)]
+pub struct Reduction {
+    /// Reduced state
+    pub state: Map<String, JsonValue>,
+    /// Hash of the last record of the checkpoint the reduction was resumed from
+    pub resumed_from: Option<String>,
+    /// New checkpoint (if any records were reduced and reducer's private state could be taken)
+    pub checkpoint: Option<Checkpoint>,
+}
+
+/// Calculates reducers' fingerprint
+///
+/// `parts` should include everything that affects the outcome of the reduction
+/// (such as reducers' source code and relevant configuration).
+pub fn fingerprint<I, P>(hashing_algorithm: &HashingAlgorithm, parts: I) -> String
+    where I: IntoIterator<Item = P>, P: AsRef<[u8]> {
+    let mut hasher = hashing_algorithm.hasher();
+    for part in parts {
+        let part = part.as_ref();
+        // length prefix keeps ["ab", "c"] and ["a", "bc"] apart
+        hasher.process(format!("{}:", part.len()).as_bytes());
+        hasher.process(part);
+    }
+    hasher.result_box().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Loads checkpoints with a given fingerprint, the most recent ones first
+///
+/// Unreadable checkpoints are ignored.
+pub fn load<P: AsRef<Path>>(path: P, fingerprint: &str) -> Vec<Checkpoint> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut checkpoints: Vec<Checkpoint> = entries.filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|ext| ext == "json").unwrap_or(false))
+        .filter_map(|path| fs::File::open(path).ok())
+        .filter_map(|file| serde_json::from_reader(file).ok())
+        .filter(|checkpoint: &Checkpoint| checkpoint.fingerprint == fingerprint)
+        .collect();
+    checkpoints.sort_by(|a, b| b.records.len().cmp(&a.records.len()));
+    checkpoints
+}
+
+/// Saves a checkpoint and removes checkpoints it supersedes
+pub fn save<P: AsRef<Path>>(path: P, checkpoint: &Checkpoint) -> Result<(), io::Error> {
+    let path = path.as_ref();
+    fs::create_dir_all(path)?;
+    // checkpoints are local to this copy of the repository
+    let gitignore = path.join(".gitignore");
+    if !gitignore.exists() {
+        fs::File::create(gitignore)?.write_all(b"*\n")?;
+    }
+    let records: HashSet<_> = checkpoint.records.iter().collect();
+    for old in load(path, &checkpoint.fingerprint) {
+        if old.record != checkpoint.record && old.records.iter().all(|r| records.contains(r)) {
+            let _ = fs::remove_file(path.join(format!("{}.json", old.record)));
+        }
+    }
+    let file = fs::File::create(path.join(format!("{}.json", checkpoint.record)))?;
+    serde_json::to_writer(file, checkpoint)?;
+    Ok(())
+}
+
+/// Reduces records, resuming from the most recent applicable checkpoint
+///
+/// Returns a new checkpoint if any records were reduced.
+pub fn reduce<RC, R>(container: &RC, reducer: &mut R, state: Map<String, JsonValue>,
+                     checkpoints: Vec<Checkpoint>, fingerprint: &str) -> Result<Reduction, ReductionError<RC::Error>>
+    where RC: RecordContainer, R: StatefulReducer<State = Map<String, JsonValue>, Item = RC::Record> {
+    let generations: Vec<Vec<RC::Record>> = container.record_iter()?.map(|records| records.into_iter().collect()).collect();
+    // number of records in the first `n` generations
+    let mut sizes = vec![0];
+    for generation in generations.iter() {
+        let size = sizes[sizes.len() - 1] + generation.len();
+        sizes.push(size);
+    }
+
+    let mut state = state;
+    let mut skip = 0;
+    let mut resumed_from = None;
+    let initial = reducer.private_state();
+    for checkpoint in checkpoints.into_iter().filter(|c| c.fingerprint == fingerprint) {
+        let generations_covered = match sizes.iter().position(|size| *size == checkpoint.records.len()) {
+            Some(n) if n > 0 => n,
+            _ => continue,
+        };
+        let records: HashSet<_> = checkpoint.records.iter().map(String::as_str).collect();
+        let covers = generations[..generations_covered].iter()
+            .all(|generation| generation.iter().all(|record| records.contains(record.encoded_hash().as_ref())));
+        if !covers {
+            continue;
+        }
+        if reducer.set_private_state(checkpoint.reducer) {
+            state = checkpoint.state;
+            skip = generations_covered;
+            resumed_from = Some(checkpoint.record);
+            break;
+        }
+        // the snapshot might have been partially restored
+        if let Some(ref initial) = initial {
+            reducer.set_private_state(initial.clone());
+        }
+    }
+
+    for generation in generations[skip..].iter() {
+        for record in generation {
+            state = reducer.reduce(state, record);
+        }
+    }
+
+    let checkpoint = match (skip < generations.len(), reducer.private_state()) {
+        (true, Some(reducer_state)) => {
+            let records: Vec<String> = generations.iter().flat_map(|generation| generation.iter())
+                .map(|record| record.encoded_hash().as_ref().to_owned()).collect();
+            records.last().cloned().map(|record| Checkpoint {
+                record,
+                records,
+                fingerprint: fingerprint.into(),
+                state: state.clone(),
+                reducer: reducer_state,
+            })
+        },
+        _ => None,
+    };
+
+    Ok(Reduction { state, resumed_from, checkpoint })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use record::RecordOwningContainer;
+    use reducers::Reducer;
+    use repository::Repository;
+
+    /// Counts records both in the state and in its private state
+    struct Counter {
+        count: u64,
+        reduced: u64,
+    }
+
+    impl Reducer for Counter {
+        type State = Map<String, JsonValue>;
+        type Item = ::repository::Record;
+
+        fn reduce(&mut self, mut state: Self::State, _item: &Self::Item) -> Self::State {
+            self.count += 1;
+            self.reduced += 1;
+            state.insert("count".into(), self.count.into());
+            state
+        }
+    }
+
+    impl StatefulReducer for Counter {
+        fn private_state(&self) -> Option<JsonValue> {
+            Some(self.count.into())
+        }
+
+        fn set_private_state(&mut self, state: JsonValue) -> bool {
+            match state.as_u64() {
+                Some(count) => {
+                    self.count = count;
+                    true
+                },
+                None => false,
+            }
+        }
+    }
+
+    fn counter() -> Counter {
+        Counter { count: 0, reduced: 0 }
+    }
+
+    #[test]
+    fn resume() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        let path = repo.checkpoints_path();
+        repo.new_record(vec![("a", &b""[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![("b", &b""[..])].into_iter(), true).unwrap();
+
+        let mut reducer = counter();
+        let reduction = reduce(&repo, &mut reducer, Default::default(), load(&path, "f"), "f").unwrap();
+        assert_eq!(reduction.state.get("count").unwrap(), &JsonValue::from(2));
+        assert!(reduction.resumed_from.is_none());
+        let checkpoint = reduction.checkpoint.unwrap();
+        assert_eq!(checkpoint.records.len(), 2);
+        save(&path, &checkpoint).unwrap();
+
+        let record = repo.new_record(vec![("c", &b""[..])].into_iter(), true).unwrap();
+        let mut reducer = counter();
+        let reduction = reduce(&repo, &mut reducer, Default::default(), load(&path, "f"), "f").unwrap();
+        assert_eq!(reduction.state.get("count").unwrap(), &JsonValue::from(3));
+        assert_eq!(reduction.resumed_from, Some(checkpoint.record));
+        assert_eq!(reducer.reduced, 1);
+        let new_checkpoint = reduction.checkpoint.unwrap();
+        assert_eq!(new_checkpoint.record, record.encoded_hash());
+        save(&path, &new_checkpoint).unwrap();
+        // the old checkpoint was superseded
+        assert_eq!(load(&path, "f"), vec![new_checkpoint.clone()]);
+
+        // nothing new
+        let mut reducer = counter();
+        let reduction = reduce(&repo, &mut reducer, Default::default(), load(&path, "f"), "f").unwrap();
+        assert_eq!(reduction.state.get("count").unwrap(), &JsonValue::from(3));
+        assert_eq!(reducer.reduced, 0);
+        assert!(reduction.checkpoint.is_none());
+
+        // different reducers
+        let mut reducer = counter();
+        let reduction = reduce(&repo, &mut reducer, Default::default(), load(&path, "g"), "g").unwrap();
+        assert_eq!(reducer.reduced, 3);
+        assert!(reduction.resumed_from.is_none());
+    }
+
+    #[test]
+    fn new_root() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        let path = repo.checkpoints_path();
+        repo.new_record(vec![("a", &b""[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![("b", &b""[..])].into_iter(), true).unwrap();
+        let reduction = reduce(&repo, &mut counter(), Default::default(), vec![], "f").unwrap();
+        save(&path, &reduction.checkpoint.unwrap()).unwrap();
+
+        // a record that doesn't descend from the checkpoint
+        repo.new_record(vec![("c", &b""[..])].into_iter(), false).unwrap();
+        let mut reducer = counter();
+        let reduction = reduce(&repo, &mut reducer, Default::default(), load(&path, "f"), "f").unwrap();
+        assert!(reduction.resumed_from.is_none());
+        assert_eq!(reducer.reduced, 3);
+    }
+
+    #[test]
+    fn fingerprints() {
+        let algorithm = HashingAlgorithm::default();
+        assert_eq!(fingerprint(&algorithm, &["ab", "c"]), fingerprint(&algorithm, &["ab", "c"]));
+        assert_ne!(fingerprint(&algorithm, &["ab", "c"]), fingerprint(&algorithm, &["a", "bc"]));
+    }
+}
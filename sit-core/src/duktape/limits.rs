@@ -0,0 +1,150 @@
+//! Execution time and heap limits
+//!
+//! Duktape heaps created with [`create_heap`] use a custom allocator that
+//! accounts for every allocation and an interrupt hook (`DUK_USE_EXEC_TIMEOUT_CHECK`,
+//! see `duk_config.h`) that checks for execution deadline. Both get [`Limits`]
+//! through heap's user data.
+//!
+//! Limits are only enforced while [`Limits::enter`] is in effect, so that allocations
+//! done by the host outside of protected calls never fail (which would be fatal).
+//!
+//! [`create_heap`]: fn.create_heap.html
+//! [`Limits`]: struct.Limits.html
+//! [`Limits::enter`]: struct.Limits.html#method.enter
+
+use std::alloc::{self, Layout};
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use super::{duk_context, duk_create_heap, duk_fatal_function, duk_size_t};
+
+/// Allocation header size (also used as allocation alignment)
+const HEADER: usize = 16;
+
+#[derive(Debug, Default)]
+pub struct Limits {
+    timeout: Option<Duration>,
+    heap_limit: Option<usize>,
+    deadline: Option<Instant>,
+    executing: bool,
+    allocated: usize,
+}
+
+impl Limits {
+    /// Returns execution time limit
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Sets execution time limit
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Returns heap size limit (in bytes)
+    pub fn heap_limit(&self) -> Option<usize> {
+        self.heap_limit
+    }
+
+    /// Sets heap size limit (in bytes)
+    pub fn set_heap_limit(&mut self, heap_limit: Option<usize>) {
+        self.heap_limit = heap_limit;
+    }
+
+    /// Returns the amount of memory currently allocated by the heap (in bytes)
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Starts enforcing limits (and sets execution deadline)
+    pub fn enter(&mut self) {
+        self.executing = true;
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Stops enforcing limits
+    pub fn leave(&mut self) {
+        self.executing = false;
+        self.deadline = None;
+    }
+
+    fn allows(&self, additional: usize) -> bool {
+        match self.heap_limit {
+            Some(limit) if self.executing => self.allocated + additional <= limit,
+            _ => true,
+        }
+    }
+}
+
+/// Creates a Duktape heap governed by given limits
+///
+/// `limits` must outlive the heap.
+pub unsafe fn create_heap(limits: *mut Limits, fatal_handler: duk_fatal_function) -> *mut duk_context {
+    duk_create_heap(Some(heap_alloc), Some(heap_realloc), Some(heap_free), limits as *mut c_void, fatal_handler)
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size + HEADER, HEADER).unwrap()
+}
+
+unsafe extern "C" fn heap_alloc(udata: *mut c_void, size: duk_size_t) -> *mut c_void {
+    let limits = &mut *(udata as *mut Limits);
+    if size == 0 || !limits.allows(size) {
+        return ptr::null_mut();
+    }
+    let base = alloc::alloc(layout(size));
+    if base.is_null() {
+        return ptr::null_mut();
+    }
+    *(base as *mut usize) = size;
+    limits.allocated += size;
+    base.add(HEADER) as *mut c_void
+}
+
+unsafe extern "C" fn heap_realloc(udata: *mut c_void, ptr: *mut c_void, size: duk_size_t) -> *mut c_void {
+    if ptr.is_null() {
+        return heap_alloc(udata, size);
+    }
+    if size == 0 {
+        heap_free(udata, ptr);
+        return ptr::null_mut();
+    }
+    let limits = &mut *(udata as *mut Limits);
+    let base = (ptr as *mut u8).sub(HEADER);
+    let old_size = *(base as *mut usize);
+    if size > old_size && !limits.allows(size - old_size) {
+        return ptr::null_mut();
+    }
+    let base = alloc::realloc(base, layout(old_size), size + HEADER);
+    if base.is_null() {
+        return ptr::null_mut();
+    }
+    *(base as *mut usize) = size;
+    limits.allocated = limits.allocated - old_size + size;
+    base.add(HEADER) as *mut c_void
+}
+
+unsafe extern "C" fn heap_free(udata: *mut c_void, ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let limits = &mut *(udata as *mut Limits);
+    let base = (ptr as *mut u8).sub(HEADER);
+    let size = *(base as *mut usize);
+    limits.allocated -= size;
+    alloc::dealloc(base, layout(size));
+}
+
+/// Execution timeout check, called by Duktape's interrupt hook
+#[no_mangle]
+pub unsafe extern "C" fn sit_duk_exec_timeout_check(udata: *mut c_void) -> c_int {
+    if udata.is_null() {
+        return 0;
+    }
+    let limits = &*(udata as *mut Limits);
+    match limits.deadline {
+        Some(deadline) if limits.executing && Instant::now() >= deadline => 1,
+        _ => 0,
+    }
+}
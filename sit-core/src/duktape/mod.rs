@@ -7,3 +7,6 @@ pub use self::bindings::*;
 pub mod module;
 #[cfg(feature = "duktape-require")]
 pub use self::module::*;
+
+pub mod limits;
+pub mod stdlib;
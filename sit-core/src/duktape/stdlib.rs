@@ -0,0 +1,158 @@
+//! Reducer standard library
+//!
+//! Defines the `sit` global (also available as `require('sit')` with the
+//! `duktape-require` feature) with helpers that reducers would otherwise have
+//! to implement themselves:
+//!
+//! * `sit.decodeText(buffer)` decodes UTF-8 (invalid sequences are replaced)
+//! * `sit.parseJSON(buffer)` decodes UTF-8 and parses JSON
+//! * `sit.parseTimestamp(buffer)` parses an ISO-8601 timestamp (such as the
+//!   one in `.timestamp`) into milliseconds since the epoch, suitable for `new Date(...)`,
+//!   or returns `null` if it can't be parsed
+//! * `sit.types(record)` returns record's types (from `.type/*`)
+//! * `sit.authors(record)` returns record's authors (from `.authors`)
+//!
+//! Strings are accepted wherever buffers are.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+
+use super::*;
+use timestamp;
+
+/// Name of the global
+pub const GLOBAL: &str = "sit";
+
+/// Defines the `sit` global
+pub unsafe fn init(context: *mut duk_context) {
+    let functions: [(&str, unsafe extern "C" fn(*mut duk_context) -> duk_ret_t); 5] = [
+        ("decodeText", decode_text),
+        ("parseJSON", parse_json),
+        ("parseTimestamp", parse_timestamp_fn),
+        ("types", types),
+        ("authors", authors),
+    ];
+    duk_push_object(context);
+    for &(name, function) in functions.iter() {
+        let name = CString::new(name).unwrap();
+        duk_push_c_function(context, Some(function), 1);
+        duk_put_prop_string(context, -2, name.as_ptr());
+    }
+    let global = CString::new(GLOBAL).unwrap();
+    duk_put_global_string(context, global.as_ptr());
+}
+
+/// Returns text of a buffer or a string at `idx`
+unsafe fn text(context: *mut duk_context, idx: duk_idx_t) -> Option<String> {
+    let mut size: duk_size_t = 0;
+    if duk_is_buffer_data(context, idx) == 1 {
+        let data = duk_get_buffer_data(context, idx, &mut size) as *const u8;
+        if data.is_null() {
+            return Some(String::new());
+        }
+        Some(String::from_utf8_lossy(::std::slice::from_raw_parts(data, size)).into_owned())
+    } else if duk_is_string(context, idx) == 1 {
+        let data = duk_get_lstring(context, idx, &mut size) as *const u8;
+        let bytes = ::std::slice::from_raw_parts(data, size);
+        #[cfg(feature = "cesu8")]
+        let text = match ::cesu8::from_cesu8(bytes) {
+            Ok(s) => s.into_owned(),
+            Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+        };
+        #[cfg(not(feature = "cesu8"))]
+        let text = String::from_utf8_lossy(bytes).into_owned();
+        Some(text)
+    } else {
+        None
+    }
+}
+
+/// Pushes a string (Duktape expects CESU-8)
+unsafe fn push_text(context: *mut duk_context, text: &str) {
+    #[cfg(feature = "cesu8")]
+    let bytes = ::cesu8::to_cesu8(text);
+    #[cfg(not(feature = "cesu8"))]
+    let bytes = text.as_bytes();
+    duk_push_lstring(context, bytes.as_ptr() as *const c_char, bytes.len());
+}
+
+unsafe extern "C" fn decode_text(context: *mut duk_context) -> duk_ret_t {
+    match text(context, 0) {
+        Some(text) => push_text(context, &text),
+        // buffer or string expected
+        None => return DUK_RET_TYPE_ERROR,
+    }
+    1
+}
+
+unsafe extern "C" fn parse_json(context: *mut duk_context) -> duk_ret_t {
+    match text(context, 0) {
+        Some(text) => push_text(context, &text),
+        // buffer or string expected
+        None => return DUK_RET_TYPE_ERROR,
+    }
+    duk_json_decode(context, -1);
+    1
+}
+
+unsafe extern "C" fn parse_timestamp_fn(context: *mut duk_context) -> duk_ret_t {
+    match text(context, 0).and_then(|text| timestamp::parse(&text)) {
+        Some(timestamp) => duk_push_number(context, timestamp as f64),
+        None => duk_push_null(context),
+    }
+    1
+}
+
+/// Pushes record's `files` (or `undefined`)
+unsafe fn push_files(context: *mut duk_context) {
+    let files = CString::new("files").unwrap();
+    if duk_is_object(context, 0) == 1 {
+        duk_get_prop_string(context, 0, files.as_ptr());
+    } else {
+        duk_push_undefined(context);
+    }
+}
+
+unsafe fn push_array(context: *mut duk_context, items: Vec<String>) {
+    duk_push_array(context);
+    for (i, item) in items.iter().enumerate() {
+        push_text(context, item);
+        duk_put_prop_index(context, -2, i as u32);
+    }
+}
+
+unsafe extern "C" fn types(context: *mut duk_context) -> duk_ret_t {
+    let mut types = vec![];
+    push_files(context);
+    if duk_is_object(context, -1) == 1 {
+        duk_enum(context, -1, DUK_ENUM_OWN_PROPERTIES_ONLY);
+        while duk_next(context, -1, 0) == 1 {
+            if let Some(name) = text(context, -1) {
+                if name.starts_with(".type/") {
+                    types.push(name[".type/".len()..].to_owned());
+                }
+            }
+            duk_pop(context);
+        }
+        duk_pop(context);
+    }
+    duk_pop(context);
+    push_array(context, types);
+    1
+}
+
+unsafe extern "C" fn authors(context: *mut duk_context) -> duk_ret_t {
+    let mut authors = vec![];
+    push_files(context);
+    let str_authors = CString::new(".authors").unwrap();
+    if duk_is_object(context, -1) == 1 {
+        duk_get_prop_string(context, -1, str_authors.as_ptr());
+        if let Some(text) = text(context, -1) {
+            authors = text.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect();
+        }
+        duk_pop(context);
+    }
+    duk_pop(context);
+    push_array(context, authors);
+    1
+}
@@ -0,0 +1,166 @@
+//! Confidential (encrypted) records
+//!
+//! Content files of a confidential record are encrypted with GnuPG to a set of
+//! recipient public keys stored in the repository (see [`Repository::recipients`]).
+//! Files starting with a dot (`.type/*`, `.prev/*`, `.authors`, etc.) are kept as is,
+//! so that records can still be ordered and dispatched on without any keys.
+//!
+//! Since it is the ciphertext that is stored, record hash is computed over it
+//! and integrity checks pass regardless of whether the keys are available.
+//!
+//! [`Repository::recipients`]: ../repository/struct.Repository.html#method.recipients
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use tempdir::TempDir;
+
+use record::{File, OrderedFiles};
+
+/// Name of the file that marks the record as encrypted
+pub const ENCRYPTED_FILE: &str = ".encrypted";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// I/O error
+    IoError(io::Error),
+    /// No recipients to encrypt to
+    #[error(no_from, non_std)]
+    NoRecipients,
+    /// GnuPG failed
+    #[error(no_from, non_std)]
+    GnuPGError(String),
+}
+
+/// Returns true if the file should be encrypted in a confidential record
+pub fn is_confidential<S: AsRef<str>>(name: S) -> bool {
+    !name.as_ref().starts_with(".")
+}
+
+/// GnuPG-backed encryption
+#[derive(Debug, Clone)]
+pub struct GnuPG(OsString);
+
+impl GnuPG {
+    /// Creates an instance that uses a given GnuPG `program`
+    pub fn new<S: Into<OsString>>(program: S) -> Self {
+        GnuPG(program.into())
+    }
+
+    fn run(&self, command: &mut Command, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // write on a separate thread to avoid blocking on a full stdout pipe
+        let mut stdin = child.stdin.take().unwrap();
+        let data = data.to_vec();
+        let writer = thread::spawn(move || stdin.write_all(&data));
+        let output = child.wait_with_output()?;
+        let _ = writer.join();
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(Error::GnuPGError(String::from_utf8_lossy(&output.stderr).into()))
+        }
+    }
+
+    /// Encrypts `data` to `recipients` (paths to public key files)
+    pub fn encrypt<P: AsRef<Path>>(&self, recipients: &[P], data: &[u8]) -> Result<Vec<u8>, Error> {
+        if recipients.is_empty() {
+            return Err(Error::NoRecipients);
+        }
+        let mut command = Command::new(&self.0);
+        command.args(&["--batch", "--yes", "--trust-model", "always", "--encrypt"]);
+        for recipient in recipients {
+            command.arg("--recipient-file").arg(recipient.as_ref());
+        }
+        command.args(&["-o", "-"]);
+        self.run(&mut command, data)
+    }
+
+    /// Decrypts `data`
+    ///
+    /// Returns `None` if the data can't be decrypted (for example, if none of the
+    /// private keys available match)
+    pub fn decrypt(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut command = Command::new(&self.0);
+        command.args(&["--batch", "--quiet", "--decrypt", "-o", "-"]);
+        self.run(&mut command, data).ok()
+    }
+
+    /// Verifies detached `signature` of `data`
+    ///
+    /// Returns fingerprints of the signing key and its primary key
+    /// if the signature is good, `None` otherwise.
+    pub fn verify(&self, signature: &[u8], data: &[u8]) -> Option<Vec<String>> {
+        let tempdir = TempDir::new("sit").ok()?;
+        let path = tempdir.path().join("signature");
+        fs::File::create(&path).and_then(|mut f| f.write_all(signature)).ok()?;
+        let mut command = Command::new(&self.0);
+        command.args(&["--batch", "--status-fd", "1", "--verify"]).arg(&path).arg("-");
+        let output = self.run(&mut command, data).ok()?;
+        String::from_utf8_lossy(&output).lines()
+            .find(|line| line.starts_with("[GNUPG:] VALIDSIG "))
+            .map(|line| {
+                let fields: Vec<_> = line.split_whitespace().collect();
+                let mut fingerprints = vec![String::from(fields[2])];
+                if let Some(primary) = fields.last() {
+                    if primary != &fields[2] {
+                        fingerprints.push(String::from(*primary));
+                    }
+                }
+                fingerprints
+            })
+    }
+
+    /// Encrypts confidential files (see [`is_confidential`]) to `recipients`
+    ///
+    /// Returns all files (including the [`ENCRYPTED_FILE`] marker) ready to be recorded.
+    ///
+    /// [`is_confidential`]: fn.is_confidential.html
+    /// [`ENCRYPTED_FILE`]: constant.ENCRYPTED_FILE.html
+    pub fn encrypt_files<'a, F: File + 'a, P: AsRef<Path>>(&self, recipients: &[P], files: OrderedFiles<'a, F>)
+        -> Result<Vec<(String, Vec<u8>)>, Error> {
+        if recipients.is_empty() {
+            return Err(Error::NoRecipients);
+        }
+        let mut result = vec![(String::from(ENCRYPTED_FILE), vec![])];
+        for mut file in files.0.into_iter() {
+            let name = String::from(file.name());
+            let mut content = vec![];
+            file.read().read_to_end(&mut content)?;
+            if is_confidential(&name) {
+                content = self.encrypt(recipients, &content)?;
+            }
+            result.push((name, content));
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confidential_files() {
+        assert!(is_confidential("text"));
+        assert!(is_confidential("attachments/file.pdf"));
+        assert!(!is_confidential(".type/Commented"));
+        assert!(!is_confidential(".prev/HASH"));
+        assert!(!is_confidential(ENCRYPTED_FILE));
+    }
+
+    #[test]
+    fn no_recipients() {
+        let files: OrderedFiles<_> = vec![("text", &b"secret"[..])].into();
+        let recipients: Vec<::std::path::PathBuf> = vec![];
+        assert_matches!(GnuPG::new("gpg").encrypt_files(&recipients, files), Err(Error::NoRecipients));
+    }
+}
@@ -0,0 +1,340 @@
+//! Repository consistency checks
+//!
+//! When records are listed, anything that doesn't look like an intact record is
+//! silently skipped. [`check`] finds such problems instead:
+//!
+//! * records whose files don't match their hash (taking redactions into account)
+//! * link files that don't resolve to a directory (see [`ResolvePath`])
+//! * records linking to parents (`.prev/*`) that don't exist
+//! * temporary directories left behind by interrupted record creation (once
+//!   they weren't modified for a while, so that records being created at the
+//!   moment are not affected)
+//! * record names that don't decode under the configured encoding
+//! * records redacted by redaction records that can't be verified (when the
+//!   repository has no [`Verifier`]); they are reported, but not quarantined
+//!
+//! [`quarantine`] moves offending files into a new directory under
+//! [`Repository::quarantine_path`], along with a report ([`REPORT_FILE`]).
+//!
+//! [`check`]: fn.check.html
+//! [`quarantine`]: fn.quarantine.html
+//! [`ResolvePath`]: ../path/trait.ResolvePath.html
+//! [`Repository::quarantine_path`]: ../repository/struct.Repository.html#method.quarantine_path
+//! [`REPORT_FILE`]: constant.REPORT_FILE.html
+//! [`Verifier`]: ../policy/struct.Verifier.html
+
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+use tempdir::TempDir;
+use walkdir::WalkDir;
+
+use path::{HasPath, ResolvePath};
+use record::Record;
+use repository::{self, Error, Repository};
+
+/// Quarantine report file name
+pub const REPORT_FILE: &str = "report.json";
+
+/// Problem found in a repository
+///
+/// Paths are relative to the repository
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "problem", rename_all = "kebab-case")]
+pub enum Problem {
+    /// Record's files don't match its hash
+    CorruptRecord { path: PathBuf },
+    /// Link file doesn't resolve to a directory
+    BrokenLink { path: PathBuf },
+    /// Record links to a parent record that doesn't exist
+    DanglingParent { path: PathBuf, parent: String },
+    /// Temporary directory left behind by an interrupted record creation
+    StrayTempDir { path: PathBuf },
+    /// Record name doesn't decode under the configured encoding
+    UndecodableName { path: PathBuf },
+    /// Record's files match a redaction whose signature can't be verified
+    UnverifiedRedaction { path: PathBuf },
+}
+
+impl Problem {
+    /// Returns the path of the offending file or directory
+    pub fn path(&self) -> &Path {
+        match self {
+            &Problem::CorruptRecord { ref path } |
+            &Problem::BrokenLink { ref path } |
+            &Problem::DanglingParent { ref path, .. } |
+            &Problem::StrayTempDir { ref path } |
+            &Problem::UndecodableName { ref path } |
+            &Problem::UnverifiedRedaction { ref path } => path,
+        }
+    }
+
+    /// Returns `true` if the problem is repaired by moving its path into quarantine
+    ///
+    /// Records with dangling parents are intact otherwise and redactions that can't be
+    /// verified may well be legitimate, so they are only reported.
+    pub fn quarantinable(&self) -> bool {
+        match self {
+            &Problem::DanglingParent { .. } |
+            &Problem::UnverifiedRedaction { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &Problem::CorruptRecord { ref path } => write!(f, "corrupt record {}", path.display()),
+            &Problem::BrokenLink { ref path } => write!(f, "broken link {}", path.display()),
+            &Problem::DanglingParent { ref path, ref parent } => write!(f, "dangling parent {} of {}", parent, path.display()),
+            &Problem::StrayTempDir { ref path } => write!(f, "stray temporary directory {}", path.display()),
+            &Problem::UndecodableName { ref path } => write!(f, "undecodable name {}", path.display()),
+            &Problem::UnverifiedRedaction { ref path } => write!(f, "unverified redaction of {}", path.display()),
+        }
+    }
+}
+
+/// Returns true if `path` is not a directory and doesn't resolve to one
+fn is_broken(path: &Path) -> bool {
+    path.resolve_dir().map(|path| !path.is_dir()).unwrap_or(true)
+}
+
+/// Returns temporary directories records are prepared in (see [`Repository::new_record_in`])
+/// that weren't modified within `older_than`
+///
+/// [`Repository::new_record_in`]: ../repository/struct.Repository.html#method.new_record_in
+pub(crate) fn temp_dirs<MI>(repo: &Repository<MI>, older_than: Duration) -> Result<Vec<PathBuf>, Error> {
+    let temp_prefix = format!("{}.", repository::TEMP_PREFIX);
+    let now = SystemTime::now();
+    let mut dirs = vec![];
+    for entry in fs::read_dir(repo.path())? {
+        let entry = entry?;
+        let is_temp = entry.file_name().to_str().map(|name| name.starts_with(&temp_prefix)).unwrap_or(false);
+        if !is_temp || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let stale = entry.metadata().and_then(|metadata| metadata.modified()).ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .map(|age| age >= older_than)
+            // if the age can't be determined, the directory is left alone
+            .unwrap_or(false);
+        if stale {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+/// Returns item link files (and links within items) that don't resolve to a directory
+#[cfg(feature = "deprecated-item-api")]
+pub(crate) fn broken_item_links<MI>(repo: &Repository<MI>) -> Result<Vec<PathBuf>, Error> {
+    let mut links = vec![];
+    if !repo.items_path().is_dir() {
+        return Ok(links);
+    }
+    for item in fs::read_dir(repo.items_path())? {
+        let item = item?.path();
+        if is_broken(&item) {
+            links.push(item);
+            continue;
+        }
+        for link in fs::read_dir(item.resolve_dir()?)? {
+            let link = link?.path();
+            if is_broken(&link) {
+                links.push(link);
+            }
+        }
+    }
+    Ok(links)
+}
+
+/// Checks the repository for problems, considering temporary directories that
+/// weren't modified within `older_than` stray
+pub fn check<MI>(repo: &Repository<MI>, older_than: Duration) -> Result<Vec<Problem>, Error> {
+    let relative = |path: &Path| path.strip_prefix(repo.path()).map(Path::to_path_buf).unwrap_or_else(|_| path.to_path_buf());
+    let mut problems = vec![];
+
+    for path in temp_dirs(repo, older_than)? {
+        problems.push(Problem::StrayTempDir { path: relative(&path) });
+    }
+
+    // without a verifier, no redactions are honoured, so redacted records
+    // are told from corrupt ones by redaction records, signed or not
+    let unverified_redactions = match repo.verifier() {
+        None => repo.unverified_redactions(),
+        Some(_) => Default::default(),
+    };
+
+    let config = repo.config();
+    let records_path = repo.records_path().resolve_dir().unwrap_or(repo.records_path().into());
+    let depth = repository::records_depth(config.hashing_algorithm(), config.encoding());
+    for entry in WalkDir::new(&records_path).min_depth(depth).max_depth(depth).into_iter().filter_map(Result::ok) {
+        let path = entry.path();
+        let name = match entry.file_name().to_str() {
+            Some(name) if config.encoding().decode(name.as_bytes()).is_ok() => name,
+            _ => {
+                problems.push(Problem::UndecodableName { path: relative(path) });
+                continue;
+            },
+        };
+        if is_broken(path) {
+            problems.push(Problem::BrokenLink { path: relative(path) });
+            continue;
+        }
+        let record = match repo.record(name) {
+            Some(record) => record,
+            // not where a record with this name belongs
+            None => continue,
+        };
+        if !record.integrity_intact(config.hashing_algorithm()) {
+            let redactions = unverified_redactions.get(&record.hash()).cloned().unwrap_or_default();
+            if !redactions.is_empty() && record.with_redactions(redactions).integrity_intact(config.hashing_algorithm()) {
+                problems.push(Problem::UnverifiedRedaction { path: relative(path) });
+            } else {
+                problems.push(Problem::CorruptRecord { path: relative(path) });
+            }
+            continue;
+        }
+        let prev = record.path().join(".prev");
+        if prev.is_dir() {
+            for parent in fs::read_dir(prev)? {
+                let parent = parent?.file_name();
+                let parent = parent.to_string_lossy();
+                if repo.record(&*parent).is_none() {
+                    problems.push(Problem::DanglingParent { path: relative(path), parent: parent.into_owned() });
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "deprecated-item-api")] {
+        for path in broken_item_links(repo)? {
+            problems.push(Problem::BrokenLink { path: relative(&path) });
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Moves offending files and directories of quarantinable problems into a new directory
+/// under [`Repository::quarantine_path`], preserving their paths relative to the repository
+///
+/// All problems are listed in the directory's report ([`REPORT_FILE`]), along with
+/// whether they were quarantined. Returns the path to the directory.
+///
+/// [`Repository::quarantine_path`]: ../repository/struct.Repository.html#method.quarantine_path
+/// [`REPORT_FILE`]: constant.REPORT_FILE.html
+pub fn quarantine<MI>(repo: &Repository<MI>, problems: &[Problem]) -> Result<PathBuf, Error> {
+    let quarantine_path = repo.quarantine_path();
+    fs::create_dir_all(&quarantine_path)?;
+    // quarantined files are local to this copy of the repository
+    let gitignore = quarantine_path.join(".gitignore");
+    if !gitignore.exists() {
+        fs::File::create(gitignore)?.write_all(b"*\n")?;
+    }
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let dir = TempDir::new_in(&quarantine_path, &timestamp.to_string())?.into_path();
+
+    let mut report = vec![];
+    for problem in problems {
+        let source = repo.path().join(problem.path());
+        let quarantined = problem.quarantinable() && source.exists();
+        if quarantined {
+            let target = dir.join(problem.path().strip_prefix("/").unwrap_or(problem.path()));
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(source, target)?;
+        }
+        let mut entry = serde_json::to_value(problem)?;
+        entry["quarantined"] = quarantined.into();
+        report.push(entry);
+    }
+    serde_json::to_writer_pretty(fs::File::create(dir.join(REPORT_FILE))?, &report)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use record::{split_path, RecordOwningContainer};
+
+    #[test]
+    fn healthy() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        repo.new_record(vec![("text", &b"Hello"[..])].into_iter(), false).unwrap();
+        repo.new_record(vec![("text", &b"Reply"[..])].into_iter(), true).unwrap();
+        assert!(check(&repo, Duration::from_secs(0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn problems() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        let corrupt = repo.new_record(vec![("text", &b"Hello"[..])].into_iter(), false).unwrap();
+        fs::File::create(corrupt.path().join("text")).unwrap().write_all(b"Tampered").unwrap();
+        let hash_len = corrupt.hash().len();
+        let missing = repo.config().encoding().encode(&vec![0; hash_len]);
+        let prev = format!(".prev/{}", missing);
+        let orphan = repo.new_record(vec![("text", &b"Orphan"[..]), (prev.as_str(), &b""[..])].into_iter(), false).unwrap();
+        let broken = repo.config().encoding().encode(&vec![1; hash_len]);
+        let broken_path = repo.records_path().join(split_path(&broken, 2));
+        fs::create_dir_all(broken_path.parent().unwrap()).unwrap();
+        fs::File::create(&broken_path).unwrap().write_all(b"nowhere").unwrap();
+        let undecodable = repo.records_path().join(split_path(corrupt.encoded_hash().replace(|_: char| true, "!"), 2));
+        fs::create_dir_all(&undecodable).unwrap();
+        let temp = TempDir::new_in(repo.path(), repository::TEMP_PREFIX).unwrap().into_path();
+
+        let relative = |path: &Path| path.strip_prefix(repo.path()).unwrap().to_path_buf();
+        // recently modified temporary directories are left alone
+        assert!(!check(&repo, Duration::from_secs(3600)).unwrap().contains(&Problem::StrayTempDir { path: relative(&temp) }));
+        let problems = check(&repo, Duration::from_secs(0)).unwrap();
+        assert_eq!(problems.len(), 5);
+        assert!(problems.contains(&Problem::CorruptRecord { path: relative(corrupt.path()) }));
+        assert!(problems.contains(&Problem::DanglingParent { path: relative(orphan.path()), parent: missing.clone() }));
+        assert!(problems.contains(&Problem::BrokenLink { path: relative(&broken_path) }));
+        assert!(problems.contains(&Problem::UndecodableName { path: relative(&undecodable) }));
+        assert!(problems.contains(&Problem::StrayTempDir { path: relative(&temp) }));
+
+        let dir = quarantine(&repo, &problems).unwrap();
+        assert!(dir.starts_with(repo.quarantine_path()));
+        assert!(!corrupt.path().exists());
+        assert!(dir.join(relative(corrupt.path())).join("text").is_file());
+        assert!(!temp.exists());
+        assert!(orphan.path().exists());
+        let report: Vec<serde_json::Value> = serde_json::from_reader(fs::File::open(dir.join(REPORT_FILE)).unwrap()).unwrap();
+        assert_eq!(report.len(), 5);
+        assert_eq!(report.iter().filter(|entry| entry["quarantined"] == true).count(), 4);
+
+        // only the dangling parent is left
+        let problems = check(&repo, Duration::from_secs(0)).unwrap();
+        assert_eq!(problems, vec![Problem::DanglingParent { path: relative(orphan.path()), parent: missing }]);
+    }
+
+    #[test]
+    fn unverified_redaction() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        let record = repo.new_record(vec![("text", &b"Secret"[..])].into_iter(), false).unwrap();
+        let redaction = repo.redaction(&record, &["text"]).unwrap();
+        repo.new_record(redaction.files(), true).unwrap();
+        repo.redact(&redaction).unwrap();
+
+        let relative = |path: &Path| path.strip_prefix(repo.path()).unwrap().to_path_buf();
+        let problems = check(&repo, Duration::from_secs(0)).unwrap();
+        assert_eq!(problems, vec![Problem::UnverifiedRedaction { path: relative(record.path()) }]);
+        assert!(!problems[0].quarantinable());
+        quarantine(&repo, &problems).unwrap();
+        assert!(record.path().join("text").is_file());
+    }
+}
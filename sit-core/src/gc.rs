@@ -0,0 +1,184 @@
+//! Garbage collection
+//!
+//! Interrupted record creation leaves temporary directories behind in the repository
+//! and (deprecated) items' link files can outlive the records they point to.
+//! [`collect`] finds such garbage and [`remove`] removes it.
+//!
+//! Temporary directories are only considered garbage once they are old enough,
+//! so records that are being created at the moment are not affected.
+//!
+//! [`collect`]: fn.collect.html
+//! [`remove`]: fn.remove.html
+
+#[cfg(feature = "deprecated-item-api")]
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use fsck;
+use path::HasPath;
+#[cfg(feature = "deprecated-item-api")]
+use path::ResolvePath;
+use repository::{Error, Repository};
+#[cfg(feature = "deprecated-item-api")]
+use record::{Record, RecordContainer};
+
+/// Garbage found in a repository
+///
+/// Paths are relative to the repository
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "garbage", rename_all = "kebab-case")]
+pub enum Garbage {
+    /// Temporary directory left behind by an interrupted record creation
+    StaleTempDir { path: PathBuf },
+    /// Item link file that doesn't resolve to a directory
+    BrokenItemLink { path: PathBuf },
+}
+
+impl Garbage {
+    /// Returns the path of the garbage file or directory
+    pub fn path(&self) -> &Path {
+        match self {
+            &Garbage::StaleTempDir { ref path } |
+            &Garbage::BrokenItemLink { ref path } => path,
+        }
+    }
+}
+
+impl fmt::Display for Garbage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &Garbage::StaleTempDir { ref path } => write!(f, "stale temporary directory {}", path.display()),
+            &Garbage::BrokenItemLink { ref path } => write!(f, "broken item link {}", path.display()),
+        }
+    }
+}
+
+/// Finds garbage, considering temporary directories that weren't modified
+/// within `older_than` stale
+pub fn collect<MI>(repo: &Repository<MI>, older_than: Duration) -> Result<Vec<Garbage>, Error> {
+    let relative = |path: &Path| path.strip_prefix(repo.path()).map(Path::to_path_buf).unwrap_or_else(|_| path.to_path_buf());
+    let mut garbage = vec![];
+    for path in fsck::temp_dirs(repo, older_than)? {
+        garbage.push(Garbage::StaleTempDir { path: relative(&path) });
+    }
+    #[cfg(feature = "deprecated-item-api")] {
+        for path in fsck::broken_item_links(repo)? {
+            garbage.push(Garbage::BrokenItemLink { path: relative(&path) });
+        }
+    }
+    Ok(garbage)
+}
+
+/// Removes garbage
+pub fn remove<MI>(repo: &Repository<MI>, garbage: &[Garbage]) -> Result<(), Error> {
+    for garbage in garbage {
+        let path = repo.path().join(garbage.path());
+        if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns (encoded hashes of) records that are not reachable from any item,
+/// either directly or as ancestors (`.prev/*`) of records that are
+#[cfg(feature = "deprecated-item-api")]
+pub fn unreachable_records<MI>(repo: &Repository<MI>) -> Result<Vec<String>, Error> {
+    let mut queue = vec![];
+    if repo.items_path().is_dir() {
+        for item in fs::read_dir(repo.items_path())? {
+            let item = item?.path();
+            if let Ok(links) = item.resolve_dir().and_then(fs::read_dir) {
+                for link in links {
+                    queue.push(link?.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    let mut reachable = HashSet::new();
+    while let Some(name) = queue.pop() {
+        if !reachable.insert(name.clone()) {
+            continue;
+        }
+        if let Some(record) = repo.record(&name) {
+            if let Ok(parents) = fs::read_dir(record.path().join(".prev")) {
+                for parent in parents {
+                    queue.push(parent?.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    Ok(repo.record_iter()?.flat_map(|records| records)
+        .map(|record| record.encoded_hash())
+        .filter(|hash| !reachable.contains(hash))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use repository;
+    use record::RecordOwningContainer;
+    #[cfg(feature = "deprecated-item-api")]
+    use item::Item;
+    #[cfg(feature = "deprecated-item-api")]
+    use std::io::Write;
+
+    #[test]
+    fn temp_dirs() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        let temp = TempDir::new_in(repo.path(), repository::TEMP_PREFIX).unwrap().into_path();
+        // fresh temporary directories are left alone
+        assert!(collect(&repo, Duration::from_secs(3600)).unwrap().is_empty());
+        let garbage = collect(&repo, Duration::from_secs(0)).unwrap();
+        assert_eq!(garbage, vec![Garbage::StaleTempDir { path: temp.strip_prefix(repo.path()).unwrap().to_path_buf() }]);
+        remove(&repo, &garbage).unwrap();
+        assert!(!temp.exists());
+        assert!(collect(&repo, Duration::from_secs(0)).unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "deprecated-item-api")]
+    fn broken_item_links() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        let item = repo.new_item().unwrap();
+        let record = item.new_record(vec![("text", &b"Hello"[..])].into_iter(), false).unwrap();
+        assert!(collect(&repo, Duration::from_secs(0)).unwrap().is_empty());
+        fs::remove_dir_all(record.path()).unwrap();
+        let link = repo.items_path().join(item.id()).join(record.encoded_hash());
+        let garbage = collect(&repo, Duration::from_secs(0)).unwrap();
+        assert_eq!(garbage, vec![Garbage::BrokenItemLink { path: link.strip_prefix(repo.path()).unwrap().to_path_buf() }]);
+        remove(&repo, &garbage).unwrap();
+        assert!(!link.exists());
+        assert!(repo.items_path().join(item.id()).is_dir());
+    }
+
+    #[test]
+    #[cfg(feature = "deprecated-item-api")]
+    fn unreachable() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        repo.new_record(vec![("text", &b"Root"[..])].into_iter(), false).unwrap();
+        let item = repo.new_item().unwrap();
+        // links to the root record as its parent
+        item.new_record(vec![("text", &b"Linked"[..])].into_iter(), true).unwrap();
+        let orphan = repo.new_record(vec![("text", &b"Orphan"[..])].into_iter(), false).unwrap();
+        assert_eq!(unreachable_records(&repo).unwrap(), vec![orphan.encoded_hash()]);
+        // items can be link files
+        let moved = repo.path().parent().unwrap().join("moved");
+        fs::rename(repo.items_path().join(item.id()), &moved).unwrap();
+        fs::File::create(repo.items_path().join(item.id())).unwrap().write_all(moved.to_str().unwrap().as_bytes()).unwrap();
+        assert_eq!(unreachable_records(&repo).unwrap(), vec![orphan.encoded_hash()]);
+    }
+}
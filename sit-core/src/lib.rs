@@ -34,6 +34,12 @@ extern crate digest;
 
 #[cfg(feature = "git")] extern crate git2;
 
+#[cfg(feature = "wasmi")] extern crate wasmi;
+#[cfg(feature = "quick-js")] extern crate quick_js;
+
+#[cfg(feature = "query")] extern crate jmespath;
+#[cfg(feature = "query")] extern crate regex;
+
 #[cfg(test)] extern crate dunce;
 
 extern crate relative_path;
@@ -49,6 +55,7 @@ extern crate walkdir;
 pub mod path;
 pub mod hash;
 pub mod encoding;
+pub mod encryption;
 #[cfg(feature = "deprecated-item-api")]
 pub mod id;
 pub mod repository;
@@ -60,6 +67,16 @@ pub mod record;
 pub use record::Record;
 pub use repository::{Repository, Error as RepositoryError};
 pub mod reducers;
+pub mod policy;
+pub mod checkpoint;
+pub mod timestamp;
+pub mod search;
+pub mod fsck;
+pub mod gc;
+#[cfg(feature = "query")]
+pub mod query;
+#[cfg(feature = "query")]
+pub mod stats;
 pub use reducers::Reducer;
 #[cfg(feature = "duktape")]
 pub mod duktape;
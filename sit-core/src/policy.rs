@@ -0,0 +1,342 @@
+//! Authorization policy
+//!
+//! Policy restricts who can author records of certain types. It is
+//! stored in repository's `policy.json`:
+//!
+//! ```json
+//! {
+//!   "types": {
+//!     "Closed": {
+//!       "authors": ["John Doe <john@example.com>"],
+//!       "keys": ["4F3E2C2A0B1D9E8F"]
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! A record that has any of the restricted types is authorized if, for
+//! every such type, either one of its `.authors` is listed in `authors`
+//! or it is signed (`.signature`) by one of the `keys` (full 40 hex digit
+//! fingerprint or a 16 hex digit long key ID; anything else is rejected
+//! when the policy is loaded). Types that are not listed are not restricted.
+//!
+//! Keep in mind that `.authors` is merely a claim, so for anything
+//! important `keys` should be used instead.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::io::Read;
+
+use encoding::Encoding;
+use encryption::GnuPG;
+use hash::HashingAlgorithm;
+use record::{OrderedFiles, Record, RecordExt};
+use reducers::{Reducer, StatefulReducer};
+use serde_json::Value as JsonValue;
+
+/// Name of the policy file in the repository
+pub const POLICY_FILE: &str = "policy.json";
+
+/// Restrictions imposed on a record type
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TypePolicy {
+    /// Authors allowed to record this type
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    /// Signing keys allowed to record this type
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+}
+
+/// Authorization policy
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Policy {
+    /// Restrictions by record type
+    #[serde(default)]
+    pub types: HashMap<String, TypePolicy>,
+}
+
+/// Policy violation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Record type that the record was not authorized to have
+    pub record_type: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "not authorized to record {}", self.record_type)
+    }
+}
+
+/// Invalid signing key in the policy
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidKey {
+    /// Record type the key is listed for
+    pub record_type: String,
+    /// Key as listed in the policy
+    pub key: String,
+}
+
+impl Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "invalid key {:?} for {} (expected a 16 hex digit key ID or a 40 hex digit fingerprint)",
+               self.key, self.record_type)
+    }
+}
+
+/// Everything that is necessary to verify record signatures
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    gnupg: GnuPG,
+    hashing_algorithm: HashingAlgorithm,
+    encoding: Encoding,
+}
+
+impl Verifier {
+    /// Creates a new verifier
+    pub fn new(gnupg: GnuPG, hashing_algorithm: HashingAlgorithm, encoding: Encoding) -> Self {
+        Verifier { gnupg, hashing_algorithm, encoding }
+    }
+
+    /// Returns fingerprints of the key that signed the record, if the signature is good
+    pub fn signed_by<R: Record>(&self, record: &R) -> Option<Vec<String>> {
+        let mut signature = vec![];
+        record.file(".signature")?.read_to_end(&mut signature).ok()?;
+        let files: OrderedFiles<_> = record.file_iter().into();
+        let files = files - ".signature";
+        let mut hasher = self.hashing_algorithm.hasher();
+        files.hash(&mut *hasher).ok()?;
+        let encoded_hash = self.encoding.encode(&hasher.result_box());
+        self.gnupg.verify(&signature, encoded_hash.as_bytes())
+    }
+}
+
+impl Policy {
+    /// Returns true if the policy doesn't restrict anything
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Checks that all listed keys are long key IDs or full fingerprints
+    pub fn validate(&self) -> Result<(), InvalidKey> {
+        for (record_type, policy) in self.types.iter() {
+            if let Some(key) = policy.keys.iter().find(|key| normalize_key(key).is_none()) {
+                return Err(InvalidKey { record_type: record_type.clone(), key: key.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the record against the policy
+    ///
+    /// Signatures are only checked if `verifier` is given.
+    pub fn check<R: Record>(&self, record: &R, verifier: Option<&Verifier>) -> Result<(), Violation> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let types: Vec<String> = record.file_iter()
+            .map(|(name, _)| name.as_ref().to_owned())
+            .filter(|name| name.starts_with(".type/"))
+            .map(|name| name[6..].to_owned())
+            .filter(|t| self.types.contains_key(t))
+            .collect();
+        if types.is_empty() {
+            return Ok(());
+        }
+        let authors: Vec<String> = match record.file(".authors") {
+            Some(mut reader) => {
+                let mut s = String::new();
+                let _ = reader.read_to_string(&mut s);
+                s.lines().map(|l| l.trim().to_owned()).filter(|l| !l.is_empty()).collect()
+            },
+            None => vec![],
+        };
+        let mut fingerprints = None;
+        for t in types {
+            let policy = &self.types[&t];
+            if authors.iter().any(|author| policy.authors.iter().any(|a| a == author)) {
+                continue;
+            }
+            if !policy.keys.is_empty() {
+                if fingerprints.is_none() {
+                    fingerprints = Some(verifier.and_then(|v| v.signed_by(record)).unwrap_or_default());
+                }
+                let signed = fingerprints.as_ref().unwrap().iter()
+                    .any(|fpr| policy.keys.iter().any(|key| key_matches(key, fpr)));
+                if signed {
+                    continue;
+                }
+            }
+            return Err(Violation { record_type: t });
+        }
+        Ok(())
+    }
+
+    /// Wraps a reducer so that it is only applied to authorized records
+    pub fn reducer<R: Reducer>(self, reducer: R, verifier: Option<Verifier>) -> AuthorizedReducer<R> {
+        AuthorizedReducer { policy: self, verifier, reducer }
+    }
+}
+
+/// Returns upper-cased hex digits of a long key ID or a full fingerprint
+fn normalize_key(key: &str) -> Option<String> {
+    let key: String = key.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_uppercase();
+    let key = if key.starts_with("0X") { &key[2..] } else { &key[..] };
+    if (key.len() == 16 || key.len() == 40) && key.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(key.to_owned())
+    } else {
+        None
+    }
+}
+
+fn key_matches(key: &str, fingerprint: &str) -> bool {
+    let fingerprint = fingerprint.to_uppercase();
+    match normalize_key(key) {
+        Some(ref key) if key.len() == 40 => fingerprint == *key,
+        Some(ref key) => fingerprint.len() == 40 && fingerprint.ends_with(key.as_str()),
+        None => false,
+    }
+}
+
+/// Reducer that skips records not authorized by the policy
+#[derive(Debug, Clone)]
+pub struct AuthorizedReducer<R> {
+    policy: Policy,
+    verifier: Option<Verifier>,
+    reducer: R,
+}
+
+impl<R> AuthorizedReducer<R> {
+    /// Returns a reference to the wrapped reducer
+    pub fn reducer(&self) -> &R {
+        &self.reducer
+    }
+
+    /// Returns a mutable reference to the wrapped reducer
+    pub fn reducer_mut(&mut self) -> &mut R {
+        &mut self.reducer
+    }
+}
+
+impl<R: Reducer> Reducer for AuthorizedReducer<R> where R::Item: Record {
+    type State = R::State;
+    type Item = R::Item;
+
+    fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
+        match self.policy.check(item, self.verifier.as_ref()) {
+            Ok(()) => self.reducer.reduce(state, item),
+            Err(_) => state,
+        }
+    }
+
+    fn claims(&self, item: &Self::Item) -> bool {
+        self.reducer.claims(item)
+    }
+}
+
+impl<R: StatefulReducer> StatefulReducer for AuthorizedReducer<R> where R::Item: Record {
+    fn private_state(&self) -> Option<JsonValue> {
+        self.reducer.private_state()
+    }
+
+    fn set_private_state(&mut self, state: JsonValue) -> bool {
+        self.reducer.set_private_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use repository::Repository;
+    use record::RecordOwningContainer;
+
+    fn policy() -> Policy {
+        let mut policy = Policy::default();
+        policy.types.insert("Closed".into(), TypePolicy {
+            authors: vec!["Maintainer <m@example.com>".into()],
+            keys: vec![],
+        });
+        policy
+    }
+
+    #[test]
+    fn unrestricted_type() {
+        let tmp = TempDir::new("sit").unwrap().into_path();
+        let repo = Repository::new(&tmp).unwrap();
+        let record = repo.new_record(vec![(".type/Commented", &b""[..])].into_iter(), false).unwrap();
+        assert!(policy().check(&record, None).is_ok());
+    }
+
+    #[test]
+    fn restricted_type() {
+        let tmp = TempDir::new("sit").unwrap().into_path();
+        let repo = Repository::new(&tmp).unwrap();
+        let record = repo.new_record(vec![(".type/Closed", &b""[..]),
+                                          (".authors", &b"Someone <s@example.com>"[..])].into_iter(), false).unwrap();
+        assert_eq!(policy().check(&record, None), Err(Violation { record_type: "Closed".into() }));
+        let record = repo.new_record(vec![(".type/Closed", &b""[..]),
+                                          (".authors", &b"Maintainer <m@example.com>"[..])].into_iter(), false).unwrap();
+        assert!(policy().check(&record, None).is_ok());
+    }
+
+    #[test]
+    fn authorized_reducer() {
+        struct Counter;
+        impl Reducer for Counter {
+            type State = usize;
+            type Item = ::repository::Record;
+            fn reduce(&mut self, state: usize, _item: &Self::Item) -> usize {
+                state + 1
+            }
+        }
+        let tmp = TempDir::new("sit").unwrap().into_path();
+        let repo = Repository::new(&tmp).unwrap();
+        let allowed = repo.new_record(vec![(".type/Commented", &b""[..])].into_iter(), false).unwrap();
+        let denied = repo.new_record(vec![(".type/Closed", &b""[..])].into_iter(), false).unwrap();
+        let mut reducer = policy().reducer(Counter, None);
+        let state = reducer.reduce(0, &allowed);
+        assert_eq!(reducer.reduce(state, &denied), 1);
+    }
+
+    #[test]
+    fn key_id_matching() {
+        let fpr = "4F3E2C2A4F3E2C2A4F3E2C2A4F3E2C2A0B1D9E8F";
+        assert!(key_matches(fpr, fpr));
+        assert!(key_matches("4F3E2C2A0B1D9E8F", fpr));
+        assert!(key_matches("0x4f3e2c2a0b1d9e8f", fpr));
+        assert!(key_matches("4F3E 2C2A 0B1D 9E8F", fpr));
+        assert!(!key_matches("4F3E2C2A0B1D9E8E", fpr));
+        // short and non-hex keys never match
+        assert!(!key_matches("0B1D9E8F", fpr));
+        assert!(!key_matches("8F", fpr));
+        assert!(!key_matches("", fpr));
+        assert!(!key_matches("4F3E2C2A0B1D9EXF", "4F3E2C2A4F3E2C2A4F3E2C2A4F3E2C2A0B1D9EXF"));
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let mut policy = policy();
+        assert!(policy.validate().is_ok());
+        policy.types.get_mut("Closed").unwrap().keys = vec!["4F3E2C2A0B1D9E8F".into(),
+                                                            "4F3E2C2A4F3E2C2A4F3E2C2A4F3E2C2A0B1D9E8F".into()];
+        assert!(policy.validate().is_ok());
+        for key in &["0B1D9E8F", "0xAB", "", "4F3E2C2A0B1D9EXF", "4F3E2C2A4F3E2C2A4F3E2C2A4F3E2C2A0B1D9E8"] {
+            policy.types.get_mut("Closed").unwrap().keys = vec![key.to_string()];
+            assert_eq!(policy.validate(), Err(InvalidKey { record_type: "Closed".into(), key: key.to_string() }));
+        }
+    }
+
+    #[test]
+    fn invalid_keys_rejected_on_load() {
+        let tmp = TempDir::new("sit").unwrap().into_path();
+        let repo = Repository::new(&tmp).unwrap();
+        ::std::fs::write(tmp.join(POLICY_FILE), r#"{"types": {"Closed": {"keys": ["0B1D9E8F"]}}}"#).unwrap();
+        assert!(repo.policy().is_err());
+        ::std::fs::write(tmp.join(POLICY_FILE), r#"{"types": {"Closed": {"keys": ["ZZ3E2C2A0B1D9E8F"]}}}"#).unwrap();
+        assert!(repo.policy().is_err());
+        ::std::fs::write(tmp.join(POLICY_FILE), r#"{"types": {"Closed": {"keys": ["4F3E2C2A0B1D9E8F"]}}}"#).unwrap();
+        assert!(repo.policy().is_ok());
+    }
+}
@@ -0,0 +1,236 @@
+//! JMESPath queries and filters
+//!
+//! Expressions compiled with [`compile`] can use the following functions
+//! in addition to the standard JMESPath ones:
+//!
+//! | Function                     | Description                                                      |
+//! |------------------------------|------------------------------------------------------------------|
+//! | `matches(string, regex)`     | Returns `true` if the string matches the regular expression       |
+//! | `lower(string)`              | Returns the string in lower case                                 |
+//! | `to_datetime(string)`        | Parses an ISO-8601 timestamp into milliseconds since the epoch (`null` if invalid) |
+//! | `days_since(string\|number)` | Returns the number of days passed since the timestamp (`null` if invalid) |
+//! | `days_between(from, to)`     | Returns the number of days between two timestamps (`null` if either is invalid) |
+//! | `week(string\|number)`       | Returns ISO-8601 week (`YYYY-Www`) of the timestamp (`null` if invalid) |
+//! | `file_text(record, name)`    | Returns text of record's file (`null` if there's no such file or it is binary) |
+//!
+//! Results can be ordered by an expression using [`sort_by`].
+//!
+//! [`compile`]: fn.compile.html
+//! [`sort_by`]: fn.sort_by.html
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use jmespath::{Context, ErrorReason, Expression, JmespathError, Rcvar, Runtime, Variable};
+use jmespath::functions::{ArgumentType, CustomFunction, Signature};
+use regex::Regex;
+use serde_json::Value as JsonValue;
+
+use timestamp;
+
+/// Result of a JMESPath function (not exported by `jmespath`)
+type SearchResult = Result<Rcvar, JmespathError>;
+
+lazy_static! {
+    /// JMESPath runtime with standard and SIT functions
+    pub static ref RUNTIME: Runtime = {
+        let mut runtime = Runtime::new();
+        runtime.register_builtin_functions();
+        register_functions(&mut runtime);
+        runtime
+    };
+}
+
+thread_local! {
+    /// Regular expressions used by `matches`, compiled once per thread
+    static REGEXES: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+/// Compiles a JMESPath expression
+pub fn compile(expression: &str) -> Result<Expression<'static>, JmespathError> {
+    RUNTIME.compile(expression)
+}
+
+/// Compares two values for sorting
+///
+/// Numbers are compared numerically, strings lexicographically and booleans
+/// (`false` first). Values of different types are ordered as follows: numbers,
+/// strings, booleans, arrays, objects, `null` (so that values a sorting expression
+/// couldn't find end up last). Arrays and objects are considered equal to each other.
+pub fn compare(a: &Variable, b: &Variable) -> Ordering {
+    fn rank(value: &Variable) -> u8 {
+        if value.is_number() { 0 }
+        else if value.is_string() { 1 }
+        else if value.is_boolean() { 2 }
+        else if value.is_array() { 3 }
+        else if value.is_object() { 4 }
+        else { 5 }
+    }
+    match (rank(a), rank(b)) {
+        (0, 0) => a.as_number().unwrap().partial_cmp(&b.as_number().unwrap()).unwrap_or(Ordering::Equal),
+        (1, 1) => a.as_string().unwrap().cmp(b.as_string().unwrap()),
+        (2, 2) => a.as_boolean().unwrap().cmp(&b.as_boolean().unwrap()),
+        (rank_a, rank_b) => rank_a.cmp(&rank_b),
+    }
+}
+
+/// Sorts values by the result of an expression (see [`compare`])
+///
+/// Sorting is stable, even if `reverse` is `true`.
+///
+/// [`compare`]: fn.compare.html
+pub fn sort_by<T, F>(values: &mut Vec<T>, expression: &Expression, reverse: bool, data: F) -> Result<(), JmespathError>
+    where F: Fn(&T) -> &Variable {
+    let mut keyed = Vec::with_capacity(values.len());
+    for value in values.drain(..) {
+        let key = expression.search(data(&value))?;
+        keyed.push((key, value));
+    }
+    keyed.sort_by(|&(ref a, _), &(ref b, _)| if reverse { compare(b, a) } else { compare(a, b) });
+    values.extend(keyed.into_iter().map(|(_, value)| value));
+    Ok(())
+}
+
+fn number(value: f64) -> Rcvar {
+    Rcvar::new(Variable::from(JsonValue::from(value)))
+}
+
+fn timestamp(value: &Rcvar) -> Option<i64> {
+    match value.as_number() {
+        Some(number) => Some(number as i64),
+        None => value.as_string().and_then(|s| timestamp::parse(s)),
+    }
+}
+
+/// Registers SIT functions
+pub fn register_functions(runtime: &mut Runtime) {
+    runtime.register_function("matches", Box::new(CustomFunction::new(
+        Signature::new(vec![ArgumentType::String, ArgumentType::String], None),
+        Box::new(|args: &[Rcvar], ctx: &mut Context| -> SearchResult {
+            let pattern = args[1].as_string().unwrap();
+            REGEXES.with(|regexes| {
+                let mut regexes = regexes.borrow_mut();
+                if !regexes.contains_key(pattern) {
+                    let regex = Regex::new(pattern)
+                        .map_err(|err| JmespathError::from_ctx(ctx, ErrorReason::Parse(format!("invalid regular expression: {}", err))))?;
+                    regexes.insert(pattern.clone(), regex);
+                }
+                Ok(Rcvar::new(Variable::Bool(regexes[pattern].is_match(args[0].as_string().unwrap()))))
+            })
+        }))));
+    runtime.register_function("lower", Box::new(CustomFunction::new(
+        Signature::new(vec![ArgumentType::String], None),
+        Box::new(|args: &[Rcvar], _: &mut Context| -> SearchResult {
+            Ok(Rcvar::new(Variable::String(args[0].as_string().unwrap().to_lowercase())))
+        }))));
+    runtime.register_function("to_datetime", Box::new(CustomFunction::new(
+        Signature::new(vec![ArgumentType::String], None),
+        Box::new(|args: &[Rcvar], _: &mut Context| -> SearchResult {
+            Ok(match timestamp(&args[0]) {
+                Some(timestamp) => number(timestamp as f64),
+                None => Rcvar::new(Variable::Null),
+            })
+        }))));
+    runtime.register_function("days_since", Box::new(CustomFunction::new(
+        Signature::new(vec![ArgumentType::Union(vec![ArgumentType::String, ArgumentType::Number])], None),
+        Box::new(|args: &[Rcvar], _: &mut Context| -> SearchResult {
+            Ok(match timestamp(&args[0]) {
+                Some(timestamp) => number((timestamp::now() - timestamp) as f64 / 86_400_000.0),
+                None => Rcvar::new(Variable::Null),
+            })
+        }))));
+    runtime.register_function("days_between", Box::new(CustomFunction::new(
+        Signature::new(vec![ArgumentType::Union(vec![ArgumentType::String, ArgumentType::Number, ArgumentType::Null]),
+                            ArgumentType::Union(vec![ArgumentType::String, ArgumentType::Number, ArgumentType::Null])], None),
+        Box::new(|args: &[Rcvar], _: &mut Context| -> SearchResult {
+            Ok(match (timestamp(&args[0]), timestamp(&args[1])) {
+                (Some(from), Some(to)) => number((to - from) as f64 / 86_400_000.0),
+                _ => Rcvar::new(Variable::Null),
+            })
+        }))));
+    runtime.register_function("week", Box::new(CustomFunction::new(
+        Signature::new(vec![ArgumentType::Union(vec![ArgumentType::String, ArgumentType::Number, ArgumentType::Null])], None),
+        Box::new(|args: &[Rcvar], _: &mut Context| -> SearchResult {
+            Ok(Rcvar::new(match timestamp(&args[0]) {
+                Some(timestamp) => Variable::String(timestamp::week(timestamp)),
+                None => Variable::Null,
+            }))
+        }))));
+    runtime.register_function("file_text", Box::new(CustomFunction::new(
+        Signature::new(vec![ArgumentType::Object, ArgumentType::String], None),
+        Box::new(|args: &[Rcvar], _: &mut Context| -> SearchResult {
+            let name = args[1].as_string().unwrap();
+            let text = args[0].as_object()
+                .and_then(|record| record.get("files"))
+                .and_then(|files| files.as_object().and_then(|files| files.get(name)))
+                .and_then(|file| file.as_string().cloned());
+            Ok(Rcvar::new(text.map(Variable::String).unwrap_or(Variable::Null)))
+        }))));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn search(expression: &str, data: &str) -> JsonValue {
+        let data = Variable::from(serde_json::from_str::<JsonValue>(data).unwrap());
+        serde_json::to_value(&*compile(expression).unwrap().search(&data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn matches() {
+        assert_eq!(search("matches(summary, '^[Cc]rash')", r#"{"summary": "Crash on startup"}"#), JsonValue::Bool(true));
+        assert_eq!(search("matches(summary, 'crash$')", r#"{"summary": "Crash on startup"}"#), JsonValue::Bool(false));
+        let data = Variable::from(serde_json::from_str::<JsonValue>(r#"{"summary": ""}"#).unwrap());
+        assert!(compile("matches(summary, '(')").unwrap().search(&data).is_err());
+    }
+
+    #[test]
+    fn lower() {
+        assert_eq!(search("lower(summary) == 'crash'", r#"{"summary": "CrAsH"}"#), JsonValue::Bool(true));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(search("to_datetime(t)", r#"{"t": "1970-01-01T00:00:01Z"}"#), JsonValue::from(1000.0));
+        assert_eq!(search("to_datetime(t)", r#"{"t": "soon"}"#), JsonValue::Null);
+        assert_eq!(search("to_datetime(a) < to_datetime(b)", r#"{"a": "2018-01-01", "b": "2018-01-02T00:00:00Z"}"#), JsonValue::Bool(true));
+        assert_eq!(search("days_since(t) > `365`", r#"{"t": "2000-01-01"}"#), JsonValue::Bool(true));
+        assert_eq!(search("days_since(t) < `1`", &format!(r#"{{"t": {}}}"#, timestamp::now())), JsonValue::Bool(true));
+        assert_eq!(search("days_between(a, b)", r#"{"a": "2018-01-01", "b": "2018-01-02T12:00:00Z"}"#), JsonValue::from(1.5));
+        assert_eq!(search("days_between(a, b)", r#"{"a": "2018-01-01"}"#), JsonValue::Null);
+        assert_eq!(search("week(t)", r#"{"t": "2018-01-08T10:00:00Z"}"#), JsonValue::String("2018-W02".into()));
+    }
+
+    fn values(json: &str) -> Vec<Variable> {
+        serde_json::from_str::<Vec<JsonValue>>(json).unwrap().into_iter().map(Variable::from).collect()
+    }
+
+    fn to_json(values: Vec<Variable>) -> JsonValue {
+        serde_json::to_value(&values).unwrap()
+    }
+
+    #[test]
+    fn sorting() {
+        let mut v = values(r#"[{"n": 2}, {"n": "b"}, {}, {"n": 10}, {"n": "a"}, {"n": true}]"#);
+        sort_by(&mut v, &compile("n").unwrap(), false, |v| v).unwrap();
+        assert_eq!(to_json(v), serde_json::from_str::<JsonValue>(r#"[{"n": 2}, {"n": 10}, {"n": "a"}, {"n": "b"}, {"n": true}, {}]"#).unwrap());
+    }
+
+    #[test]
+    fn sorting_stable() {
+        let mut v = values(r#"[{"n": 1, "i": 0}, {"n": 2, "i": 1}, {"n": 1, "i": 2}]"#);
+        sort_by(&mut v, &compile("n").unwrap(), true, |v| v).unwrap();
+        assert_eq!(to_json(v), serde_json::from_str::<JsonValue>(r#"[{"n": 2, "i": 1}, {"n": 1, "i": 0}, {"n": 1, "i": 2}]"#).unwrap());
+    }
+
+    #[test]
+    fn file_text() {
+        let record = r#"{"hash": "h", "files": {".type/SummaryChanged": "", "text": "Title", "image": {"type": "binary"}}}"#;
+        assert_eq!(search("file_text(@, 'text')", record), JsonValue::String("Title".into()));
+        assert_eq!(search("file_text(@, 'image')", record), JsonValue::Null);
+        assert_eq!(search("file_text(@, 'missing')", record), JsonValue::Null);
+    }
+}
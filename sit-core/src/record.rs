@@ -43,7 +43,7 @@ use std::marker::PhantomData;
 /// With limited ways to construct this structure, it's
 /// always ensured to have all its files sorted as required
 /// by SIT for deterministic hashing.
-pub struct OrderedFiles<'a, F: File>(Vec<F>, PhantomData<&'a ()>);
+pub struct OrderedFiles<'a, F: File>(pub(crate) Vec<F>, PhantomData<&'a ()>);
 
 impl<'a, F: File> OrderedFiles<'a, F>  where F: 'a, F::Read: 'a {
     /// Returns a boxed version of itself
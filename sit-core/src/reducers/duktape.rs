@@ -1,16 +1,22 @@
 use std::io::Read;
 
-use super::Reducer;
+use super::{Reducer, StatefulReducer};
+use super::order;
 use serde_json::{Map, Value as JsonValue};
 use std::marker::PhantomData;
 use ::Record;
 use duktape;
+use duktape::limits::{self, Limits};
 use std::ptr;
-use std::ffi::{CString, CStr, OsStr};
+use std::ffi::{CString, CStr};
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::io;
+use std::time::Duration;
 use path::HasPath;
+use cfg;
+use record::RecordExt;
+use encryption::{self, GnuPG};
 
 #[cfg(feature = "duktape-mmap")]
 use memmap;
@@ -18,43 +24,7 @@ use memmap;
 #[cfg(feature = "cesu8")]
 use cesu8;
 
-pub trait SourceFiles {
-    type Iter : Iterator<Item = PathBuf>;
-
-    fn source_files(self) -> Result<Self::Iter, Error>;
-}
-
-impl<T> SourceFiles for T where T: IntoIterator<Item = PathBuf> {
-    type Iter = T::IntoIter;
-
-    fn source_files(self) -> Result<Self::Iter, Error> {
-        Ok(self.into_iter())
-    }
-}
-
-impl<'a, MI> SourceFiles for &'a ::Repository<MI> where MI: ::repository::ModuleIterator<PathBuf, ::repository::Error> {
-
-    type Iter = ::std::vec::IntoIter<PathBuf>;
-
-    fn source_files(self) -> Result<Self::Iter, Error> {
-        let mut files = vec![];
-
-        let reducers_path = self.path().join("reducers");
-        if reducers_path.is_dir() {
-            files.push(reducers_path);
-        }
-
-        for module_name in self.module_iter()? {
-            let module_name = module_name?;
-            let path = self.modules_path().join(module_name).join("reducers");
-            if path.is_dir() {
-                files.push(path);
-            }
-        }
-
-        Ok(files.into_iter())
-    }
-}
+pub use super::SourceFiles;
 
 #[derive(Debug)]
 pub struct DuktapeReducer<R: Record> {
@@ -63,6 +33,14 @@ pub struct DuktapeReducer<R: Record> {
     filenames: Vec<PathBuf>,
     phantom_data: PhantomData<R>,
     functions: Vec<Vec<u8>>,
+    /// Record types each reducer handles (`None` if all)
+    types: Vec<Option<Vec<String>>>,
+    decryptor: Option<GnuPG>,
+    limits: *mut Limits,
+    console: *mut Console,
+    logging: Logging,
+    logs: Vec<LogEntry>,
+    trace: Option<Vec<TraceEntry>>,
 }
 
 unsafe impl<R: Record> Send for DuktapeReducer<R> {}
@@ -71,6 +49,7 @@ unsafe impl<R: Record> Send for DuktapeReducer<R> {}
 pub enum Error {
     IoError(::std::io::Error),
     RepositoryError(::repository::Error),
+    OrderError(order::Error),
     #[error(no_from, non_std)]
     ExecutionError {
         error: String,
@@ -82,10 +61,140 @@ pub enum Error {
     },
 }
 
+/// Where reducers' `console` output goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Logging {
+    /// Output is discarded (default)
+    Disabled,
+    /// Output is printed to stderr
+    Stderr,
+    /// Output is collected (see [`DuktapeReducer::take_logs`])
+    ///
+    /// [`DuktapeReducer::take_logs`]: struct.DuktapeReducer.html#method.take_logs
+    Collect,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging::Disabled
+    }
+}
+
+/// `console` method used
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Log,
+    Warn,
+    Error,
+}
+
+impl ::std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            LogLevel::Log => write!(f, "log"),
+            LogLevel::Warn => write!(f, "warn"),
+            LogLevel::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Message logged by a reducer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub level: LogLevel,
+    /// Reducer's file
+    pub file: PathBuf,
+    /// Hash of the record being reduced
+    pub hash: String,
+    pub message: String,
+}
+
+/// Effect of a reducer function on the state when reducing a record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Reducer's file
+    pub file: PathBuf,
+    /// Record's hash
+    pub hash: String,
+    /// Record's types
+    pub types: Vec<String>,
+    /// State before the reducer function was called
+    pub input: JsonValue,
+    /// State after the reducer function was called (if it succeeded)
+    pub output: Option<JsonValue>,
+    /// Error (if the reducer function failed)
+    pub error: Option<String>,
+}
+
+/// Messages logged during a reducer function call
+#[derive(Debug, Default)]
+struct Console {
+    enabled: bool,
+    messages: Vec<(LogLevel, String)>,
+}
+
+const CONSOLE_STASH_PROP: &str = "console";
+
+unsafe extern "C" fn console_fn(context: *mut duktape::duk_context) -> duktape::duk_ret_t {
+    let level = match duktape::duk_get_current_magic(context) {
+        1 => LogLevel::Warn,
+        2 => LogLevel::Error,
+        _ => LogLevel::Log,
+    };
+    let nargs = duktape::duk_get_top(context);
+    duktape::duk_push_heap_stash(context);
+    let prop = CString::new(CONSOLE_STASH_PROP).unwrap();
+    duktape::duk_get_prop_string(context, -1, prop.as_ptr());
+    let console = duktape::duk_get_pointer(context, -1) as *mut Console;
+    duktape::duk_pop_2(context);
+    if console.is_null() || !(*console).enabled {
+        return 0;
+    }
+    let mut parts = vec![];
+    for i in 0..nargs {
+        if duktape::duk_is_string(context, i) == 1 {
+            parts.push(CStr::from_ptr(duktape::duk_get_string(context, i)).to_string_lossy().into_owned());
+        } else {
+            duktape::duk_dup(context, i);
+            duktape::duk_json_encode(context, -1);
+            parts.push(CStr::from_ptr(duktape::duk_safe_to_lstring(context, -1, ptr::null_mut())).to_string_lossy().into_owned());
+            duktape::duk_pop(context);
+        }
+    }
+    (*console).messages.push((level, parts.join(" ")));
+    0
+}
+
+/// Decodes JSON produced by `duk_json_encode`
+fn decode_json(json: &CStr) -> Map<String, JsonValue> {
+    use serde_json;
+    #[cfg(feature = "cesu8")]
+    let map: Map<String, JsonValue> = match cesu8::from_cesu8(json.to_bytes()) {
+        Ok(s) => serde_json::from_str(&s),
+        Err(_) => serde_json::from_slice(json.to_bytes()),
+    }.unwrap();
+    #[cfg(not(feature = "cesu8"))]
+    let map: Map<String, JsonValue> = serde_json::from_slice(json.to_bytes()).unwrap();
+    map
+}
+
+/// Returns a copy of the object on top of the stack
+unsafe fn top_state(ctx: *mut duktape::duk_context) -> JsonValue {
+    duktape::duk_dup_top(ctx);
+    duktape::duk_json_encode(ctx, -1);
+    let state = JsonValue::Object(decode_json(CStr::from_ptr(duktape::duk_get_string(ctx, -1))));
+    duktape::duk_pop(ctx);
+    state
+}
+
 impl<R: Record> Drop for DuktapeReducer<R> {
     fn drop(&mut self) {
         unsafe {
             duktape::duk_destroy_heap(self.context);
+            // the heap is gone, limits and console are no longer referenced
+            drop(Box::from_raw(self.limits));
+            drop(Box::from_raw(self.console));
         }
     }
 }
@@ -96,7 +205,26 @@ unsafe extern "C" fn fatal_handler(_udata: *mut ::std::os::raw::c_void, msg: *co
 
 impl<R: Record> DuktapeReducer<R> {
 
-    unsafe fn load_module(context: *mut duktape::duk_hthread) -> Result<(), Error> {
+    /// Defines `console.log`, `console.warn` and `console.error`
+    unsafe fn init_console(context: *mut duktape::duk_context, console: *mut Console) {
+        let prop = CString::new(CONSOLE_STASH_PROP).unwrap();
+        duktape::duk_push_heap_stash(context);
+        duktape::duk_push_pointer(context, console as *mut _);
+        duktape::duk_put_prop_string(context, -2, prop.as_ptr());
+        duktape::duk_pop(context);
+        duktape::duk_push_object(context);
+        for (magic, name) in ["log", "warn", "error"].iter().enumerate() {
+            let name = CString::new(*name).unwrap();
+            // -1 is DUK_VARARGS
+            duktape::duk_push_c_function(context, Some(console_fn), -1);
+            duktape::duk_set_magic(context, -1, magic as i32);
+            duktape::duk_put_prop_string(context, -2, name.as_ptr());
+        }
+        duktape::duk_put_global_string(context, prop.as_ptr());
+    }
+
+    /// Loads the module, leaves `module.exports` on the stack and returns `module.types`
+    unsafe fn load_module(context: *mut duktape::duk_hthread) -> Result<Option<Vec<String>>, Error> {
         // Now, execute the function with a defined module
         duktape::duk_require_function(context, -1);
         duktape::duk_push_object(context); // module
@@ -123,11 +251,47 @@ impl<R: Record> DuktapeReducer<R> {
         // module retval
         duktape::duk_pop(context);
         // module
+        let types = DuktapeReducer::<R>::module_types(context)?;
         duktape::duk_get_prop_string(context, -1, exports_prop.as_ptr());
         // module f'
         duktape::duk_remove(context, -2);
         // f'
-        Ok(())
+        Ok(types)
+    }
+
+    /// Reads `module.types` (record types the reducer handles) of the module on top of the stack
+    unsafe fn module_types(context: *mut duktape::duk_hthread) -> Result<Option<Vec<String>>, Error> {
+        let types_prop = CString::new("types").unwrap();
+        duktape::duk_get_prop_string(context, -1, types_prop.as_ptr());
+        if duktape::duk_is_undefined(context, -1) == 1 {
+            duktape::duk_pop(context);
+            return Ok(None);
+        }
+        let error = || Error::ExecutionError { error: "module.types should be an array of strings".into() };
+        if duktape::duk_is_array(context, -1) != 1 {
+            duktape::duk_pop(context);
+            return Err(error());
+        }
+        let mut types = vec![];
+        for i in 0..duktape::duk_get_length(context, -1) {
+            duktape::duk_get_prop_index(context, -1, i as u32);
+            if duktape::duk_is_string(context, -1) != 1 {
+                duktape::duk_pop_2(context);
+                return Err(error());
+            }
+            types.push(CStr::from_ptr(duktape::duk_get_string(context, -1)).to_string_lossy().into_owned());
+            duktape::duk_pop(context);
+        }
+        duktape::duk_pop(context);
+        Ok(Some(types))
+    }
+
+    /// Returns true if the reducer handles any of record's types
+    fn handles(&self, reducer: usize, record_types: &[String]) -> bool {
+        match self.types[reducer] {
+            None => true,
+            Some(ref types) => types.iter().any(|t| record_types.contains(t)),
+        }
     }
 
 
@@ -135,6 +299,13 @@ impl<R: Record> DuktapeReducer<R> {
     unsafe extern "C" fn mod_search(context: *mut duktape::duk_hthread) -> duktape::duk_ret_t {
         // module id
         let id = CStr::from_ptr(duktape::duk_get_string(context, 0));
+        if id.to_bytes() == duktape::stdlib::GLOBAL.as_bytes() {
+            let str_global = CString::new(duktape::stdlib::GLOBAL).unwrap();
+            let str_exports = CString::new("exports").unwrap();
+            duktape::duk_get_global_string(context, str_global.as_ptr());
+            duktape::duk_put_prop_string(context, 3, str_exports.as_ptr());
+            return 0;
+        }
         let str_paths_prop = CString::new("paths").unwrap();
         let str_duktape = CString::new("Duktape").unwrap();
         duktape::duk_get_global_string(context, str_duktape.as_ptr());
@@ -171,39 +342,133 @@ impl<R: Record> DuktapeReducer<R> {
                 duktape::duk_pop(context);
             }
         };
-        // find matching allowed path
-        let path = match paths.into_iter().find(|path| filename.starts_with(path)) {
+        // find matching allowed path (the most specific one)
+        let root = match paths.into_iter().map(Path::new)
+            .filter(|path| Path::new(&filename).starts_with(path))
+            .max_by_key(|path| path.components().count()) {
             None => {
                 let err = CString::new(format!("matching path not found for {}", filename)).unwrap();
                 duktape::duk_error_raw(context, duktape::DUK_ERR_ERROR as i32, ptr::null_mut(), 0,err.as_ptr());
                 return duktape::DUK_RET_ERROR;
             }
-            Some(path) => path,
+            Some(path) => path.to_path_buf(),
         };
         // find the module
-        let prefix = PathBuf::from(path);
-        let mut mod_path = prefix.join(filename);
-        mod_path.pop();
-        mod_path.push(PathBuf::from(id.to_str().unwrap()));
-        if mod_path.is_file() && mod_path.strip_prefix(&prefix).is_ok() {
-            let mut f = fs::File::open(mod_path).unwrap();
-            let mut s = String::new();
-            f.read_to_string(&mut s).unwrap();
-            let src = CString::new(s).unwrap();
-            duktape::duk_push_string(context, src.as_ptr());
-            return 1;
-        } else {
-            let err = CString::new(format!("module not found: {:?}", id)).unwrap();
-            duktape::duk_error_raw(context, duktape::DUK_ERR_ERROR as i32, ptr::null_mut(), 0,err.as_ptr());
-            return duktape::DUK_RET_ERROR;
+        let from = Path::new(&filename).parent().unwrap_or(&root).to_path_buf();
+        let mod_path = match resolve_module(&root, &from, id.to_str().unwrap()) {
+            Some(mod_path) => mod_path,
+            None => {
+                let err = CString::new(format!("module not found: {:?}", id)).unwrap();
+                duktape::duk_error_raw(context, duktape::DUK_ERR_ERROR as i32, ptr::null_mut(), 0,err.as_ptr());
+                return duktape::DUK_RET_ERROR;
+            }
+        };
+        // relative requires within the module are resolved against
+        // the file it was found in
+        let resolved_id = mod_path.strip_prefix(&root).unwrap().components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>().join("/");
+        let str_id = CString::new("id").unwrap();
+        let str_resolved_id = CString::new(resolved_id).unwrap();
+        duktape::duk_push_string(context, str_id.as_ptr());
+        duktape::duk_push_string(context, str_resolved_id.as_ptr());
+        duktape::duk_def_prop(context, 1, duktape::DUK_DEFPROP_HAVE_VALUE | duktape::DUK_DEFPROP_FORCE);
+        let str_filename_prop = CString::new("filename").unwrap();
+        let str_filename = CString::new(mod_path.to_str().unwrap()).unwrap();
+        duktape::duk_push_string(context, str_filename.as_ptr());
+        duktape::duk_put_prop_string(context, 3, str_filename_prop.as_ptr());
+
+        let mut f = fs::File::open(&mod_path).unwrap();
+        let mut s = String::new();
+        f.read_to_string(&mut s).unwrap();
+        let src = CString::new(s).unwrap();
+        duktape::duk_push_string(context, src.as_ptr());
+        if mod_path.extension() == Some(::std::ffi::OsStr::new("json")) {
+            // JSON modules are their own exports
+            let str_exports = CString::new("exports").unwrap();
+            duktape::duk_json_decode(context, -1);
+            duktape::duk_put_prop_string(context, 3, str_exports.as_ptr());
+            return 0;
+        }
+        return 1;
+    }
+}
+
+/// Resolves a module the way Node.js does
+///
+/// `id` (already resolved by Duktape against the id of the requiring module)
+/// is looked up relative to `root` first, then in `node_modules` directories
+/// from `from` up to `root`. Modules can't be found outside of `root`.
+#[cfg(feature = "duktape-require")]
+fn resolve_module(root: &Path, from: &Path, id: &str) -> Option<PathBuf> {
+    let mut candidates = vec![root.join(id)];
+    for dir in from.ancestors().take_while(|dir| dir.starts_with(root)) {
+        if dir.file_name() != Some(::std::ffi::OsStr::new("node_modules")) {
+            candidates.push(dir.join("node_modules").join(id));
+        }
+    }
+    candidates.into_iter()
+        .filter_map(|candidate| resolve_file(&candidate).or_else(|| resolve_directory(&candidate)))
+        .map(|path| normalize(&path))
+        .find(|path| path.starts_with(root))
+}
+
+/// Resolves `PATH`, `PATH.js` or `PATH.json`
+#[cfg(feature = "duktape-require")]
+fn resolve_file(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    for ext in &["js", "json"] {
+        let mut file = path.as_os_str().to_os_string();
+        file.push(".");
+        file.push(ext);
+        let file = PathBuf::from(file);
+        if file.is_file() {
+            return Some(file);
+        }
+    }
+    None
+}
+
+/// Resolves `main` of `PATH/package.json`, `PATH/index.js` or `PATH/index.json`
+#[cfg(feature = "duktape-require")]
+fn resolve_directory(path: &Path) -> Option<PathBuf> {
+    use serde_json;
+    if !path.is_dir() {
+        return None;
+    }
+    let main = fs::File::open(path.join("package.json")).ok()
+        .and_then(|file| serde_json::from_reader::<_, JsonValue>(file).ok())
+        .and_then(|package| package.get("main").and_then(JsonValue::as_str).map(|main| path.join(main)))
+        .and_then(|main| resolve_file(&main).or_else(|| resolve_file(&main.join("index"))));
+    main.or_else(|| resolve_file(&path.join("index")))
+}
+
+/// Removes `.` and `..` components without consulting the file system
+#[cfg(feature = "duktape-require")]
+fn normalize(path: &Path) -> PathBuf {
+    use std::path::Component;
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => { result.pop(); },
+            component => result.push(component.as_os_str()),
         }
     }
+    result
 }
 
 impl<R: Record> DuktapeReducer<R> {
     pub fn new<SF: SourceFiles>(source_files: SF) -> Result<Self, Error> {
+        let limits = Box::into_raw(Box::new(Limits::default()));
+        let console = Box::into_raw(Box::new(Console::default()));
         let context = unsafe {
-            duktape::duk_create_heap(None, None, None,ptr::null_mut(), Some(fatal_handler))
+            let context = limits::create_heap(limits, Some(fatal_handler));
+            DuktapeReducer::<R>::init_console(context, console);
+            duktape::stdlib::init(context);
+            context
         };
         #[cfg(feature = "duktape-require")]
         let str_duktape = CString::new("Duktape").unwrap();
@@ -241,63 +506,163 @@ impl<R: Record> DuktapeReducer<R> {
         let mut reducers = 0;
         let mut filenames = vec![];
         let mut functions = vec![];
-        let files = source_files.source_files()?;
-        // in test builds, we guarantee the order of files, but not in other builds as
-        // it is not a great idea to rely on the order of these files
-        #[cfg(test)]
-        let mut files : Vec<_> = files.collect();
-        #[cfg(test)]
-        files.sort();
-        for file in files {
-            #[cfg(feature = "duktape-require")] {
-                let path = if !file.is_dir() {
-                    file.parent().unwrap_or(Path::new("/")).to_path_buf()
-                } else {
-                    file.clone()
-                };
-                if !directories.iter().any(|d| d == &path) {
-                    directories.push(path);
-                    let str_path = CString::new(directories.last().unwrap().to_str().unwrap()).unwrap();
-                    unsafe {
-                        duktape::duk_push_heapptr(context, paths_array);
-                        duktape::duk_push_string(context, str_path.as_ptr());
-                        duktape::duk_put_prop_index(context, -2, paths_counter);
-                        duktape::duk_pop(context);
-                    }
-                    paths_counter += 1;
-                }
-            }
-
-            if file.is_file() {
-                filenames.push(file.clone());
-                functions.push(unsafe { DuktapeReducer::<R>::load_source(file, context)? });
-                reducers += 1;
-            } else if file.is_dir() {
-                let js_ext = Some(OsStr::new("js"));
-                for entry in fs::read_dir(file)?.filter_map(Result::ok) {
-                    let file = entry.path();
-                    if file.extension() == js_ext {
-                        filenames.push(file);
-                        functions.push(unsafe { DuktapeReducer::<R>::load_source(entry.path(), context)? });
-                        reducers += 1;
-                    } 
-                }
+        let mut types = vec![];
+        let repository = source_files.repository_reducers();
+        let files: Vec<_> = source_files.source_files()?.collect();
+        #[cfg(feature = "duktape-require")]
+        for file in files.iter() {
+            let path = if !file.is_dir() {
+                file.parent().unwrap_or(Path::new("/")).to_path_buf()
             } else {
-                let err: io::Error = io::ErrorKind::NotFound.into();
-                return Err(err.into());
+                file.clone()
+            };
+            if !directories.iter().any(|d| d == &path) {
+                directories.push(path);
+                let str_path = CString::new(directories.last().unwrap().to_str().unwrap()).unwrap();
+                unsafe {
+                    duktape::duk_push_heapptr(context, paths_array);
+                    duktape::duk_push_string(context, str_path.as_ptr());
+                    duktape::duk_put_prop_index(context, -2, paths_counter);
+                    duktape::duk_pop(context);
+                }
+                paths_counter += 1;
             }
-
-       }
+        }
+        for file in order::order(files, repository, "js")? {
+            filenames.push(file.clone());
+            let (function, handled_types) = unsafe { DuktapeReducer::<R>::load_source(file, context)? };
+            functions.push(function);
+            types.push(handled_types);
+            reducers += 1;
+        }
         Ok(DuktapeReducer {
             context,
             reducers,
             filenames,
             functions,
+            types,
             phantom_data: PhantomData,
+            decryptor: None,
+            limits,
+            console,
+            logging: Logging::Disabled,
+            logs: vec![],
+            trace: None,
         })
     }
 
-    unsafe fn load_source(file: PathBuf, context: *mut duktape::duk_context) -> Result<Vec<u8>, Error> {
+    /// Sets a decryptor for encrypted records
+    ///
+    /// If set, reducers will be given decrypted content of encrypted records,
+    /// provided it can be decrypted. Otherwise, the content is passed as is.
+    pub fn set_decryptor(&mut self, decryptor: Option<GnuPG>) {
+        self.decryptor = decryptor;
+    }
+
+    /// Returns the reducer with a changed decryptor for encrypted records
+    pub fn with_decryptor(mut self, decryptor: Option<GnuPG>) -> Self {
+        self.decryptor = decryptor;
+        self
+    }
+
+    /// Sets maximum execution time of a reducer function per record
+    ///
+    /// If exceeded, reducer's execution is aborted and the error is reported
+    /// in the `errors` array of the state.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        unsafe { (*self.limits).set_timeout(timeout) }
+    }
+
+    /// Returns the reducer with a changed execution time limit
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Sets maximum heap size (in bytes) while reducer functions are executed
+    ///
+    /// If exceeded, reducer's execution is aborted and the error is reported
+    /// in the `errors` array of the state.
+    pub fn set_heap_limit(&mut self, heap_limit: Option<usize>) {
+        unsafe { (*self.limits).set_heap_limit(heap_limit) }
+    }
+
+    /// Returns the reducer with a changed heap size limit
+    pub fn with_heap_limit(mut self, heap_limit: Option<usize>) -> Self {
+        self.set_heap_limit(heap_limit);
+        self
+    }
+
+    /// Sets where reducers' `console` output goes
+    pub fn set_logging(&mut self, logging: Logging) {
+        self.logging = logging;
+        unsafe { (*self.console).enabled = logging != Logging::Disabled }
+    }
+
+    /// Returns the reducer with changed destination of reducers' `console` output
+    pub fn with_logging(mut self, logging: Logging) -> Self {
+        self.set_logging(logging);
+        self
+    }
+
+    /// Returns where reducers' `console` output goes
+    pub fn logging(&self) -> Logging {
+        self.logging
+    }
+
+    /// Takes collected `console` output (if logging is set to [`Logging::Collect`])
+    ///
+    /// [`Logging::Collect`]: enum.Logging.html#variant.Collect
+    pub fn take_logs(&mut self) -> Vec<LogEntry> {
+        ::std::mem::replace(&mut self.logs, vec![])
+    }
+
+    fn flush_console(&mut self, reducer: usize, hash: &str) {
+        let messages = unsafe { ::std::mem::replace(&mut (*self.console).messages, vec![]) };
+        for (level, message) in messages {
+            let entry = LogEntry { level, file: self.filenames[reducer].clone(), hash: hash.into(), message };
+            match self.logging {
+                Logging::Stderr => eprintln!("{}: {} ({}): {}", entry.level, entry.file.to_string_lossy(), entry.hash, entry.message),
+                Logging::Collect => self.logs.push(entry),
+                Logging::Disabled => (),
+            }
+        }
+    }
+
+    /// Enables or disables tracing of every reducer function call
+    ///
+    /// See [`take_trace`](#method.take_trace).
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.trace = if tracing { Some(vec![]) } else { None };
+    }
+
+    /// Takes trace entries collected since the last call (if tracing is enabled)
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        self.trace.as_mut().map(|trace| ::std::mem::replace(trace, vec![])).unwrap_or_default()
+    }
+
+    fn push_trace(&mut self, reducer: usize, hash: &str, types: &[String], input: Option<JsonValue>,
+                  output: Result<JsonValue, String>) {
+        if let (Some(trace), Some(input)) = (self.trace.as_mut(), input) {
+            let (output, error) = match output {
+                Ok(output) => (Some(output), None),
+                Err(error) => (None, Some(error)),
+            };
+            trace.push(TraceEntry {
+                file: self.filenames[reducer].clone(),
+                hash: hash.into(),
+                types: types.to_vec(),
+                input, output, error,
+            });
+        }
+    }
+
+    /// Returns the reducer with limits set from client configuration
+    pub fn with_limits(self, limits: &cfg::Reducers) -> Self {
+        self.with_timeout(limits.timeout()).with_heap_limit(limits.heap_limit)
+    }
+
+    unsafe fn load_source(file: PathBuf, context: *mut duktape::duk_context) -> Result<(Vec<u8>, Option<Vec<String>>), Error> {
         let mut func = vec![];
         // source code
         let mut source = String::new();
@@ -316,6 +681,7 @@ impl<R: Record> DuktapeReducer<R> {
         duktape::DUK_COMPILE_SAFE |
         duktape::DUK_COMPILE_FUNCTION | duktape::DUK_COMPILE_STRLEN);
 
+        let types;
         if res as u32 == duktape::DUK_EXEC_ERROR {
             let err = ::std::ffi::CStr::from_ptr(duktape::duk_safe_to_lstring(context, -1, ptr::null_mut())).to_str().unwrap();
             return Err(Error::CompileError { file, error: err.into() })
@@ -337,7 +703,7 @@ impl<R: Record> DuktapeReducer<R> {
             ptr::copy_nonoverlapping(data, func.as_mut_ptr() as *mut _, sz);
             duktape::duk_pop(context);
             // load module
-            DuktapeReducer::<R>::load_module(context)?;
+            types = DuktapeReducer::<R>::load_module(context)?;
             // If module.export is not function, bail
             if duktape::duk_is_function(context, -1) != 1 {
                 return Err(Error::CompileError {
@@ -351,7 +717,7 @@ impl<R: Record> DuktapeReducer<R> {
         duktape::duk_push_object(context);
         duktape::duk_require_function(context, -2);
         duktape::duk_require_object(context, -1);
-        Ok(func)
+        Ok((func, types))
     }
 
     /// Resets every reducer's state back to an empty object
@@ -374,8 +740,18 @@ impl<R: Record> DuktapeReducer<R> {
 
 impl<R: Record> Clone for DuktapeReducer<R> {
     fn clone(&self) -> Self {
+        let mut limits = Limits::default();
+        unsafe {
+            limits.set_timeout((*self.limits).timeout());
+            limits.set_heap_limit((*self.limits).heap_limit());
+        }
+        let limits = Box::into_raw(Box::new(limits));
+        let console = Box::into_raw(Box::new(Console { enabled: self.logging != Logging::Disabled, messages: vec![] }));
         let context = unsafe {
-            duktape::duk_create_heap(None, None, None,ptr::null_mut(), Some(fatal_handler))
+            let context = limits::create_heap(limits, Some(fatal_handler));
+            DuktapeReducer::<R>::init_console(context, console);
+            duktape::stdlib::init(context);
+            context
         };
 
         unsafe {
@@ -401,7 +777,14 @@ impl<R: Record> Clone for DuktapeReducer<R> {
             reducers: self.reducers,
             filenames: self.filenames.clone(),
             functions: self.functions.clone(),
+            types: self.types.clone(),
             phantom_data: PhantomData,
+            decryptor: self.decryptor.clone(),
+            limits,
+            console,
+            logging: self.logging,
+            logs: vec![],
+            trace: self.trace.as_ref().map(|_| vec![]),
         }
     }
 }
@@ -414,6 +797,15 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
     fn reduce(&mut self, mut state: Self::State, item: &Self::Item) -> Self::State {
         use serde_json;
 
+        let types: Vec<String> = item.file_iter().map(|(name, _)| name.as_ref().to_string())
+            .filter(|name| name.starts_with(".type/"))
+            .map(|name| name[".type/".len()..].to_string())
+            .collect();
+        // don't bother marshalling the record if no reducer handles its types
+        if !(0..self.reducers as usize).any(|i| self.handles(i, &types)) {
+            return state;
+        }
+
         let json = serde_json::to_string(&JsonValue::Object(state.clone())).unwrap();
         unsafe {
             let ctx = self.context;
@@ -435,39 +827,54 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
             duktape::duk_push_object(ctx);
             #[cfg(feature = "duktape-mmap")]
             let mut mmaps = vec![];
+            let encrypted = self.decryptor.is_some() && item.file(encryption::ENCRYPTED_FILE).is_some();
             for (name, mut reader) in item.file_iter() {
                 let filename = CString::new(name.as_ref()).unwrap();
-                #[cfg(feature = "duktape-mmap")] {
-                    // avoid unused warning
-                    let _ = reader;
-                    #[cfg(windows)] // replace slashes with backslashes
-                    let name = name.as_ref().replace("/", "\\");
-                    #[cfg(not(windows))]
-                    let name = name.as_ref();
-
-                    let path = item.path().join(name);
-
-                    if fs::metadata(&path).unwrap().len() == 0 {
-                        // if the file is empty, it can't be mmapped
-                        // (also, no reason to do so anyway)
-                        duktape::duk_push_buffer_raw(ctx, 0, duktape::DUK_BUF_MODE_FIXED);
-                    } else {
-                        let file = fs::File::open(&path).unwrap();
-                        let mmap = memmap::MmapOptions::new().map(&file).unwrap();
-                        duktape::duk_push_buffer_raw(ctx, 0, duktape::DUK_BUF_FLAG_DYNAMIC | duktape::DUK_BUF_FLAG_EXTERNAL);
-                        mmaps.push(mmap);
-                        let mmap_ref = &mmaps[mmaps.len() - 1];
-                        duktape::duk_config_buffer(ctx, -1, mmap_ref.as_ptr() as *mut _, mmap_ref.len());
+                let plaintext = match self.decryptor {
+                    Some(ref decryptor) if encrypted && encryption::is_confidential(name.as_ref()) => {
+                        let mut buf = vec![];
+                        item.file(name.as_ref()).and_then(|mut f| f.read_to_end(&mut buf).ok())
+                            .and_then(|_| decryptor.decrypt(&buf))
+                    },
+                    _ => None,
+                };
+                if let Some(plaintext) = plaintext {
+                    let sz = plaintext.len();
+                    let ptr = duktape::duk_push_buffer_raw(ctx, sz, 0);
+                    ptr::copy_nonoverlapping(plaintext.as_ptr(), ptr.offset(0) as *mut _, sz);
+                } else {
+                    #[cfg(feature = "duktape-mmap")] {
+                        // avoid unused warning
+                        let _ = reader;
+                        #[cfg(windows)] // replace slashes with backslashes
+                        let name = name.as_ref().replace("/", "\\");
+                        #[cfg(not(windows))]
+                        let name = name.as_ref();
+
+                        let path = item.path().join(name);
+
+                        if fs::metadata(&path).unwrap().len() == 0 {
+                            // if the file is empty, it can't be mmapped
+                            // (also, no reason to do so anyway)
+                            duktape::duk_push_buffer_raw(ctx, 0, duktape::DUK_BUF_MODE_FIXED);
+                        } else {
+                            let file = fs::File::open(&path).unwrap();
+                            let mmap = memmap::MmapOptions::new().map(&file).unwrap();
+                            duktape::duk_push_buffer_raw(ctx, 0, duktape::DUK_BUF_FLAG_DYNAMIC | duktape::DUK_BUF_FLAG_EXTERNAL);
+                            mmaps.push(mmap);
+                            let mmap_ref = &mmaps[mmaps.len() - 1];
+                            duktape::duk_config_buffer(ctx, -1, mmap_ref.as_ptr() as *mut _, mmap_ref.len());
+                        }
+                    }
+                    #[cfg(not(feature = "duktape-mmap"))] {
+                        use std::io::Read;
+                        // INEFFICIENT BUT WORKS FOR NOW {
+                        let mut buf = vec![];
+                        let sz = reader.read_to_end(&mut buf).unwrap();
+                        let ptr = duktape::duk_push_buffer_raw(ctx,sz, 0);
+                        ptr::copy_nonoverlapping(buf.as_ptr(), ptr.offset(0) as *mut _, sz);
+                        // }
                     }
-                }
-                #[cfg(not(feature = "duktape-mmap"))] {
-                    use std::io::Read;
-                    // INEFFICIENT BUT WORKS FOR NOW {
-                    let mut buf = vec![];
-                    let sz = reader.read_to_end(&mut buf).unwrap();
-                    let ptr = duktape::duk_push_buffer_raw(ctx,sz, 0);
-                    ptr::copy_nonoverlapping(buf.as_ptr(), ptr.offset(0) as *mut _, sz);
-                    // }
                 }
                 duktape::duk_put_prop_string(ctx, -2, filename.as_ptr());
             }
@@ -479,7 +886,13 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
             duktape::duk_push_string(ctx, json_cstring.as_ptr());
             duktape::duk_json_decode(ctx, -1);
 
+            let hash = item.encoded_hash();
+
             for i in 0..self.reducers {
+                if !self.handles(i as usize, &types) {
+                    continue;
+                }
+                let input = if self.trace.is_some() { Some(top_state(ctx)) } else { None };
                 // function
                 duktape::duk_require_function(ctx, i * 2);
                 duktape::duk_dup(ctx, i * 2);
@@ -496,11 +909,15 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
                 duktape::duk_require_object(ctx, -1);
 
                 // execute
+                (*self.limits).enter();
                 let res = duktape::duk_pcall_method(ctx,2);
+                (*self.limits).leave();
+                self.flush_console(i as usize, hash.as_ref());
 
                // now, check for error
                 if res as u32 == duktape::DUK_EXEC_ERROR {
                     let err = ::std::ffi::CStr::from_ptr(duktape::duk_safe_to_lstring(ctx, -1, ptr::null_mut()));
+                    self.push_trace(i as usize, hash.as_ref(), &types, input, Err(err.to_string_lossy().into_owned()));
                     {
                         let mut arr = state.entry(String::from("errors")).or_insert(JsonValue::Array(vec![]));
                         let mut error = Map::new();
@@ -508,6 +925,8 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
                         error.insert("error".into(), JsonValue::String(err.to_str().unwrap().into()));
                         arr.as_array_mut().unwrap().push(JsonValue::Object(error));
                     }
+                    // leave only reducer functions and their states on the stack
+                    duktape::duk_set_top(ctx, self.reducers * 2);
                     return state;
                 }
 
@@ -521,6 +940,7 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
                     duktape::duk_copy(ctx, -2, -1);
                 } else {
                     let err = format!("TypeError: invalid return value {}, expected an object", ::std::ffi::CStr::from_ptr(duktape::duk_safe_to_lstring(ctx, -1, ptr::null_mut())).to_string_lossy());
+                    self.push_trace(i as usize, hash.as_ref(), &types, input, Err(err.clone()));
                     {
                         let mut arr = state.entry(String::from("errors")).or_insert(JsonValue::Array(vec![]));
                         let mut error = Map::new();
@@ -528,9 +948,15 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
                         error.insert("error".into(), JsonValue::String(err));
                         arr.as_array_mut().unwrap().push(JsonValue::Object(error));
                     }
+                    // leave only reducer functions and their states on the stack
+                    duktape::duk_set_top(ctx, self.reducers * 2);
                     return state;
                 }
 
+                if self.trace.is_some() {
+                    let output = top_state(ctx);
+                    self.push_trace(i as usize, hash.as_ref(), &types, input, Ok(output));
+                }
             }
 
             // remove item
@@ -541,14 +967,7 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
             duktape::duk_json_encode(ctx, -1);
             let json = duktape::duk_get_string(ctx, -1);
 
-            let json = ::std::ffi::CStr::from_ptr(json);
-            #[cfg(feature = "cesu8")]
-            let map: Map<String, JsonValue> = match cesu8::from_cesu8(json.to_bytes()) {
-                Ok(s) => serde_json::from_str(&s),
-                Err(_) => serde_json::from_slice(json.to_bytes()),
-            }.unwrap();
-            #[cfg(not(feature = "cesu8"))]
-            let map: Map<String, JsonValue> = serde_json::from_slice(json.to_bytes()).unwrap();
+            let map = decode_json(::std::ffi::CStr::from_ptr(json));
 
             // drop the json
             duktape::duk_pop(ctx);
@@ -558,6 +977,94 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
     }
 }
 
+impl<R: Record + HasPath> StatefulReducer for DuktapeReducer<R> {
+    /// Returns every reducer's state (`this`)
+    fn private_state(&self) -> Option<JsonValue> {
+        let mut states = vec![];
+        for i in 0..self.reducers {
+            unsafe {
+                duktape::duk_dup(self.context, i * 2 + 1);
+                duktape::duk_json_encode(self.context, -1);
+                states.push(JsonValue::Object(decode_json(CStr::from_ptr(duktape::duk_get_string(self.context, -1)))));
+                duktape::duk_pop(self.context);
+            }
+        }
+        Some(JsonValue::Array(states))
+    }
+
+    fn set_private_state(&mut self, state: JsonValue) -> bool {
+        use serde_json;
+        let states = match state {
+            JsonValue::Array(ref states) if states.len() == self.reducers as usize &&
+                                            states.iter().all(JsonValue::is_object) => states,
+            _ => return false,
+        };
+        for (i, state) in states.iter().enumerate() {
+            let json = CString::new(serde_json::to_string(state).unwrap()).unwrap();
+            unsafe {
+                duktape::duk_push_string(self.context, json.as_ptr());
+                duktape::duk_json_decode(self.context, -1);
+                duktape::duk_replace(self.context, (i * 2 + 1) as i32);
+            }
+        }
+        true
+    }
+}
+
+/// Reducer that writes out a trace of [`DuktapeReducer`]'s execution
+///
+/// For every record and every reducer file, input state, record's hash and types
+/// and output state (or an error) are written.
+///
+/// [`DuktapeReducer`]: struct.DuktapeReducer.html
+pub struct TracingReducer<R: Record, W: io::Write> {
+    reducer: DuktapeReducer<R>,
+    output: W,
+}
+
+impl<R: Record, W: io::Write> TracingReducer<R, W> {
+    pub fn new(mut reducer: DuktapeReducer<R>, output: W) -> Self {
+        reducer.set_tracing(true);
+        TracingReducer { reducer, output }
+    }
+
+    /// Returns the traced reducer
+    pub fn into_inner(mut self) -> DuktapeReducer<R> {
+        self.reducer.set_tracing(false);
+        self.reducer
+    }
+}
+
+impl<R: Record + HasPath, W: io::Write> Reducer for TracingReducer<R, W> {
+    type State = Map<String, JsonValue>;
+    type Item = R;
+
+    fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
+        let state = self.reducer.reduce(state, item);
+        for entry in self.reducer.take_trace() {
+            let _ = writeln!(self.output, "record {} [{}]", entry.hash, entry.types.join(", "));
+            let _ = writeln!(self.output, "  reducer {}", entry.file.to_string_lossy());
+            let _ = writeln!(self.output, "    input: {}", entry.input);
+            match (entry.output, entry.error) {
+                (Some(output), _) => { let _ = writeln!(self.output, "    output: {}", output); },
+                (_, Some(error)) => { let _ = writeln!(self.output, "    error: {}", error); },
+                _ => (),
+            }
+        }
+        state
+    }
+}
+
+impl<R: Record + HasPath, W: io::Write> StatefulReducer for TracingReducer<R, W> {
+    fn private_state(&self) -> Option<JsonValue> {
+        self.reducer.private_state()
+    }
+
+    fn set_private_state(&mut self, state: JsonValue) -> bool {
+        self.reducer.set_private_state(state)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use tempdir::TempDir;
@@ -566,27 +1073,36 @@ mod tests {
     use record::{RecordOwningContainer, RecordContainerReduction};
     use path::HasPath;
 
+    js_reducer_fixtures!(DuktapeReducer);
+
+
     #[test]
-    fn undefined_result() {
+    fn anonymous_function_error() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/2.js")).unwrap();
-        f.write(b"module.exports = function(state, record) {  }").unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/1.js")).unwrap();
-        f.write(b"module.exports = function(state, record) { return {test: true} }").unwrap();
-
-        let _record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
-
-        assert_eq!(state.get("test").unwrap(), &JsonValue::Bool(true));
+        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+        f.write(b"function(state) { return state }").unwrap();
+        let res = DuktapeReducer::<::repository::Record>::new(&repo);
+        assert!(res.is_err());
+        let reducer_file = repo.path().join("reducers/reducer.js");
+        let err = res.unwrap_err();
+        match err {
+            Error::CompileError { file, error } => {
+                assert_eq!(file, reducer_file);
+                assert_eq!(error, "SyntaxError: function name required (line 1)");
+            },
+            err => {
+                panic!("Wrong type of error {}", err);
+            }
+        }
     }
 
     #[test]
-    fn mistyped_result() {
+    fn invalid_syntax() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
@@ -594,27 +1110,55 @@ mod tests {
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = function(state, record) { return 1 }").unwrap();
+        f.write(b"module.exports = function(state) { return Object.assign{\"hello\": 1}, state); }").unwrap();
+        let res = DuktapeReducer::<::repository::Record>::new(&repo);
+        assert!(res.is_err());
+        let reducer_file = repo.path().join("reducers/reducer.js");
+        let err = res.unwrap_err();
+        match err {
+            Error::CompileError { file, error } => {
+                assert_eq!(file, reducer_file);
+                assert_eq!(error, "SyntaxError: unterminated statement (line 1)");
+            },
+            err => {
+                panic!("Wrong type of error {}", err);
+            }
+        }
+   }
+
+    #[test]
+    fn runtime_error() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        use std::io::Write;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers").join("reducer.js")).unwrap();
+        f.write(b"module.exports = function(state) { return Object.assign({\"hello\": record.a}, state); }").unwrap();
 
-        let _record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
         let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
 
         assert!(state.get("errors").is_some());
         let errors = state.get("errors").unwrap().as_array().unwrap();
-        assert_eq!(errors[0].as_object().unwrap().get("error").unwrap(), &JsonValue::String("TypeError: invalid return value 1, expected an object".into()));
+        assert_eq!(errors[0].as_object().unwrap().get("error").unwrap(), &JsonValue::String("ReferenceError: identifier \'record\' undefined".into()));
         assert_eq!(errors[0].as_object().unwrap().get("file").unwrap(), &JsonValue::String(repo.path().join("reducers").join("reducer.js").to_str().unwrap().into()));
     }
 
 
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn record_hash() {
+    fn require() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::create_dir_all(repo.path().join("reducers").join("reducer")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+        f.write(b"module.exports = require(\"reducer/index.js\");").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/reducer/index.js")).unwrap();
         f.write(b"module.exports = function(state, record) { return {\"hello\": record.hash}; }").unwrap();
 
         let record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
@@ -623,44 +1167,48 @@ mod tests {
         assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
     }
 
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn record_contents() {
+    fn require_path_modification() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::create_dir_all(repo.path().join("reducers").join("reducer")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = function(state, record) { return {\"hello\": new TextDecoder('utf-8').decode(record.files.text)}; }").unwrap();
+        f.write(b"Duktape.path = Duktape.path + '/reducer'; module.exports = require(\"index.js\");").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/reducer/index.js")).unwrap();
+        f.write(b"module.exports = function(state, record) { return {\"hello\": record.hash}; }").unwrap();
 
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        let err_str = "Error: module not found: \"index.js\"";
 
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::String("Title".into()));
+        assert_matches!(DuktapeReducer::<::repository::Record>::new(&repo),
+        Err(Error::ExecutionError { ref error }) if error == err_str);
     }
 
+
+    #[cfg(feature = "duktape-require")]
     #[test]
-    #[cfg(feature = "deprecated-item-api")]
-    fn record_contents_item() {
+    fn require_not_found() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::create_dir_all(repo.path().join("reducers").join("reducer")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = function(state, record) { return {\"hello\": new TextDecoder('utf-8').decode(record.files.text)}; }").unwrap();
-        let item = repo.new_item().unwrap();
-        item.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        let state = item.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        f.write(b"module.exports = require(\"reducer/index.js\");").unwrap();
 
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::String("Title".into()));
+        let err_str = "Error: module not found: \"reducer/index.js\"";
+        assert_matches!(DuktapeReducer::<::repository::Record>::new(&repo),
+        Err(Error::ExecutionError { ref error }) if error == err_str);
     }
 
 
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn reducer_state() {
+    fn require_external_relative() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
@@ -668,159 +1216,129 @@ mod tests {
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = function() {\
-         if (this.counter == undefined) { \
-           this.counter = 1;   \
-         } else { \
-           this.counter++;
-         } \
-         return {\"hello\": this.counter}; \
-         }").unwrap();
-
-        // create three records
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        f.write(b"module.exports = require(\"../reducer.js\");").unwrap();
+
+        let mut f = fs::File::create(repo.path().join("reducer.js")).unwrap();
+        f.write(b"module.exports = function() {};").unwrap();
+
+        let err_str = "TypeError: cannot resolve module id: ../reducer.js";
 
-        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
 
-        use serde_json::Number;
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(3)));
+        assert_matches!(DuktapeReducer::<::repository::Record>::new(&repo),
+        Err(Error::ExecutionError { ref error }) if error == err_str);
     }
 
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn multiple_reducers() {
+    fn require_external_absolute() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
-        f.write(b"module.exports = function(state) { return Object.assign({\"hello\": 1}, state); }").unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer2.js")).unwrap();
-        f.write(b"module.exports = function(state) { return Object.assign({\"bye\": 2}, state); }").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+        f.write(b"module.exports = require(\"/reducer.js\");").unwrap();
 
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        let err_str = "TypeError: cannot resolve module id: /reducer.js";
 
-        use serde_json::Number;
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
-        assert_eq!(state.get("bye").unwrap(), &JsonValue::Number(Number::from(2)));
+        assert_matches!(DuktapeReducer::<::repository::Record>::new(&repo),
+        Err(Error::ExecutionError { ref error }) if error == err_str);
     }
 
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn module_reducers() {
+    fn require_in_module() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
-        fs::create_dir_all(repo.modules_path().join("test").join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
-        f.write(b"module.exports = function(state) { return Object.assign({\"hello\": 1}, state); }").unwrap();
-        let mut f = fs::File::create(repo.path().join("modules/test/reducers/reducer2.js")).unwrap();
-        f.write(b"module.exports = function(state) { return Object.assign({\"bye\": 2}, state); }").unwrap();
+        fs::create_dir_all(repo.modules_path().join("test").join("reducers").join("reducer")).unwrap();
+        let mut f = fs::File::create(repo.modules_path().join("test").join("reducers").join("reducer.js")).unwrap();
+        f.write(b"module.exports = require(\"reducer/index.js\");").unwrap();
+        let mut f = fs::File::create(repo.modules_path().join("test").join("reducers").join("reducer").join("index.js")).unwrap();
+        f.write(b"module.exports = function(state, record) { return {\"hello\": record.hash}; }").unwrap();
 
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
         let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
 
-        use serde_json::Number;
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
-        assert_eq!(state.get("bye").unwrap(), &JsonValue::Number(Number::from(2)));
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
     }
 
-
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn module_export_non_function_error() {
+    fn cross_module_require() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = 'hello'").unwrap();
-        let res: Result<DuktapeReducer<::repository::Record>, _> = DuktapeReducer::new(&repo);
-        assert!(res.is_err());
-        let reducer_file = repo.path().join("reducers/reducer.js");
-        let err = res.unwrap_err();
-        match err {
-            Error::CompileError { file, error } => {
-                assert_eq!(file, reducer_file);
-                assert_eq!(error, "module.exports should export a function");
-            },
-            err => {
-                panic!("Wrong type of error {}", err);
-            }
-        }
+        fs::create_dir_all(repo.modules_path().join("test").join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.modules_path().join("test").join("reducers").join("reducer.js")).unwrap();
+        f.write(b"module.exports = require(\"reducer/index.js\");").unwrap();
+        fs::create_dir_all(repo.modules_path().join("test1").join("reducers").join("reducer")).unwrap();
+        let mut f = fs::File::create(repo.modules_path().join("test1").join("reducers").join("reducer").join("index.js")).unwrap();
+        f.write(b"module.exports = function(state, record) { return {\"hello\": record.hash}; }").unwrap();
+
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let result: Result<DuktapeReducer<::repository::Record>, _> = DuktapeReducer::new(&repo);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        let err_str = "Error: module not found: \"reducer/index.js\"";
+        assert_matches!(err, Error::ExecutionError { ref error } if error == err_str);
     }
 
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn module_export_props() {
+    fn require_directory_index() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
-        f.write(b"module.exports = function(state) { return Object.assign({\"hello\": module.exports.data}, state); }; module.exports.data = 1;").unwrap();
+        fs::create_dir_all(repo.path().join("reducers").join("lib")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+        f.write(b"module.exports = require(\"./lib\");").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/lib/index.js")).unwrap();
+        f.write(b"var hello = require(\"./hello\"); module.exports = function(state, record) { return hello(record); }").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/lib/hello.js")).unwrap();
+        f.write(b"module.exports = function(record) { return {\"hello\": record.hash}; }").unwrap();
 
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
         let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
 
-        use serde_json::Number;
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
     }
 
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn module_closure() {
+    fn require_node_modules() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
-        f.write(b"var a = 1; module.exports = function(state) { return Object.assign({\"hello\": a}, state); };").unwrap();
+        fs::create_dir_all(repo.path().join("reducers/node_modules/hello/src")).unwrap();
+        fs::create_dir_all(repo.path().join("reducers/node_modules/greeting")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+        f.write(b"module.exports = require(\"hello\");").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/node_modules/hello/package.json")).unwrap();
+        f.write(br#"{"name": "hello", "main": "src/main.js"}"#).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/node_modules/hello/src/main.js")).unwrap();
+        f.write(b"var greeting = require(\"greeting\"); module.exports = function(state, record) { return {\"hello\": greeting}; }").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/node_modules/greeting/index.js")).unwrap();
+        f.write(b"module.exports = \"hi\";").unwrap();
 
         repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
         let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
 
-        use serde_json::Number;
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
-    }
-
-
-    #[test]
-    fn anonymous_function_error() {
-        let mut tmp = TempDir::new("sit").unwrap().into_path();
-        tmp.push(".sit");
-        let repo = Repository::new(tmp).unwrap();
-        use std::fs;
-        use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"function(state) { return state }").unwrap();
-        let res = DuktapeReducer::<::repository::Record>::new(&repo);
-        assert!(res.is_err());
-        let reducer_file = repo.path().join("reducers/reducer.js");
-        let err = res.unwrap_err();
-        match err {
-            Error::CompileError { file, error } => {
-                assert_eq!(file, reducer_file);
-                assert_eq!(error, "SyntaxError: function name required (line 1)");
-            },
-            err => {
-                panic!("Wrong type of error {}", err);
-            }
-        }
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::String("hi".into()));
     }
 
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn invalid_syntax() {
+    fn require_json() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
@@ -828,129 +1346,64 @@ mod tests {
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = function(state) { return Object.assign{\"hello\": 1}, state); }").unwrap();
-        let res = DuktapeReducer::<::repository::Record>::new(&repo);
-        assert!(res.is_err());
-        let reducer_file = repo.path().join("reducers/reducer.js");
-        let err = res.unwrap_err();
-        match err {
-            Error::CompileError { file, error } => {
-                assert_eq!(file, reducer_file);
-                assert_eq!(error, "SyntaxError: unterminated statement (line 1)");
-            },
-            err => {
-                panic!("Wrong type of error {}", err);
-            }
-        }
-   }
-
-    #[test]
-    fn runtime_error() {
-        let mut tmp = TempDir::new("sit").unwrap().into_path();
-        tmp.push(".sit");
-        let repo = Repository::new(tmp).unwrap();
-        use std::fs;
-        use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers").join("reducer.js")).unwrap();
-        f.write(b"module.exports = function(state) { return Object.assign({\"hello\": record.a}, state); }").unwrap();
+        f.write(b"var data = require(\"./data.json\"); module.exports = function(state, record) { return {\"hello\": data.greeting}; }").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/data.json")).unwrap();
+        f.write(br#"{"greeting": "hi"}"#).unwrap();
 
         repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
         let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
 
-        assert!(state.get("errors").is_some());
-        let errors = state.get("errors").unwrap().as_array().unwrap();
-        assert_eq!(errors[0].as_object().unwrap().get("error").unwrap(), &JsonValue::String("ReferenceError: identifier \'record\' undefined".into()));
-        assert_eq!(errors[0].as_object().unwrap().get("file").unwrap(), &JsonValue::String(repo.path().join("reducers").join("reducer.js").to_str().unwrap().into()));
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::String("hi".into()));
     }
 
+    #[cfg(feature = "duktape-require")]
     #[test]
-    fn resetting_state() {
+    fn require_package_outside() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::create_dir_all(repo.path().join("reducers/lib")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = function() {\
-         if (this.counter == undefined) { \
-           this.counter = 1;   \
-         } else { \
-           this.counter++;
-         } \
-         return {\"hello\": this.counter}; \
-         }").unwrap();
-
-        // create three records
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-
-        let mut reducer = DuktapeReducer::new(&repo).unwrap();
-
-        use serde_json::Number;
-
-        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(3)));
-
-        // run it again without touching the state
-        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(6)));
-
-        // now, reset state
-        reducer.reset_state();
+        f.write(b"module.exports = require(\"lib\");").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/lib/package.json")).unwrap();
+        f.write(br#"{"main": "../../reducer.js"}"#).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducer.js")).unwrap();
+        f.write(b"module.exports = function() {};").unwrap();
 
-        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(3)));
+        let err_str = "Error: module not found: \"lib\"";
+        assert_matches!(DuktapeReducer::<::repository::Record>::new(&repo),
+        Err(Error::ExecutionError { ref error }) if error == err_str);
     }
 
     #[test]
-    fn cloned() {
+    fn module_types() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"\
-        var a = 1;\
-        module.exports = function() {\
-         if (this.counter == undefined) { \
-           this.counter = a;   \
-         } else { \
-           this.counter++;
-         } \
-         return {\"hello\": this.counter}; \
-         };").unwrap();
-
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-
-        let reducer = DuktapeReducer::new(&repo).unwrap();
-        let mut reducer1 = reducer.clone();
-        let mut reducer2 = reducer.clone();
-
-        let state1 = repo.reduce_with_reducer(&mut reducer1).unwrap();
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title 1"[..])].into_iter(), true).unwrap();
-        let state2 = repo.reduce_with_reducer(&mut reducer2).unwrap();
-
-        use serde_json::Number;
-        assert_eq!(state1.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
-        assert_eq!(state2.get("hello").unwrap(), &JsonValue::Number(Number::from(2)));
+        let mut f = fs::File::create(repo.path().join("reducers/1.js")).unwrap();
+        f.write(b"module.types = ['Closed', 'Reopened']; module.exports = function(state, record) { return Object.assign(state, {closed: (state.closed || 0) + 1}) }").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/2.js")).unwrap();
+        f.write(b"module.types = ['Commented']; module.exports = function(state, record) { return Object.assign(state, {commented: (state.commented || 0) + 1}) }").unwrap();
 
-        // Now, make sure state gets copied from where it is, and not the original value:
-        let mut reducer3 = reducer2.clone();
-        let state3 = repo.reduce_with_reducer(&mut reducer3).unwrap();
-        assert_eq!(state3.get("hello").unwrap(), &JsonValue::Number(Number::from(4)));
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![(".type/Closed", &b""[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![(".type/Reopened", &b""[..]), (".type/Commented", &b""[..])].into_iter(), true).unwrap();
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        reducer.set_tracing(true);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert_eq!(state.get("closed").unwrap(), &JsonValue::Number(2.into()));
+        assert_eq!(state.get("commented").unwrap(), &JsonValue::Number(1.into()));
+        // SummaryChanged record was not given to any reducer
+        assert_eq!(reducer.take_trace().len(), 3);
     }
 
-
-    // Duktape uses CESU-8 internally, which is not the standard UTF-8
-    // encoding. Make sure we convert whatever is produced by Duktape.
-    #[cfg(feature = "cesu8")]
     #[test]
-    fn cesu8_output() {
+    fn invalid_module_types() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
@@ -958,121 +1411,56 @@ mod tests {
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = function(state, record) { \
-            return Object.assign(state, {hello: new TextDecoder('utf-8').decode(record.files.text)});
-        }").unwrap();
-
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &"🙂😵😾🤔".as_bytes()[..])].into_iter(), true).unwrap();
-        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
-
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::String("🙂😵😾🤔".into()));
+        f.write(b"module.types = 'Closed'; module.exports = function(state, record) { return state }").unwrap();
+        let res = DuktapeReducer::<::repository::Record>::new(&repo);
+        assert_matches!(res, Err(Error::ExecutionError { ref error }) if error == "module.types should be an array of strings");
     }
 
-    #[cfg(feature = "cesu8")]
     #[test]
-    fn cesu8_input() {
+    fn timeout() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
-
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = function(state, record) {
-          if (typeof record.files['.type/DetailsChanged'] !== 'undefined') {
-              var decoder = new TextDecoder('utf-8');
-              return Object.assign(state, {
-                  details: decoder.decode(record.files.text).trim()
-              });
-          } else {
-              return state;
-          }
-        }").unwrap();
-
-        let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
-        f.write(b"module.exports = function(state, record) {
-          if (typeof record.files['.type/Commented'] !== 'undefined') {
-            var comments = this.comments || [];
-            var decoder = new TextDecoder('utf-8');
-            comments.push({
-               text: decoder.decode(record.files.text),
-            });
-            this.comments = comments;
-            return Object.assign(state, {comments: comments});
-          }
-          return state;
-        }").unwrap();
+        f.write(b"module.exports = function(state, record) { if (record.files.loop) { while(true) {} }; return {hash: record.hash} }").unwrap();
 
-        repo.new_record(vec![(".type/DetailsChanged", &b""[..]), ("text", &"🙂😵😾🤔   ".as_bytes()[..])].into_iter(), true).unwrap();
-        repo.new_record(vec![(".type/Commented", &b""[..]), ("text", &"test🙂😵".as_bytes()[..])].into_iter(), true).unwrap();
-
-        // SHOULD NOT FAIL
-        repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
-    }
-
-    #[cfg(feature = "duktape-require")]
-    #[test]
-    fn require() {
-        let mut tmp = TempDir::new("sit").unwrap().into_path();
-        tmp.push(".sit");
-        let repo = Repository::new(tmp).unwrap();
-        use std::fs;
-        use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers").join("reducer")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = require(\"reducer/index.js\");").unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer/index.js")).unwrap();
-        f.write(b"module.exports = function(state, record) { return {\"hello\": record.hash}; }").unwrap();
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("loop", &b""[..])].into_iter(), true).unwrap();
+        let mut reducer = DuktapeReducer::new(&repo).unwrap().with_timeout(Some(Duration::from_millis(100)));
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
 
-        let record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        let errors = state.get("errors").unwrap().as_array().unwrap();
+        assert_eq!(errors[0].as_object().unwrap().get("error").unwrap(), &JsonValue::String("RangeError: execution timeout".into()));
 
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
+        // the reducer is still usable afterwards
+        let record = repo.new_record(vec![(".type/SummaryChanged", &b""[..])].into_iter(), true).unwrap();
+        let state = reducer.reduce(Default::default(), &record);
+        assert_eq!(state.get("hash").unwrap(), &JsonValue::String(record.encoded_hash()));
     }
 
-    #[cfg(feature = "duktape-require")]
     #[test]
-    fn require_path_modification() {
+    fn heap_limit() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers").join("reducer")).unwrap();
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"Duktape.path = Duktape.path + '/reducer'; module.exports = require(\"index.js\");").unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer/index.js")).unwrap();
-        f.write(b"module.exports = function(state, record) { return {\"hello\": record.hash}; }").unwrap();
-
-        let err_str = "Error: module not found: \"index.js\"";
-
-        assert_matches!(DuktapeReducer::<::repository::Record>::new(&repo),
-        Err(Error::ExecutionError { ref error }) if error == err_str);
-    }
-
+        f.write(b"module.exports = function(state, record) { var a = []; while(true) { a.push(new Array(1024).join('x') + a.length) } }").unwrap();
 
-    #[cfg(feature = "duktape-require")]
-    #[test]
-    fn require_not_found() {
-        let mut tmp = TempDir::new("sit").unwrap().into_path();
-        tmp.push(".sit");
-        let repo = Repository::new(tmp).unwrap();
-        use std::fs;
-        use std::io::Write;
-        fs::create_dir_all(repo.path().join("reducers").join("reducer")).unwrap();
-        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = require(\"reducer/index.js\");").unwrap();
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..])].into_iter(), true).unwrap();
+        let mut reducer = DuktapeReducer::new(&repo).unwrap().with_heap_limit(Some(4 * 1024 * 1024));
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
 
-        let err_str = "Error: module not found: \"reducer/index.js\"";
-        assert_matches!(DuktapeReducer::<::repository::Record>::new(&repo),
-        Err(Error::ExecutionError { ref error }) if error == err_str);
+        let errors = state.get("errors").unwrap().as_array().unwrap();
+        assert_eq!(errors[0].as_object().unwrap().get("error").unwrap(), &JsonValue::String("Error: alloc failed".into()));
     }
 
-
-    #[cfg(feature = "duktape-require")]
     #[test]
-    fn require_external_relative() {
+    fn console() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
@@ -1080,21 +1468,30 @@ mod tests {
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = require(\"../reducer.js\");").unwrap();
+        f.write(b"module.exports = function(state, record) { console.log('hash', record.hash, {a: 1}); console.warn(1); console.error('e'); return {} }").unwrap();
 
-        let mut f = fs::File::create(repo.path().join("reducer.js")).unwrap();
-        f.write(b"module.exports = function() {};").unwrap();
+        let record = repo.new_record(vec![(".type/SummaryChanged", &b""[..])].into_iter(), true).unwrap();
 
-        let err_str = "TypeError: cannot resolve module id: ../reducer.js";
-
-
-        assert_matches!(DuktapeReducer::<::repository::Record>::new(&repo),
-        Err(Error::ExecutionError { ref error }) if error == err_str);
+        // disabled by default
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert!(state.get("errors").is_none());
+        assert!(reducer.take_logs().is_empty());
+
+        let mut reducer = DuktapeReducer::new(&repo).unwrap().with_logging(Logging::Collect);
+        repo.reduce_with_reducer(&mut reducer).unwrap();
+        let logs = reducer.take_logs();
+        let file = repo.path().join("reducers/reducer.js");
+        let entry = |level, message: String| LogEntry { level, file: file.clone(), hash: record.encoded_hash(), message };
+        assert_eq!(logs, vec![entry(LogLevel::Log, format!("hash {} {{\"a\":1}}", record.encoded_hash())),
+                              entry(LogLevel::Warn, "1".into()),
+                              entry(LogLevel::Error, "e".into())]);
+        assert!(reducer.take_logs().is_empty());
     }
 
-    #[cfg(feature = "duktape-require")]
     #[test]
-    fn require_external_absolute() {
+    fn stdlib() {
+        use serde_json;
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
@@ -1102,55 +1499,87 @@ mod tests {
         use std::io::Write;
         fs::create_dir_all(repo.path().join("reducers")).unwrap();
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
-        f.write(b"module.exports = require(\"/reducer.js\");").unwrap();
-
-        let err_str = "TypeError: cannot resolve module id: /reducer.js";
-
-        assert_matches!(DuktapeReducer::<::repository::Record>::new(&repo),
-        Err(Error::ExecutionError { ref error }) if error == err_str);
+        f.write(b"module.exports = function(state, record) { return {\
+                  text: sit.decodeText(record.files.text), \
+                  data: sit.parseJSON(record.files['data.json']), \
+                  timestamp: new Date(sit.parseTimestamp(record.files['.timestamp'])).toISOString(), \
+                  invalid: sit.parseTimestamp('never'), \
+                  types: sit.types(record), \
+                  authors: sit.authors(record)} }").unwrap();
+
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), (".type/Comment", &b""[..]),
+                             ("text", "Zdravствуйте, 🌍".as_bytes()), ("data.json", &br#"{"a": [1]}"#[..]),
+                             (".timestamp", &b"2018-06-14T09:35:11.123456789Z"[..]),
+                             (".authors", &b"A <a@example.com>\n B <b@example.com>\n"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        let mut types = state.get("types").unwrap().as_array().unwrap().clone();
+        types.sort_by_key(|t| t.as_str().unwrap().to_owned());
+        assert_eq!(JsonValue::Array(types), serde_json::from_str::<JsonValue>(r#"["Comment", "SummaryChanged"]"#).unwrap());
+        assert_eq!(state.get("text").unwrap(), &JsonValue::String("Zdravствуйте, 🌍".into()));
+        assert_eq!(state.get("data").unwrap(), &serde_json::from_str::<JsonValue>(r#"{"a": [1]}"#).unwrap());
+        assert_eq!(state.get("timestamp").unwrap(), &JsonValue::String("2018-06-14T09:35:11.123Z".into()));
+        assert_eq!(state.get("invalid").unwrap(), &JsonValue::Null);
+        assert_eq!(state.get("authors").unwrap(), &serde_json::from_str::<JsonValue>(r#"["A <a@example.com>", "B <b@example.com>"]"#).unwrap());
     }
 
     #[cfg(feature = "duktape-require")]
     #[test]
-    fn require_in_module() {
+    fn require_stdlib() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.modules_path().join("test").join("reducers").join("reducer")).unwrap();
-        let mut f = fs::File::create(repo.modules_path().join("test").join("reducers").join("reducer.js")).unwrap();
-        f.write(b"module.exports = require(\"reducer/index.js\");").unwrap();
-        let mut f = fs::File::create(repo.modules_path().join("test").join("reducers").join("reducer").join("index.js")).unwrap();
-        f.write(b"module.exports = function(state, record) { return {\"hello\": record.hash}; }").unwrap();
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+        f.write(b"var sit = require('sit'); module.exports = function(state, record) { return {text: sit.decodeText(record.files.text)} }").unwrap();
 
-        let record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
         let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
-
-        assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
+        assert_eq!(state.get("text").unwrap(), &JsonValue::String("Title".into()));
     }
 
-    #[cfg(feature = "duktape-require")]
     #[test]
-    fn cross_module_require() {
+    fn trace() {
+        use serde_json;
         let mut tmp = TempDir::new("sit").unwrap().into_path();
         tmp.push(".sit");
         let repo = Repository::new(tmp).unwrap();
         use std::fs;
         use std::io::Write;
-        fs::create_dir_all(repo.modules_path().join("test").join("reducers")).unwrap();
-        let mut f = fs::File::create(repo.modules_path().join("test").join("reducers").join("reducer.js")).unwrap();
-        f.write(b"module.exports = require(\"reducer/index.js\");").unwrap();
-        fs::create_dir_all(repo.modules_path().join("test1").join("reducers").join("reducer")).unwrap();
-        let mut f = fs::File::create(repo.modules_path().join("test1").join("reducers").join("reducer").join("index.js")).unwrap();
-        f.write(b"module.exports = function(state, record) { return {\"hello\": record.hash}; }").unwrap();
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/1.js")).unwrap();
+        f.write(b"module.exports = function(state, record) { return {count: (state.count || 0) + 1} }").unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/2.js")).unwrap();
+        f.write(b"module.exports = function(state, record) { throw new Error('failed') }").unwrap();
 
-        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
-        let result: Result<DuktapeReducer<::repository::Record>, _> = DuktapeReducer::new(&repo);
-        assert!(result.is_err());
-        let err = result.unwrap_err();
-        let err_str = "Error: module not found: \"reducer/index.js\"";
-        assert_matches!(err, Error::ExecutionError { ref error } if error == err_str);
+        let record = repo.new_record(vec![(".type/SummaryChanged", &b""[..])].into_iter(), true).unwrap();
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        reducer.set_tracing(true);
+        repo.reduce_with_reducer(&mut reducer).unwrap();
+        let trace = reducer.take_trace();
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace[0], TraceEntry {
+            file: repo.path().join("reducers/1.js"),
+            hash: record.encoded_hash(),
+            types: vec!["SummaryChanged".into()],
+            input: JsonValue::Object(Default::default()),
+            output: Some(serde_json::from_str(r#"{"count": 1}"#).unwrap()),
+            error: None,
+        });
+        assert_eq!(trace[1].input, serde_json::from_str::<JsonValue>(r#"{"count": 1}"#).unwrap());
+        assert_eq!(trace[1].output, None);
+        assert_eq!(trace[1].error, Some("Error: failed".into()));
+
+        let mut output = vec![];
+        {
+            let mut reducer = TracingReducer::new(DuktapeReducer::new(&repo).unwrap(), &mut output);
+            repo.reduce_with_reducer(&mut reducer).unwrap();
+        }
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(&format!("record {} [SummaryChanged]\n  reducer {}\n    input: {{}}\n    output: {{\"count\":1}}\n",
+                                            record.encoded_hash(), repo.path().join("reducers/1.js").to_string_lossy())));
+        assert!(output.ends_with("    error: Error: failed\n"));
     }
 
     #[cfg(feature = "duktape-require")]
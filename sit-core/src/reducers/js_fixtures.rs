@@ -0,0 +1,380 @@
+//! Fixtures shared by JavaScript reducer engines
+
+/// Defines tests every JavaScript reducer engine has to pass
+///
+/// Expects `tests` module's usual imports and engine's `Error`
+/// (with a `CompileError` variant) to be in scope.
+#[allow(unused_macros)]
+macro_rules! js_reducer_fixtures {
+    ($reducer: ident) => {
+        #[test]
+        fn undefined_result() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/2.js")).unwrap();
+            f.write(b"module.exports = function(state, record) {  }").unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/1.js")).unwrap();
+            f.write(b"module.exports = function(state, record) { return {test: true} }").unwrap();
+
+            let _record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            assert_eq!(state.get("test").unwrap(), &JsonValue::Bool(true));
+        }
+
+        #[test]
+        fn mistyped_result() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"module.exports = function(state, record) { return 1 }").unwrap();
+
+            let _record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            assert!(state.get("errors").is_some());
+            let errors = state.get("errors").unwrap().as_array().unwrap();
+            assert_eq!(errors[0].as_object().unwrap().get("error").unwrap(), &JsonValue::String("TypeError: invalid return value 1, expected an object".into()));
+            assert_eq!(errors[0].as_object().unwrap().get("file").unwrap(), &JsonValue::String(repo.path().join("reducers").join("reducer.js").to_str().unwrap().into()));
+        }
+
+        #[test]
+        fn record_hash() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"module.exports = function(state, record) { return {\"hello\": record.hash}; }").unwrap();
+
+            let record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
+        }
+
+        #[test]
+        fn record_contents() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"module.exports = function(state, record) { return {\"hello\": new TextDecoder('utf-8').decode(record.files.text)}; }").unwrap();
+
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::String("Title".into()));
+        }
+
+        #[test]
+        #[cfg(feature = "deprecated-item-api")]
+        fn record_contents_item() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"module.exports = function(state, record) { return {\"hello\": new TextDecoder('utf-8').decode(record.files.text)}; }").unwrap();
+            let item = repo.new_item().unwrap();
+            item.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            let state = item.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::String("Title".into()));
+        }
+
+        #[test]
+        fn reducer_state() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"module.exports = function() {\
+             if (this.counter == undefined) { \
+               this.counter = 1;   \
+             } else { \
+               this.counter++;
+             } \
+             return {\"hello\": this.counter}; \
+             }").unwrap();
+
+            // create three records
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            use serde_json::Number;
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(3)));
+        }
+
+        #[test]
+        fn multiple_reducers() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
+            f.write(b"module.exports = function(state) { return Object.assign({\"hello\": 1}, state); }").unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer2.js")).unwrap();
+            f.write(b"module.exports = function(state) { return Object.assign({\"bye\": 2}, state); }").unwrap();
+
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            use serde_json::Number;
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
+            assert_eq!(state.get("bye").unwrap(), &JsonValue::Number(Number::from(2)));
+        }
+
+        #[test]
+        fn module_reducers() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            fs::create_dir_all(repo.modules_path().join("test").join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
+            f.write(b"module.exports = function(state) { return Object.assign({\"hello\": 1}, state); }").unwrap();
+            let mut f = fs::File::create(repo.path().join("modules/test/reducers/reducer2.js")).unwrap();
+            f.write(b"module.exports = function(state) { return Object.assign({\"bye\": 2}, state); }").unwrap();
+
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            use serde_json::Number;
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
+            assert_eq!(state.get("bye").unwrap(), &JsonValue::Number(Number::from(2)));
+        }
+
+        #[test]
+        fn module_export_non_function_error() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"module.exports = 'hello'").unwrap();
+            let res: Result<$reducer<::repository::Record>, _> = $reducer::new(&repo);
+            assert!(res.is_err());
+            let reducer_file = repo.path().join("reducers/reducer.js");
+            let err = res.unwrap_err();
+            match err {
+                Error::CompileError { file, error } => {
+                    assert_eq!(file, reducer_file);
+                    assert_eq!(error, "module.exports should export a function");
+                },
+                err => {
+                    panic!("Wrong type of error {}", err);
+                }
+            }
+        }
+
+        #[test]
+        fn module_export_props() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
+            f.write(b"module.exports = function(state) { return Object.assign({\"hello\": module.exports.data}, state); }; module.exports.data = 1;").unwrap();
+
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            use serde_json::Number;
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
+        }
+
+        #[test]
+        fn module_closure() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
+            f.write(b"var a = 1; module.exports = function(state) { return Object.assign({\"hello\": a}, state); };").unwrap();
+
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            use serde_json::Number;
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
+        }
+
+        #[test]
+        fn resetting_state() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"module.exports = function() {\
+             if (this.counter == undefined) { \
+               this.counter = 1;   \
+             } else { \
+               this.counter++;
+             } \
+             return {\"hello\": this.counter}; \
+             }").unwrap();
+
+            // create three records
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+
+            let mut reducer = $reducer::new(&repo).unwrap();
+
+            use serde_json::Number;
+
+            let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(3)));
+
+            // run it again without touching the state
+            let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(6)));
+
+            // now, reset state
+            reducer.reset_state();
+
+            let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(3)));
+        }
+
+        #[test]
+        fn cloned() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"\
+            var a = 1;\
+            module.exports = function() {\
+             if (this.counter == undefined) { \
+               this.counter = a;   \
+             } else { \
+               this.counter++;
+             } \
+             return {\"hello\": this.counter}; \
+             };").unwrap();
+
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+
+            let reducer = $reducer::new(&repo).unwrap();
+            let mut reducer1 = reducer.clone();
+            let mut reducer2 = reducer.clone();
+
+            let state1 = repo.reduce_with_reducer(&mut reducer1).unwrap();
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title 1"[..])].into_iter(), true).unwrap();
+            let state2 = repo.reduce_with_reducer(&mut reducer2).unwrap();
+
+            use serde_json::Number;
+            assert_eq!(state1.get("hello").unwrap(), &JsonValue::Number(Number::from(1)));
+            assert_eq!(state2.get("hello").unwrap(), &JsonValue::Number(Number::from(2)));
+
+            // Now, make sure state gets copied from where it is, and not the original value:
+            let mut reducer3 = reducer2.clone();
+            let state3 = repo.reduce_with_reducer(&mut reducer3).unwrap();
+            assert_eq!(state3.get("hello").unwrap(), &JsonValue::Number(Number::from(4)));
+        }
+
+        // Duktape uses CESU-8 internally, which is not the standard UTF-8
+        // encoding. Make sure we convert whatever is produced by Duktape.
+        #[cfg(feature = "cesu8")]
+        #[test]
+        fn cesu8_output() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"module.exports = function(state, record) { \
+                return Object.assign(state, {hello: new TextDecoder('utf-8').decode(record.files.text)});
+            }").unwrap();
+
+            repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &"🙂😵😾🤔".as_bytes()[..])].into_iter(), true).unwrap();
+            let state = repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+
+            assert_eq!(state.get("hello").unwrap(), &JsonValue::String("🙂😵😾🤔".into()));
+        }
+
+        #[cfg(feature = "cesu8")]
+        #[test]
+        fn cesu8_input() {
+            let mut tmp = TempDir::new("sit").unwrap().into_path();
+            tmp.push(".sit");
+            let repo = Repository::new(tmp).unwrap();
+            use std::fs;
+            use std::io::Write;
+            fs::create_dir_all(repo.path().join("reducers")).unwrap();
+
+            let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+            f.write(b"module.exports = function(state, record) {
+              if (typeof record.files['.type/DetailsChanged'] !== 'undefined') {
+                  var decoder = new TextDecoder('utf-8');
+                  return Object.assign(state, {
+                      details: decoder.decode(record.files.text).trim()
+                  });
+              } else {
+                  return state;
+              }
+            }").unwrap();
+
+            let mut f = fs::File::create(repo.path().join("reducers/reducer1.js")).unwrap();
+            f.write(b"module.exports = function(state, record) {
+              if (typeof record.files['.type/Commented'] !== 'undefined') {
+                var comments = this.comments || [];
+                var decoder = new TextDecoder('utf-8');
+                comments.push({
+                   text: decoder.decode(record.files.text),
+                });
+                this.comments = comments;
+                return Object.assign(state, {comments: comments});
+              }
+              return state;
+            }").unwrap();
+
+            repo.new_record(vec![(".type/DetailsChanged", &b""[..]), ("text", &"🙂😵😾🤔   ".as_bytes()[..])].into_iter(), true).unwrap();
+            repo.new_record(vec![(".type/Commented", &b""[..]), ("text", &"test🙂😵".as_bytes()[..])].into_iter(), true).unwrap();
+
+            // SHOULD NOT FAIL
+            repo.reduce_with_reducer(&mut $reducer::new(&repo).unwrap()).unwrap();
+        }
+    };
+}
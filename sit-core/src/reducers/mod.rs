@@ -10,19 +10,120 @@ pub trait Reducer: Sized {
 
     /// Takes current state, item and returns new state
     fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State;
+    /// Returns true if the reducer takes sole responsibility for the item
+    ///
+    /// Reducers chained after it (see [`ChainedReducer`]) will skip such items.
+    /// By default, no items are claimed.
+    ///
+    /// [`ChainedReducer`]: struct.ChainedReducer.html
+    fn claims(&self, _item: &Self::Item) -> bool {
+        false
+    }
     /// Chains two reducers together sequentially
     fn chain<R: Reducer<State=Self::State, Item=Self::Item>>(self, other: R) -> ChainedReducer<Self, R> {
        ChainedReducer::new(self, other)
     }
 }
 
+/// Reducer with private state that can be saved and restored
+///
+/// Allows [checkpoints] to resume reduction without reducing
+/// already reduced records again.
+///
+/// [checkpoints]: ../checkpoint/index.html
+pub trait StatefulReducer: Reducer {
+    /// Returns a snapshot of reducer's private state (`None` if it can't be taken)
+    fn private_state(&self) -> Option<JsonValue>;
+    /// Restores a snapshot of reducer's private state
+    ///
+    /// Returns `false` if the snapshot doesn't fit the reducer.
+    fn set_private_state(&mut self, state: JsonValue) -> bool;
+}
+
+#[cfg(test)]
+#[macro_use]
+mod js_fixtures;
+
 #[cfg(feature = "duktape-reducers")]
 pub mod duktape;
+pub mod native;
+pub mod order;
+#[cfg(feature = "quickjs-reducers")]
+pub mod quickjs;
+#[cfg(feature = "wasm-reducers")]
+pub mod wasm;
+pub mod testing;
+
+use std::path::PathBuf;
+use path::HasPath;
+use serde_json::Value as JsonValue;
+
+/// Reducer source files
+///
+/// Implemented for collections of paths (files or directories) and for
+/// [`Repository`], in which case `reducers` directories of the repository
+/// and of its modules are listed.
+///
+/// [`Repository`]: ../repository/struct.Repository.html
+pub trait SourceFiles {
+    type Iter : Iterator<Item = PathBuf>;
+
+    fn source_files(self) -> Result<Self::Iter, ::repository::Error>;
+
+    /// Returns the directory of repository's own reducers (if any)
+    ///
+    /// Order manifests refer to these reducers as `/FILE` (see [`order`])
+    ///
+    /// [`order`]: order/index.html
+    fn repository_reducers(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+impl<T> SourceFiles for T where T: IntoIterator<Item = PathBuf> {
+    type Iter = T::IntoIter;
+
+    fn source_files(self) -> Result<Self::Iter, ::repository::Error> {
+        Ok(self.into_iter())
+    }
+}
+
+impl<'a, MI> SourceFiles for &'a ::Repository<MI> where MI: ::repository::ModuleIterator<PathBuf, ::repository::Error> {
+
+    type Iter = ::std::vec::IntoIter<PathBuf>;
+
+    fn source_files(self) -> Result<Self::Iter, ::repository::Error> {
+        let mut files = vec![];
+
+        let reducers_path = self.path().join("reducers");
+        if reducers_path.is_dir() {
+            files.push(reducers_path);
+        }
+
+        for module_name in self.module_iter()? {
+            let module_name = module_name?;
+            let path = self.modules_path().join(module_name).join("reducers");
+            if path.is_dir() {
+                files.push(path);
+            }
+        }
+
+        Ok(files.into_iter())
+    }
+
+    fn repository_reducers(&self) -> Option<PathBuf> {
+        Some(self.path().join("reducers"))
+    }
+}
 
 /// Chained reducer (consists of two reducers)
 ///
 /// Will apply first and then second reducer to a given state
-/// when used as a reducer itself
+/// when used as a reducer itself. Items claimed by the first
+/// reducer (see [`Reducer::claims`]) are not passed to the second one.
+///
+/// [`Reducer::claims`]: trait.Reducer.html#method.claims
+#[derive(Clone)]
 pub struct ChainedReducer<R1: Reducer, R2: Reducer>(R1, R2);
 
 impl<R1: Reducer, R2: Reducer> ChainedReducer<R1, R2> {
@@ -30,6 +131,16 @@ impl<R1: Reducer, R2: Reducer> ChainedReducer<R1, R2> {
     pub fn new(r1: R1, r2: R2) -> Self {
         ChainedReducer(r1, r2)
     }
+
+    /// Returns a mutable reference to the first reducer
+    pub fn first_mut(&mut self) -> &mut R1 {
+        &mut self.0
+    }
+
+    /// Returns a mutable reference to the second reducer
+    pub fn second_mut(&mut self) -> &mut R2 {
+        &mut self.1
+    }
 }
 
 
@@ -38,7 +149,33 @@ impl<T, I, R1: Reducer<State=T, Item=I>, R2: Reducer<State=T, Item=I>> Reducer f
     type Item = R1::Item;
 
     fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
-        self.1.reduce(self.0.reduce(state, item), item)
+        let state = self.0.reduce(state, item);
+        if self.0.claims(item) {
+            state
+        } else {
+            self.1.reduce(state, item)
+        }
+    }
+
+    fn claims(&self, item: &Self::Item) -> bool {
+        self.0.claims(item) || self.1.claims(item)
+    }
+}
+
+impl<T, I, R1: StatefulReducer<State=T, Item=I>, R2: StatefulReducer<State=T, Item=I>> StatefulReducer for ChainedReducer<R1, R2> {
+    fn private_state(&self) -> Option<JsonValue> {
+        Some(JsonValue::Array(vec![self.0.private_state()?, self.1.private_state()?]))
+    }
+
+    fn set_private_state(&mut self, state: JsonValue) -> bool {
+        match state {
+            JsonValue::Array(mut states) if states.len() == 2 => {
+                let second = states.pop().unwrap();
+                let first = states.pop().unwrap();
+                self.0.set_private_state(first) && self.1.set_private_state(second)
+            },
+            _ => false,
+        }
     }
 }
 
@@ -58,9 +195,35 @@ mod tests {
         }
     }
 
+    /// Claims odd items
+    struct Odd;
+
+    impl Reducer for Odd {
+        type State = i32;
+        type Item = i32;
+
+        fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
+            if item % 2 == 1 { state + 1 } else { state }
+        }
+
+        fn claims(&self, item: &Self::Item) -> bool {
+            item % 2 == 1
+        }
+    }
+
     #[test]
     fn chained_reducer() {
         assert_eq!(R(1).chain(R(2)).reduce(0, &0), 2);
     }
 
+    #[test]
+    fn chained_reducer_claims() {
+        let mut reducer = Odd.chain(R(10));
+        assert_eq!(reducer.reduce(0, &1), 1);
+        assert_eq!(reducer.reduce(0, &2), 10);
+        assert!(reducer.claims(&1));
+        assert!(!reducer.claims(&2));
+        assert!(R(0).chain(Odd).claims(&1));
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,285 @@
+//! Native implementations of the standard issue-tracking reducers
+//!
+//! These reducers handle the most common record types without going
+//! through a JavaScript engine:
+//!
+//! | Record type        | Effect on the state                                      |
+//! |--------------------|----------------------------------------------------------|
+//! | `SummaryChanged`   | `summary` is set to trimmed `text`                       |
+//! | `DetailsChanged`   | `details` is set to trimmed `text`                       |
+//! | `Commented`        | `{text, authors, timestamp, hash}` is appended to `comments` |
+//! | `Closed`           | `state` is set to `"closed"`                             |
+//! | `Reopened`         | `state` is set to `"open"`                               |
+//! | `Merged`           | `merged` is set to `true` and `state` to `"closed"`      |
+//!
+//! Every reducer can be used on its own and composed with others (as well as with
+//! JavaScript reducers) using [`ChainedReducer`]. Repositories opt into them through
+//! the `native_reducers` property of their configuration, listing types to handle
+//! natively (or `true` for all of them). Native reducers claim records of the types
+//! they handle (see [`Reducer::claims`]), so reducers chained after them (such as
+//! JavaScript ones) don't process these records again.
+//!
+//! [`Reducer::claims`]: ../trait.Reducer.html#method.claims
+//!
+//! [`ChainedReducer`]: ../struct.ChainedReducer.html
+
+use std::io::Read;
+use std::marker::PhantomData;
+
+use serde_json::{Map, Value as JsonValue};
+
+use super::{Reducer, StatefulReducer};
+use record::{Record, RecordExt};
+use repository::Config;
+
+/// Repository configuration property that lists natively reduced types
+pub const CONFIG_PROPERTY: &str = "native_reducers";
+
+/// All natively supported record types
+pub const TYPES: &[&str] = &["SummaryChanged", "DetailsChanged", "Commented", "Closed", "Reopened", "Merged"];
+
+fn text<R: Record, S: AsRef<str>>(record: &R, name: S) -> Option<String> {
+    record.file(name).and_then(|mut reader| {
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).ok()?;
+        Some(String::from_utf8_lossy(&buf).into_owned())
+    })
+}
+
+macro_rules! native_reducer {
+    ($(#[$attr:meta])* $name: ident, $typ: expr, |$state: ident, $record: ident| $body: block) => {
+        $(#[$attr])*
+        pub struct $name<R: Record>(PhantomData<R>);
+
+        impl<R: Record> $name<R> {
+            pub fn new() -> Self {
+                $name(PhantomData)
+            }
+        }
+
+        impl<R: Record> Default for $name<R> {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl<R: Record> Clone for $name<R> {
+            fn clone(&self) -> Self {
+                Self::new()
+            }
+        }
+
+        impl<R: Record> Reducer for $name<R> {
+            type State = Map<String, JsonValue>;
+            type Item = R;
+
+            fn reduce(&mut self, mut $state: Self::State, $record: &Self::Item) -> Self::State {
+                if $record.has_type($typ) $body
+                $state
+            }
+
+            fn claims(&self, item: &Self::Item) -> bool {
+                item.has_type($typ)
+            }
+        }
+    };
+}
+
+native_reducer!(
+    /// Sets `summary` from `SummaryChanged` records
+    SummaryChanged, "SummaryChanged", |state, record| {
+    if let Some(text) = text(record, "text") {
+        state.insert("summary".into(), JsonValue::String(text.trim().into()));
+    }
+});
+
+native_reducer!(
+    /// Sets `details` from `DetailsChanged` records
+    DetailsChanged, "DetailsChanged", |state, record| {
+    if let Some(text) = text(record, "text") {
+        state.insert("details".into(), JsonValue::String(text.trim().into()));
+    }
+});
+
+native_reducer!(
+    /// Appends `Commented` records to `comments`
+    Commented, "Commented", |state, record| {
+    let mut comment = Map::new();
+    comment.insert("text".into(), text(record, "text").map(JsonValue::String).unwrap_or(JsonValue::Null));
+    comment.insert("authors".into(), text(record, ".authors").map(|s| JsonValue::String(s.trim().into())).unwrap_or(JsonValue::Null));
+    comment.insert("timestamp".into(), text(record, ".timestamp").map(|s| JsonValue::String(s.trim().into())).unwrap_or(JsonValue::Null));
+    comment.insert("hash".into(), JsonValue::String(record.encoded_hash().as_ref().into()));
+    let comments = state.entry(String::from("comments")).or_insert(JsonValue::Array(vec![]));
+    if !comments.is_array() {
+        *comments = JsonValue::Array(vec![]);
+    }
+    comments.as_array_mut().unwrap().push(JsonValue::Object(comment));
+});
+
+native_reducer!(
+    /// Sets `state` to `"closed"` on `Closed` records
+    Closed, "Closed", |state, _record| {
+    state.insert("state".into(), JsonValue::String("closed".into()));
+});
+
+native_reducer!(
+    /// Sets `state` to `"open"` on `Reopened` records
+    Reopened, "Reopened", |state, _record| {
+    state.insert("state".into(), JsonValue::String("open".into()));
+});
+
+native_reducer!(
+    /// Sets `merged` to `true` (and `state` to `"closed"`) on `Merged` records
+    Merged, "Merged", |state, _record| {
+    state.insert("merged".into(), JsonValue::Bool(true));
+    state.insert("state".into(), JsonValue::String("closed".into()));
+});
+
+/// A set of native reducers selected by record type
+///
+/// Useful when the set is only known at runtime (for example, from configuration).
+pub struct NativeReducers<R: Record> {
+    types: Vec<String>,
+    phantom_data: PhantomData<R>,
+}
+
+impl<R: Record> Clone for NativeReducers<R> {
+    fn clone(&self) -> Self {
+        NativeReducers {
+            types: self.types.clone(),
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<R: Record> NativeReducers<R> {
+    /// Creates a set of reducers for given types
+    ///
+    /// Types that are not natively supported are ignored.
+    pub fn new<S: AsRef<str>, I: IntoIterator<Item = S>>(types: I) -> Self {
+        NativeReducers {
+            types: types.into_iter()
+                .filter(|t| TYPES.contains(&t.as_ref()))
+                .map(|t| t.as_ref().into())
+                .collect(),
+            phantom_data: PhantomData,
+        }
+    }
+
+    /// Creates a set of reducers enabled in repository configuration
+    pub fn from_config(config: &Config) -> Self {
+        match config.extra().get(CONFIG_PROPERTY) {
+            Some(&JsonValue::Bool(true)) => Self::new(TYPES),
+            Some(&JsonValue::Array(ref types)) => Self::new(types.iter().filter_map(JsonValue::as_str)),
+            _ => Self::new(Vec::<String>::new()),
+        }
+    }
+
+    /// Returns natively reduced types
+    pub fn types(&self) -> &[String] {
+        &self.types
+    }
+
+    /// Returns true if no types are reduced natively
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+}
+
+impl<R: Record> Reducer for NativeReducers<R> {
+    type State = Map<String, JsonValue>;
+    type Item = R;
+
+    fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
+        self.types.iter().fold(state, |state, typ| match typ.as_str() {
+            "SummaryChanged" => SummaryChanged::new().reduce(state, item),
+            "DetailsChanged" => DetailsChanged::new().reduce(state, item),
+            "Commented" => Commented::new().reduce(state, item),
+            "Closed" => Closed::new().reduce(state, item),
+            "Reopened" => Reopened::new().reduce(state, item),
+            "Merged" => Merged::new().reduce(state, item),
+            _ => state,
+        })
+    }
+
+    fn claims(&self, item: &Self::Item) -> bool {
+        self.types.iter().any(|typ| item.has_type(typ))
+    }
+}
+
+impl<R: Record> StatefulReducer for NativeReducers<R> {
+    fn private_state(&self) -> Option<JsonValue> {
+        // native reducers are stateless
+        Some(JsonValue::Null)
+    }
+
+    fn set_private_state(&mut self, state: JsonValue) -> bool {
+        state.is_null()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use ::Repository;
+    use record::{RecordOwningContainer, RecordContainerReduction};
+
+    #[test]
+    fn standard_reducers() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title  "[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![(".type/DetailsChanged", &b""[..]), ("text", &b"Details\n"[..])].into_iter(), true).unwrap();
+        let comment = repo.new_record(vec![(".type/Commented", &b""[..]), ("text", &b"Comment"[..]),
+                                           (".authors", &b"Someone <s@example.com>"[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![(".type/Closed", &b""[..])].into_iter(), true).unwrap();
+
+        let mut reducer = SummaryChanged::new().chain(DetailsChanged::new()).chain(Commented::new())
+            .chain(Closed::new()).chain(Reopened::new()).chain(Merged::new());
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+
+        assert_eq!(state.get("summary").unwrap(), &JsonValue::String("Title".into()));
+        assert_eq!(state.get("details").unwrap(), &JsonValue::String("Details".into()));
+        assert_eq!(state.get("state").unwrap(), &JsonValue::String("closed".into()));
+        let comments = state.get("comments").unwrap().as_array().unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].get("text").unwrap(), &JsonValue::String("Comment".into()));
+        assert_eq!(comments[0].get("authors").unwrap(), &JsonValue::String("Someone <s@example.com>".into()));
+        assert_eq!(comments[0].get("timestamp").unwrap(), &JsonValue::Null);
+        assert_eq!(comments[0].get("hash").unwrap(), &JsonValue::String(comment.encoded_hash()));
+
+        repo.new_record(vec![(".type/Reopened", &b""[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut NativeReducers::new(TYPES)).unwrap();
+        assert_eq!(state.get("state").unwrap(), &JsonValue::String("open".into()));
+    }
+
+    #[test]
+    fn selected_types() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![(".type/Merged", &b""[..])].into_iter(), true).unwrap();
+
+        let mut reducer = NativeReducers::new(vec!["Merged", "UnknownType"]);
+        assert_eq!(reducer.types(), &[String::from("Merged")]);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert!(state.get("summary").is_none());
+        assert_eq!(state.get("merged").unwrap(), &JsonValue::Bool(true));
+    }
+
+    #[test]
+    fn from_config() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let mut config = Repository::new(tmp).unwrap().config().clone();
+        assert!(NativeReducers::<::repository::Record>::from_config(&config).is_empty());
+        config.set_extra_properties(vec![(CONFIG_PROPERTY, JsonValue::Bool(true))]);
+        assert_eq!(NativeReducers::<::repository::Record>::from_config(&config).types().len(), TYPES.len());
+        config.set_extra_properties(vec![(CONFIG_PROPERTY, JsonValue::Array(vec!["Closed".into(), "Reopened".into()]))]);
+        assert_eq!(NativeReducers::<::repository::Record>::from_config(&config).types(),
+                   &[String::from("Closed"), String::from("Reopened")]);
+    }
+}
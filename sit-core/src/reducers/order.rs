@@ -0,0 +1,282 @@
+//! Reducer ordering
+//!
+//! Reducers found in a directory are ordered by their file names, unless the
+//! directory has an `order` manifest that declares the order and dependencies:
+//!
+//! ```text
+//! # reducers listed without dependencies run in the listed order
+//! summary.js
+//! details.js
+//! # dependencies are listed after a colon and can refer to reducers of the
+//! # same directory, to a reducer of a module (MODULE/FILE), to all
+//! # reducers of a module (MODULE/), to a reducer of the repository
+//! # itself (/FILE) or to all of repository's reducers (/)
+//! state.js: summary.js workflow/status.js
+//! comments.js: users/
+//! history.js: /
+//! ```
+//!
+//! A module is identified by the name of its directory (the last component of
+//! its module path). The leading `/` is reserved for repository's own reducers
+//! (see [`SourceFiles::repository_reducers`]), which are never matched by a module
+//! name. Entries with a different extension than that of reducers being ordered
+//! are ignored, so one manifest can be used for reducers in different languages.
+//!
+//! Dependencies that can't be satisfied (cycles) result in an error.
+//!
+//! [`SourceFiles::repository_reducers`]: ../trait.SourceFiles.html#method.repository_reducers
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Order manifest file name
+pub const ORDER_FILE: &str = "order";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// I/O error
+    IoError(io::Error),
+    /// Reducer listed in the order manifest is not found
+    #[error(no_from, non_std)]
+    UnknownReducer {
+        manifest: PathBuf,
+        reducer: String,
+    },
+    /// Dependency is not found
+    #[error(no_from, non_std)]
+    UnknownDependency {
+        reducer: PathBuf,
+        dependency: String,
+    },
+    /// Reducers depend on each other
+    #[error(no_from, non_std)]
+    Cycle(Vec<PathBuf>),
+}
+
+impl Error {
+    /// Returns a human-readable explanation of the error
+    pub fn explain(&self) -> String {
+        match *self {
+            Error::IoError(ref err) => format!("{}", err),
+            Error::UnknownReducer { ref manifest, ref reducer } =>
+                format!("{} lists {}, which is not found", manifest.to_string_lossy(), reducer),
+            Error::UnknownDependency { ref reducer, ref dependency } =>
+                format!("{} depends on {}, which is not found", reducer.to_string_lossy(), dependency),
+            Error::Cycle(ref cycle) =>
+                format!("dependency cycle: {}", cycle.iter().map(|p| p.to_string_lossy().into_owned())
+                    .collect::<Vec<_>>().join(" -> ")),
+        }
+    }
+}
+
+struct Node {
+    path: PathBuf,
+    /// Index of the directory the reducer was found in
+    dir: Option<usize>,
+    deps: Vec<usize>,
+}
+
+fn has_extension<P: AsRef<Path>>(path: P, extension: &str) -> bool {
+    path.as_ref().extension() == Some(OsStr::new(extension))
+}
+
+/// Returns the name of the module that has a given `reducers` directory
+///
+/// Module's reducers are in `MODULE/reducers`, where `MODULE` is the module path.
+fn module_name(dir: &Path) -> Option<&OsStr> {
+    dir.parent().and_then(Path::file_name)
+}
+
+/// Returns reducer files (with a given extension) in the order they should be executed in
+///
+/// `paths` are either reducer files (which are returned as is) or directories
+/// of reducers. `repository` is the directory of repository's own reducers, the
+/// only one `/FILE` and `/` dependencies refer to; all other directories are
+/// reducers of modules.
+pub fn order<I: IntoIterator<Item = PathBuf>>(paths: I, repository: Option<PathBuf>, extension: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut nodes = vec![];
+    let mut dirs = vec![];
+    for path in paths {
+        if path.is_file() {
+            nodes.push(Node { path, dir: None, deps: vec![] });
+        } else if path.is_dir() {
+            let mut files: Vec<_> = fs::read_dir(&path)?.filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| has_extension(path, extension) && path.is_file())
+                .collect();
+            files.sort();
+            let dir = dirs.len();
+            nodes.extend(files.into_iter().map(|path| Node { path, dir: Some(dir), deps: vec![] }));
+            dirs.push(path);
+        } else {
+            return Err(io::Error::from(io::ErrorKind::NotFound).into());
+        }
+    }
+
+    let repository = repository.and_then(|repository| dirs.iter().position(|d| d == &repository));
+
+    for (dir, dir_path) in dirs.iter().enumerate() {
+        let manifest = dir_path.join(ORDER_FILE);
+        if !manifest.is_file() {
+            continue;
+        }
+        let mut text = String::new();
+        fs::File::open(&manifest)?.read_to_string(&mut text)?;
+        let find = |dir: usize, name: &str| nodes.iter().position(|n| n.dir == Some(dir) &&
+                                                                       n.path.file_name() == Some(OsStr::new(name)));
+        let mut previous = None;
+        // dependencies are recorded once the manifest is parsed, as `find` borrows `nodes`
+        let mut edges = vec![];
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap().trim();
+            if !has_extension(name, extension) {
+                continue;
+            }
+            let node = find(dir, name).ok_or_else(|| Error::UnknownReducer { manifest: manifest.clone(), reducer: name.into() })?;
+            let mut deps = vec![];
+            match parts.next() {
+                // listed order
+                None => deps.extend(previous),
+                Some(dependencies) => for dependency in dependencies.split_whitespace() {
+                    let mut dep_parts = dependency.splitn(2, '/');
+                    let found: Vec<usize> = match (dep_parts.next(), dep_parts.next()) {
+                        (Some(file), None) if has_extension(file, extension) => find(dir, file).into_iter().collect(),
+                        (Some(_), None) => continue,
+                        (Some(module), Some(file)) if file.is_empty() || has_extension(file, extension) => {
+                            let module_dirs: Vec<_> = if module.is_empty() {
+                                repository.into_iter().collect()
+                            } else {
+                                (0..dirs.len()).filter(|d| Some(*d) != repository && module_name(&dirs[*d]) == Some(OsStr::new(module))).collect()
+                            };
+                            nodes.iter().enumerate()
+                                .filter(|&(_, n)| n.dir.map(|d| module_dirs.contains(&d)).unwrap_or(false))
+                                .filter(|&(_, n)| file.is_empty() || n.path.file_name() == Some(OsStr::new(file)))
+                                .map(|(i, _)| i)
+                                .collect()
+                        },
+                        _ => continue,
+                    };
+                    if found.is_empty() {
+                        return Err(Error::UnknownDependency { reducer: nodes[node].path.clone(), dependency: dependency.into() });
+                    }
+                    deps.extend(found);
+                },
+            }
+            edges.push((node, deps));
+            previous = Some(node);
+        }
+        for (node, deps) in edges {
+            nodes[node].deps.extend(deps);
+        }
+    }
+
+    // stable topological sort: the first reducer (in the original order)
+    // that has all its dependencies satisfied goes next
+    let mut done = vec![false; nodes.len()];
+    let mut ordered = vec![];
+    while ordered.len() < nodes.len() {
+        match (0..nodes.len()).find(|&i| !done[i] && nodes[i].deps.iter().all(|&d| done[d])) {
+            Some(i) => {
+                done[i] = true;
+                ordered.push(i);
+            },
+            None => return Err(Error::Cycle(cycle(&nodes, &done))),
+        }
+    }
+    Ok(ordered.into_iter().map(|i| nodes[i].path.clone()).collect())
+}
+
+/// Finds a cycle among reducers that are not done yet
+fn cycle(nodes: &[Node], done: &[bool]) -> Vec<PathBuf> {
+    // every pending reducer has a pending dependency, so following them
+    // will eventually get us to a reducer we've already seen
+    let mut path: Vec<usize> = vec![(0..nodes.len()).find(|&i| !done[i]).unwrap()];
+    loop {
+        let current = *path.last().unwrap();
+        let next = *nodes[current].deps.iter().find(|&&d| !done[d]).unwrap();
+        if let Some(start) = path.iter().position(|&i| i == next) {
+            let mut cycle: Vec<_> = path[start..].iter().map(|&i| nodes[i].path.clone()).collect();
+            cycle.push(nodes[next].path.clone());
+            return cycle;
+        }
+        path.push(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use std::io::Write;
+
+    fn module(tmp: &Path, name: &str, files: &[&str], order: Option<&str>) -> PathBuf {
+        let dir = tmp.join(name).join("reducers");
+        fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            fs::File::create(dir.join(file)).unwrap();
+        }
+        if let Some(order) = order {
+            fs::File::create(dir.join(ORDER_FILE)).unwrap().write_all(order.as_bytes()).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn default_order() {
+        let tmp = TempDir::new("sit").unwrap();
+        let dir = module(tmp.path(), "m", &["b.js", "a.js", "c.wasm"], None);
+        assert_eq!(order(vec![dir.clone()], None, "js").unwrap(), vec![dir.join("a.js"), dir.join("b.js")]);
+        assert_eq!(order(vec![dir.clone()], None, "wasm").unwrap(), vec![dir.join("c.wasm")]);
+    }
+
+    #[test]
+    fn listed_order() {
+        let tmp = TempDir::new("sit").unwrap();
+        let dir = module(tmp.path(), "m", &["a.js", "b.js", "c.js", "d.js"], Some("# comment\nc.js\n\nb.js\nx.wasm\n"));
+        assert_eq!(order(vec![dir.clone()], None, "js").unwrap(),
+                   vec![dir.join("a.js"), dir.join("c.js"), dir.join("b.js"), dir.join("d.js")]);
+    }
+
+    #[test]
+    fn dependencies() {
+        let tmp = TempDir::new("sit").unwrap();
+        let m1 = module(tmp.path(), "m1", &["a.js", "b.js"], Some("a.js: b.js m2/c.js\n"));
+        let m2 = module(tmp.path(), "m2", &["c.js", "d.js"], Some("c.js: m3/\n"));
+        let m3 = module(tmp.path(), "m3", &["e.js", "f.js"], None);
+        assert_eq!(order(vec![m1.clone(), m2.clone(), m3.clone()], None, "js").unwrap(),
+                   vec![m1.join("b.js"), m2.join("d.js"), m3.join("e.js"), m3.join("f.js"), m2.join("c.js"), m1.join("a.js")]);
+    }
+
+    #[test]
+    fn repository_dependencies() {
+        let tmp = TempDir::new("sit").unwrap();
+        // repository's reducers directory is in a directory named like the module
+        let repository = module(tmp.path(), "m", &["a.js", "b.js"], Some("a.js: m/c.js\n"));
+        let m = module(&tmp.path().join("modules"), "m", &["c.js", "d.js"], Some("c.js: /b.js\nd.js: /\n"));
+        assert_eq!(order(vec![repository.clone(), m.clone()], Some(repository.clone()), "js").unwrap(),
+                   vec![repository.join("b.js"), m.join("c.js"), repository.join("a.js"), m.join("d.js")]);
+        assert_matches!(order(vec![repository.clone(), m.clone()], None, "js"),
+                        Err(Error::UnknownDependency { ref dependency, .. }) if dependency == "/b.js");
+    }
+
+    #[test]
+    fn unknown() {
+        let tmp = TempDir::new("sit").unwrap();
+        let dir = module(tmp.path(), "m1", &["a.js"], Some("b.js\n"));
+        assert_matches!(order(vec![dir.clone()], None, "js"), Err(Error::UnknownReducer { ref reducer, .. }) if reducer == "b.js");
+        let dir = module(tmp.path(), "m2", &["a.js"], Some("a.js: m3/\n"));
+        assert_matches!(order(vec![dir.clone()], None, "js"), Err(Error::UnknownDependency { ref dependency, .. }) if dependency == "m3/");
+    }
+
+    #[test]
+    fn cycle() {
+        let tmp = TempDir::new("sit").unwrap();
+        let dir = module(tmp.path(), "m", &["a.js", "b.js", "c.js"], Some("a.js: c.js\nb.js: a.js\nc.js: b.js\n"));
+        let err = order(vec![dir.clone()], None, "js").unwrap_err();
+        assert_matches!(err, Error::Cycle(ref cycle) if cycle == &vec![dir.join("a.js"), dir.join("c.js"), dir.join("b.js"), dir.join("a.js")]);
+        assert!(err.explain().starts_with("dependency cycle: "));
+    }
+}
@@ -0,0 +1,445 @@
+//! QuickJS-based JavaScript reducers
+//!
+//! Offers the same `module.exports = function(state, record)` contract as
+//! [`DuktapeReducer`], but with a modern (ES2020) JavaScript engine: arrow
+//! functions, `let`/`const`, template strings, destructuring, etc. are all
+//! available to reducer authors.
+//!
+//! `require()` is not supported by this engine.
+//!
+//! [`DuktapeReducer`]: ../duktape/struct.DuktapeReducer.html
+
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+use quick_js::{Context, JsValue};
+use serde_json::{self, Map, Value as JsonValue};
+
+use super::{Reducer, StatefulReducer, SourceFiles, order};
+use ::Record;
+use record::RecordExt;
+use encryption::{self, GnuPG};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    IoError(::std::io::Error),
+    RepositoryError(::repository::Error),
+    OrderError(order::Error),
+    #[error(no_from, non_std)]
+    ExecutionError {
+        error: String,
+    },
+    #[error(no_from, non_std)]
+    CompileError {
+        file: PathBuf,
+        error: String,
+    },
+}
+
+/// Host-side part of the reducer runtime
+///
+/// Keeps loaded reducers and their states (`this`), converts records
+/// passed in as JSON into objects with `Uint8Array` files and provides
+/// `TextDecoder` (which is not a part of QuickJS).
+const RUNTIME: &str = r#"
+(function(global) {
+  var reducers = [];
+
+  if (typeof global.TextDecoder === 'undefined') {
+    global.TextDecoder = function TextDecoder(encoding) {
+      this.encoding = (encoding || 'utf-8').toLowerCase();
+      if (this.encoding !== 'utf-8' && this.encoding !== 'utf8') {
+        throw new RangeError('unsupported encoding: ' + encoding);
+      }
+    };
+    global.TextDecoder.prototype.decode = function(bytes) {
+      if (typeof bytes === 'undefined') {
+        return '';
+      }
+      var result = '', chunk = [], i = 0;
+      while (i < bytes.length) {
+        var b = bytes[i++], cp;
+        if (b < 0x80) {
+          cp = b;
+        } else if (b >= 0xc0 && b < 0xe0) {
+          cp = ((b & 0x1f) << 6) | (bytes[i++] & 0x3f);
+        } else if (b >= 0xe0 && b < 0xf0) {
+          cp = ((b & 0x0f) << 12) | ((bytes[i++] & 0x3f) << 6) | (bytes[i++] & 0x3f);
+        } else if (b >= 0xf0 && b < 0xf8) {
+          cp = ((b & 0x07) << 18) | ((bytes[i++] & 0x3f) << 12) | ((bytes[i++] & 0x3f) << 6) | (bytes[i++] & 0x3f);
+        } else {
+          cp = 0xfffd;
+        }
+        chunk.push(cp > 0x10ffff ? 0xfffd : cp);
+        if (chunk.length >= 4096) {
+          result += String.fromCodePoint.apply(null, chunk);
+          chunk = [];
+        }
+      }
+      return result + String.fromCodePoint.apply(null, chunk);
+    };
+  }
+
+  function bytes(s) {
+    var a = new Uint8Array(s.length);
+    for (var i = 0; i < s.length; i++) {
+      a[i] = s.charCodeAt(i);
+    }
+    return a;
+  }
+
+  global.__sit_load = function(source) {
+    var f, module = {exports: {}};
+    try {
+      f = new Function('module', source);
+    } catch (e) {
+      return ['compile', String(e)];
+    }
+    try {
+      f(module);
+    } catch (e) {
+      return ['execution', String(e)];
+    }
+    if (typeof module.exports !== 'function') {
+      return ['compile', 'module.exports should export a function'];
+    }
+    reducers.push({reducer: module.exports, state: {}});
+    return null;
+  };
+
+  function states() {
+    return reducers.map(function(r) { return r.state; });
+  }
+
+  // private states are reported along with every result so that the host
+  // doesn't need to call back into this context to read them
+  function respond(r) {
+    r.states = states();
+    return JSON.stringify(r);
+  }
+
+  global.__sit_reduce = function(state, record) {
+    state = JSON.parse(state);
+    record = JSON.parse(record);
+    var files = {};
+    for (var name in record.files) {
+      files[name] = bytes(record.files[name]);
+    }
+    record.files = files;
+    for (var i = 0; i < reducers.length; i++) {
+      var result;
+      try {
+        result = reducers[i].reducer.call(reducers[i].state, state, record);
+      } catch (e) {
+        return respond({index: i, error: String(e)});
+      }
+      if (typeof result === 'object' && result !== null) {
+        state = result;
+      } else if (typeof result !== 'undefined') {
+        return respond({index: i, error: 'TypeError: invalid return value ' + String(result) + ', expected an object'});
+      }
+    }
+    return respond({state: state});
+  };
+
+  global.__sit_reset = function() {
+    for (var i = 0; i < reducers.length; i++) {
+      reducers[i].state = {};
+    }
+  };
+
+  global.__sit_set_states = function(states) {
+    states = JSON.parse(states);
+    for (var i = 0; i < reducers.length; i++) {
+      reducers[i].state = states[i];
+    }
+  };
+})(this);
+"#;
+
+pub struct QuickJsReducer<R: Record> {
+    context: Context,
+    filenames: Vec<PathBuf>,
+    sources: Vec<String>,
+    // mirrors reducers' private states, as QuickJS contexts can't be
+    // called into from threads other than the one they were created on
+    states: JsonValue,
+    phantom_data: PhantomData<R>,
+    decryptor: Option<GnuPG>,
+}
+
+unsafe impl<R: Record> Send for QuickJsReducer<R> {}
+
+impl<R: Record> fmt::Debug for QuickJsReducer<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QuickJsReducer")
+            .field("filenames", &self.filenames)
+            .finish()
+    }
+}
+
+impl<R: Record> QuickJsReducer<R> {
+    fn context() -> Result<Context, Error> {
+        let context = Context::new().map_err(|err| Error::ExecutionError { error: format!("{:?}", err) })?;
+        context.eval(RUNTIME).map_err(|err| Error::ExecutionError { error: format!("{}", err) })?;
+        Ok(context)
+    }
+
+    fn load_source(context: &Context, file: PathBuf, source: &str) -> Result<PathBuf, Error> {
+        match context.call_function("__sit_load", vec![JsValue::String(source.into())]) {
+            Ok(JsValue::Null) => Ok(file),
+            Ok(JsValue::Array(ref result)) if result.len() == 2 => {
+                let error = match result[1] {
+                    JsValue::String(ref error) => error.clone(),
+                    _ => String::from("unknown error"),
+                };
+                match result[0] {
+                    JsValue::String(ref kind) if kind == "execution" => Err(Error::ExecutionError { error }),
+                    _ => Err(Error::CompileError { file, error }),
+                }
+            },
+            Ok(_) => Err(Error::ExecutionError { error: "unexpected runtime result".into() }),
+            Err(err) => Err(Error::ExecutionError { error: format!("{}", err) }),
+        }
+    }
+
+    pub fn new<SF: SourceFiles>(source_files: SF) -> Result<Self, Error> {
+        let context = Self::context()?;
+        let mut filenames = vec![];
+        let mut sources = vec![];
+        let repository = source_files.repository_reducers();
+        let files: Vec<_> = source_files.source_files()?.collect();
+        let paths = order::order(files, repository, "js")?;
+        for path in paths {
+            let mut source = String::new();
+            fs::File::open(&path)?.read_to_string(&mut source)?;
+            filenames.push(Self::load_source(&context, path, &source)?);
+            sources.push(source);
+        }
+        let states = Self::empty_states(filenames.len());
+        Ok(QuickJsReducer {
+            context,
+            filenames,
+            sources,
+            states,
+            phantom_data: PhantomData,
+            decryptor: None,
+        })
+    }
+
+    /// Sets a decryptor for encrypted records
+    ///
+    /// If set, reducers will be given decrypted content of encrypted records,
+    /// provided it can be decrypted. Otherwise, the content is passed as is.
+    pub fn set_decryptor(&mut self, decryptor: Option<GnuPG>) {
+        self.decryptor = decryptor;
+    }
+
+    /// Returns the reducer with a changed decryptor for encrypted records
+    pub fn with_decryptor(mut self, decryptor: Option<GnuPG>) -> Self {
+        self.decryptor = decryptor;
+        self
+    }
+
+    /// Resets every reducer's state back to an empty object
+    ///
+    /// Very useful for re-using the same set of reducers for
+    /// multiple items, helps avoiding re-reading and re-compiling
+    /// reducer functions every time.
+    pub fn reset_state(&mut self) {
+        self.context.call_function("__sit_reset", Vec::<JsValue>::new()).expect("can't reset state");
+        self.states = Self::empty_states(self.filenames.len());
+    }
+
+    fn empty_states(len: usize) -> JsonValue {
+        JsonValue::Array((0..len).map(|_| JsonValue::Object(Map::new())).collect())
+    }
+
+    fn record_json(&self, item: &R) -> String {
+        let encrypted = self.decryptor.is_some() && item.file(encryption::ENCRYPTED_FILE).is_some();
+        let mut files = Map::new();
+        for (name, mut reader) in item.file_iter() {
+            let mut buf = vec![];
+            reader.read_to_end(&mut buf).unwrap();
+            if encrypted && encryption::is_confidential(name.as_ref()) {
+                if let Some(plaintext) = self.decryptor.as_ref().and_then(|d| d.decrypt(&buf)) {
+                    buf = plaintext;
+                }
+            }
+            // every byte is passed as a character code
+            files.insert(name.as_ref().into(), JsonValue::String(buf.iter().map(|b| *b as char).collect()));
+        }
+        let mut record = Map::new();
+        record.insert("hash".into(), JsonValue::String(item.encoded_hash().as_ref().into()));
+        record.insert("files".into(), JsonValue::Object(files));
+        serde_json::to_string(&JsonValue::Object(record)).unwrap()
+    }
+}
+
+impl<R: Record> Clone for QuickJsReducer<R> {
+    fn clone(&self) -> Self {
+        let context = Self::context().unwrap();
+        for (file, source) in self.filenames.iter().zip(self.sources.iter()) {
+            // since it's a clone we assume the first load went fine
+            Self::load_source(&context, file.clone(), source).unwrap();
+        }
+        // transfer state
+        let states = JsValue::String(serde_json::to_string(&self.states).unwrap());
+        context.call_function("__sit_set_states", vec![states]).unwrap();
+        QuickJsReducer {
+            context,
+            filenames: self.filenames.clone(),
+            sources: self.sources.clone(),
+            states: self.states.clone(),
+            phantom_data: PhantomData,
+            decryptor: self.decryptor.clone(),
+        }
+    }
+}
+
+impl<R: Record> Reducer for QuickJsReducer<R> {
+    type State = Map<String, JsonValue>;
+    type Item = R;
+
+    fn reduce(&mut self, mut state: Self::State, item: &Self::Item) -> Self::State {
+        let json = serde_json::to_string(&JsonValue::Object(state.clone())).unwrap();
+        let record = self.record_json(item);
+        let (file, error) = match self.context.call_function("__sit_reduce", vec![JsValue::String(json), JsValue::String(record)]) {
+            Ok(JsValue::String(result)) => {
+                let mut result: Map<String, JsonValue> = serde_json::from_str(&result).unwrap();
+                if let Some(states) = result.remove("states") {
+                    self.states = states;
+                }
+                match result.remove("state") {
+                    Some(JsonValue::Object(new_state)) => return new_state,
+                    _ => {
+                        let file = result.get("index").and_then(JsonValue::as_u64)
+                            .map(|i| self.filenames[i as usize].to_str().unwrap().into());
+                        let error = result.get("error").and_then(JsonValue::as_str).unwrap_or("unknown error").into();
+                        (file, error)
+                    },
+                }
+            },
+            Ok(_) => (None, String::from("unexpected runtime result")),
+            Err(err) => (None, format!("{}", err)),
+        };
+        {
+            let arr = state.entry(String::from("errors")).or_insert(JsonValue::Array(vec![]));
+            let mut error_ = Map::new();
+            if let Some(file) = file {
+                error_.insert("file".into(), JsonValue::String(file));
+            }
+            error_.insert("error".into(), JsonValue::String(error));
+            arr.as_array_mut().unwrap().push(JsonValue::Object(error_));
+        }
+        state
+    }
+}
+
+impl<R: Record> StatefulReducer for QuickJsReducer<R> {
+    fn private_state(&self) -> Option<JsonValue> {
+        Some(self.states.clone())
+    }
+
+    fn set_private_state(&mut self, state: JsonValue) -> bool {
+        match state {
+            JsonValue::Array(ref states) if states.len() == self.filenames.len() => (),
+            _ => return false,
+        }
+        let states = JsValue::String(serde_json::to_string(&state).unwrap());
+        if self.context.call_function("__sit_set_states", vec![states]).is_err() {
+            return false;
+        }
+        self.states = state;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use ::Repository;
+    use record::{RecordOwningContainer, RecordContainerReduction};
+    use path::HasPath;
+
+    js_reducer_fixtures!(QuickJsReducer);
+
+    #[test]
+    fn modern_syntax() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::io::Write;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+        f.write(b"const decoder = new TextDecoder('utf-8');
+        module.exports = (state, {files}) => {
+          let text = decoder.decode(files.text);
+          return {...state, hello: `${text}!`};
+        }").unwrap();
+
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut QuickJsReducer::new(&repo).unwrap()).unwrap();
+
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::String("Title!".into()));
+    }
+
+    #[test]
+    fn invalid_syntax() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::io::Write;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+        f.write(b"module.exports = function(state) { return Object.assign{\"hello\": 1}, state); }").unwrap();
+        let reducer_file = repo.path().join("reducers/reducer.js");
+        assert_matches!(QuickJsReducer::<::repository::Record>::new(&repo),
+                        Err(Error::CompileError { ref file, ref error }) if file == &reducer_file && error.starts_with("SyntaxError"));
+    }
+
+    #[test]
+    fn runtime_error() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::io::Write;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers").join("reducer.js")).unwrap();
+        f.write(b"module.exports = function(state) { return Object.assign({\"hello\": record.a}, state); }").unwrap();
+
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut QuickJsReducer::new(&repo).unwrap()).unwrap();
+
+        let errors = state.get("errors").unwrap().as_array().unwrap();
+        assert!(errors[0].as_object().unwrap().get("error").unwrap().as_str().unwrap().starts_with("ReferenceError"));
+        assert_eq!(errors[0].as_object().unwrap().get("file").unwrap(), &JsonValue::String(repo.path().join("reducers").join("reducer.js").to_str().unwrap().into()));
+    }
+
+    #[test]
+    fn clone_on_another_thread() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::io::Write;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
+        f.write_all(b"module.exports = function(state) { this.count = (this.count || 0) + 1; return Object.assign({}, state, {count: this.count}); }").unwrap();
+
+        repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let mut reducer = QuickJsReducer::new(&repo).unwrap();
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert_eq!(state.get("count").unwrap(), &JsonValue::Number(1.into()));
+
+        // private state is transferred without calling into the original context
+        let state = ::std::thread::spawn(move || {
+            let mut cloned = reducer.clone();
+            repo.reduce_with_reducer(&mut cloned).unwrap()
+        }).join().unwrap();
+        assert_eq!(state.get("count").unwrap(), &JsonValue::Number(2.into()));
+    }
+}
@@ -0,0 +1,250 @@
+//! Reducer test harness
+//!
+//! A fixture is a directory that contains a sequence of records and the state
+//! they are expected to be reduced to:
+//!
+//! ```text
+//! FIXTURE/
+//!   records/
+//!     01/        # record's file tree, e.g. `.type/SummaryChanged` and `text`
+//!     02/
+//!   expected.json
+//! ```
+//!
+//! Records are added (in the order of their directory names) to a temporary
+//! repository, each one linked to the previous one, and reduced by any [`Reducer`].
+//! This allows the same fixtures to be used for every reducer backend.
+//!
+//! [`Reducer`]: ../trait.Reducer.html
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::{self, Map, Value as JsonValue};
+use tempdir::TempDir;
+use walkdir::WalkDir;
+
+use super::Reducer;
+use record::{RecordOwningContainer, RecordContainerReduction, ReductionError};
+use repository::{self, Repository};
+
+/// Directory with records' file trees
+pub const RECORDS_DIR: &str = "records";
+/// Expected state file
+pub const EXPECTED_FILE: &str = "expected.json";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// I/O error
+    IoError(io::Error),
+    /// JSON (de)serialization error
+    JsonError(serde_json::Error),
+    /// Repository error
+    RepositoryError(repository::Error),
+    /// Reduction error
+    ReductionError(ReductionError<repository::Error>),
+    /// Not a fixture directory
+    #[error(no_from, non_std)]
+    InvalidFixture(PathBuf),
+}
+
+/// Difference between expected and actual states
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// Path to the value (`@` for the entire state)
+    pub path: String,
+    /// Expected value (`None` if it was not expected)
+    pub expected: Option<JsonValue>,
+    /// Actual value (`None` if it is missing)
+    pub actual: Option<JsonValue>,
+}
+
+/// Returns differences between expected and actual values
+pub fn diff(expected: &JsonValue, actual: &JsonValue) -> Vec<Difference> {
+    let mut differences = vec![];
+    diff_at(String::new(), Some(expected), Some(actual), &mut differences);
+    differences
+}
+
+fn diff_at(path: String, expected: Option<&JsonValue>, actual: Option<&JsonValue>, differences: &mut Vec<Difference>) {
+    match (expected, actual) {
+        (Some(&JsonValue::Object(ref expected)), Some(&JsonValue::Object(ref actual))) => {
+            for key in expected.keys().chain(actual.keys().filter(|k| !expected.contains_key(*k))) {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                diff_at(path, expected.get(key), actual.get(key), differences);
+            }
+        },
+        (Some(&JsonValue::Array(ref expected)), Some(&JsonValue::Array(ref actual))) => {
+            for i in 0..::std::cmp::max(expected.len(), actual.len()) {
+                diff_at(format!("{}[{}]", path, i), expected.get(i), actual.get(i), differences);
+            }
+        },
+        (expected, actual) if expected != actual => {
+            differences.push(Difference {
+                path: if path.is_empty() { "@".into() } else { path },
+                expected: expected.cloned(),
+                actual: actual.cloned(),
+            });
+        },
+        _ => (),
+    }
+}
+
+/// Reducer test fixture
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    path: PathBuf,
+}
+
+impl Fixture {
+    /// Opens a fixture
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        if !Fixture::is_fixture(&path) {
+            return Err(Error::InvalidFixture(path));
+        }
+        Ok(Fixture { path })
+    }
+
+    fn is_fixture(path: &Path) -> bool {
+        path.join(RECORDS_DIR).is_dir() && path.join(EXPECTED_FILE).is_file()
+    }
+
+    /// Returns fixture's path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns fixture's name (its directory name)
+    pub fn name(&self) -> String {
+        self.path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+    }
+
+    /// Returns the expected state
+    pub fn expected(&self) -> Result<JsonValue, Error> {
+        let file = fs::File::open(self.path.join(EXPECTED_FILE))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// Returns paths to records' file trees, in order
+    pub fn records(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut records: Vec<_> = fs::read_dir(self.path.join(RECORDS_DIR))?
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect();
+        records.sort();
+        Ok(records)
+    }
+
+    /// Reduces fixture's records with a reducer
+    pub fn reduce<R>(&self, reducer: &mut R) -> Result<Map<String, JsonValue>, Error>
+        where R: Reducer<State = Map<String, JsonValue>, Item = repository::Record> {
+        let tmp = TempDir::new("sit")?;
+        let repo = Repository::new(tmp.path().join(".sit"))?;
+        for record in self.records()? {
+            let mut files = vec![];
+            for entry in WalkDir::new(&record).min_depth(1).into_iter().filter_map(Result::ok) {
+                if entry.file_type().is_file() {
+                    let name = entry.path().strip_prefix(&record).unwrap().to_string_lossy().replace("\\", "/");
+                    files.push((name, fs::File::open(entry.path())?));
+                }
+            }
+            repo.new_record(files.into_iter(), true)?;
+        }
+        Ok(repo.reduce_with_reducer(reducer)?)
+    }
+
+    /// Reduces fixture's records with a reducer and returns differences from the expected state
+    pub fn check<R>(&self, reducer: &mut R) -> Result<Vec<Difference>, Error>
+        where R: Reducer<State = Map<String, JsonValue>, Item = repository::Record> {
+        let expected = self.expected()?;
+        let actual = JsonValue::Object(self.reduce(reducer)?);
+        Ok(diff(&expected, &actual))
+    }
+}
+
+/// Returns fixtures found at the path
+///
+/// The path can either be a fixture itself or a directory of fixtures.
+pub fn fixtures<P: AsRef<Path>>(path: P) -> Result<Vec<Fixture>, Error> {
+    let path = path.as_ref();
+    if Fixture::is_fixture(path) {
+        return Ok(vec![Fixture::open(path)?]);
+    }
+    let mut fixtures: Vec<_> = fs::read_dir(path)?
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| Fixture::is_fixture(p))
+        .map(|path| Fixture { path })
+        .collect();
+    fixtures.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(fixtures)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use reducers::native::NativeReducers;
+    use reducers::native;
+
+    fn create_fixture(path: &Path, expected: &str) {
+        use std::io::Write;
+        fs::create_dir_all(path.join("records/01/.type")).unwrap();
+        fs::File::create(path.join("records/01/.type/SummaryChanged")).unwrap();
+        fs::File::create(path.join("records/01/text")).unwrap().write_all(b"Title").unwrap();
+        fs::create_dir_all(path.join("records/02/.type")).unwrap();
+        fs::File::create(path.join("records/02/.type/Closed")).unwrap();
+        fs::File::create(path.join(EXPECTED_FILE)).unwrap().write_all(expected.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn passing_fixture() {
+        let tmp = TempDir::new("sit").unwrap();
+        create_fixture(&tmp.path().join("fixture"), r#"{"summary": "Title", "state": "closed"}"#);
+        let fixtures = fixtures(tmp.path()).unwrap();
+        assert_eq!(fixtures.len(), 1);
+        assert_eq!(fixtures[0].name(), "fixture");
+        assert_eq!(fixtures[0].records().unwrap().len(), 2);
+        let mut reducer = NativeReducers::new(native::TYPES);
+        assert!(fixtures[0].check(&mut reducer).unwrap().is_empty());
+    }
+
+    #[test]
+    fn failing_fixture() {
+        let tmp = TempDir::new("sit").unwrap();
+        create_fixture(tmp.path(), r#"{"summary": "Another title", "state": "closed", "comments": []}"#);
+        // a fixture itself can be given, too
+        let fixtures = fixtures(tmp.path()).unwrap();
+        assert_eq!(fixtures.len(), 1);
+        let mut reducer = NativeReducers::new(native::TYPES);
+        assert_eq!(fixtures[0].check(&mut reducer).unwrap(), vec![
+            Difference { path: "comments".into(), expected: Some(JsonValue::Array(vec![])), actual: None },
+            Difference { path: "summary".into(), expected: Some(JsonValue::String("Another title".into())),
+                         actual: Some(JsonValue::String("Title".into())) },
+        ]);
+    }
+
+    #[test]
+    fn invalid_fixture() {
+        let tmp = TempDir::new("sit").unwrap();
+        assert_matches!(Fixture::open(tmp.path()), Err(Error::InvalidFixture(_)));
+    }
+
+    #[test]
+    fn nested_diff() {
+        let expected = serde_json::from_str(r#"{"a": {"b": [1, 2]}, "c": 1}"#).unwrap();
+        let actual = serde_json::from_str(r#"{"a": {"b": [1, 3, 4]}, "d": true}"#).unwrap();
+        assert_eq!(diff(&expected, &actual), vec![
+            Difference { path: "a.b[1]".into(), expected: Some(2.into()), actual: Some(3.into()) },
+            Difference { path: "a.b[2]".into(), expected: None, actual: Some(4.into()) },
+            Difference { path: "c".into(), expected: Some(1.into()), actual: None },
+            Difference { path: "d".into(), expected: None, actual: Some(true.into()) },
+        ]);
+        assert_eq!(diff(&JsonValue::Null, &JsonValue::Bool(true)), vec![
+            Difference { path: "@".into(), expected: Some(JsonValue::Null), actual: Some(JsonValue::Bool(true)) },
+        ]);
+    }
+}
@@ -0,0 +1,344 @@
+//! WebAssembly reducers
+//!
+//! Reducers can be compiled to WebAssembly from any language that targets it
+//! (Rust, Go, C, AssemblyScript, etc.) and placed into `reducers/*.wasm` (or
+//! `modules/*/reducers/*.wasm`), the same directories JavaScript reducers
+//! are discovered in. They are executed with a pure-Rust interpreter.
+//!
+//! # ABI
+//!
+//! A reducer module must export:
+//!
+//! * `memory`: linear memory
+//! * `alloc(size: i32) -> i32`: allocates `size` bytes in `memory` and returns a pointer
+//!   to them. The host uses it to pass the state and the record in.
+//! * `reduce(state_ptr: i32, state_len: i32, record_ptr: i32, record_len: i32) -> i64`:
+//!   reduces the record over the state.
+//!
+//! The state is passed in as a UTF-8 JSON object. The record is passed in a
+//! binary form (all integers are unsigned 32-bit little-endian):
+//!
+//! ```text
+//! hash_len, hash (encoded, UTF-8),
+//! files_count,
+//! files_count times: name_len, name (UTF-8), content_len, content
+//! ```
+//!
+//! `reduce` returns a pointer to the new state (UTF-8 JSON object) in the upper
+//! 32 bits and its length in the lower 32 bits. Zero length means the state is left
+//! unchanged.
+//!
+//! Every module is instantiated once and then re-used for all records, so it
+//! can keep its own state in its memory (similarly to `this` in JavaScript reducers).
+//! It is reset by [`WasmReducer::reset_state`], which re-instantiates all modules.
+//!
+//! If a module traps or returns something that is not a JSON object, the error
+//! is appended to the `errors` array in the state and the record is not processed
+//! any further.
+//!
+//! [`WasmReducer::reset_state`]: struct.WasmReducer.html#method.reset_state
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+use serde_json::{self, Map, Value as JsonValue};
+use wasmi::{self, ImportsBuilder, MemoryRef, Module, ModuleInstance, ModuleRef, NopExternals, RuntimeValue};
+
+use super::{Reducer, StatefulReducer, SourceFiles, order};
+use ::Record;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    IoError(::std::io::Error),
+    RepositoryError(::repository::Error),
+    OrderError(order::Error),
+    #[error(no_from, non_std)]
+    CompileError {
+        file: PathBuf,
+        error: String,
+    },
+}
+
+/// Reducer that executes WebAssembly modules
+pub struct WasmReducer<R: Record> {
+    filenames: Vec<PathBuf>,
+    modules: Vec<Module>,
+    instances: Vec<ModuleRef>,
+    phantom_data: PhantomData<R>,
+}
+
+fn instantiate(module: &Module) -> Result<ModuleRef, wasmi::Error> {
+    let instance = ModuleInstance::new(module, &ImportsBuilder::default())?
+        .run_start(&mut NopExternals)?;
+    Ok(instance)
+}
+
+fn memory(instance: &ModuleRef) -> Result<MemoryRef, String> {
+    instance.export_by_name("memory")
+        .and_then(|export| export.as_memory().cloned())
+        .ok_or_else(|| String::from("module should export memory"))
+}
+
+fn alloc(instance: &ModuleRef, memory: &MemoryRef, data: &[u8]) -> Result<i32, String> {
+    let ptr = match instance.invoke_export("alloc", &[RuntimeValue::I32(data.len() as i32)], &mut NopExternals) {
+        Ok(Some(RuntimeValue::I32(ptr))) => ptr,
+        Ok(_) => return Err(String::from("alloc should return i32")),
+        Err(err) => return Err(format!("{}", err)),
+    };
+    memory.set(ptr as u32, data).map_err(|err| format!("{}", err))?;
+    Ok(ptr)
+}
+
+fn encode_record<R: Record>(record: &R) -> Vec<u8> {
+    fn push(buf: &mut Vec<u8>, data: &[u8]) {
+        let len = data.len() as u32;
+        buf.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+        buf.extend_from_slice(data);
+    }
+    let mut buf = vec![];
+    push(&mut buf, record.encoded_hash().as_ref().as_bytes());
+    let files: Vec<_> = record.file_iter().collect();
+    let count = files.len() as u32;
+    buf.extend_from_slice(&[count as u8, (count >> 8) as u8, (count >> 16) as u8, (count >> 24) as u8]);
+    for (name, mut reader) in files {
+        push(&mut buf, name.as_ref().as_bytes());
+        let mut content = vec![];
+        reader.read_to_end(&mut content).unwrap();
+        push(&mut buf, &content);
+    }
+    buf
+}
+
+impl<R: Record> fmt::Debug for WasmReducer<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WasmReducer")
+            .field("filenames", &self.filenames)
+            .finish()
+    }
+}
+
+impl<R: Record> WasmReducer<R> {
+    pub fn new<SF: SourceFiles>(source_files: SF) -> Result<Self, Error> {
+        let mut filenames = vec![];
+        let mut modules = vec![];
+        let mut instances = vec![];
+        let repository = source_files.repository_reducers();
+        let files: Vec<_> = source_files.source_files()?.collect();
+        let paths = order::order(files, repository, "wasm")?.into_iter()
+            .filter(|path| path.extension() == Some(OsStr::new("wasm")));
+        for path in paths {
+            let mut buf = vec![];
+            fs::File::open(&path)?.read_to_end(&mut buf)?;
+            let module = Module::from_buffer(&buf)
+                .map_err(|err| Error::CompileError { file: path.clone(), error: format!("{}", err) })?;
+            let instance = instantiate(&module)
+                .map_err(|err| Error::CompileError { file: path.clone(), error: format!("{}", err) })?;
+            for export in &["alloc", "reduce"] {
+                if instance.export_by_name(export).and_then(|e| e.as_func().cloned()).is_none() {
+                    return Err(Error::CompileError { file: path, error: format!("module should export {} function", export) });
+                }
+            }
+            memory(&instance).map_err(|error| Error::CompileError { file: path.clone(), error })?;
+            filenames.push(path);
+            modules.push(module);
+            instances.push(instance);
+        }
+        Ok(WasmReducer {
+            filenames,
+            modules,
+            instances,
+            phantom_data: PhantomData,
+        })
+    }
+
+    /// Resets every reducer's state by re-instantiating its module
+    pub fn reset_state(&mut self) {
+        self.instances = self.modules.iter()
+            .map(|module| instantiate(module).expect("module instantiated before"))
+            .collect();
+    }
+
+    fn reduce_with(instance: &ModuleRef, state: &[u8], record: &[u8]) -> Result<Option<Map<String, JsonValue>>, String> {
+        let memory = memory(instance)?;
+        let state_ptr = alloc(instance, &memory, state)?;
+        let record_ptr = alloc(instance, &memory, record)?;
+        let args = [RuntimeValue::I32(state_ptr), RuntimeValue::I32(state.len() as i32),
+                    RuntimeValue::I32(record_ptr), RuntimeValue::I32(record.len() as i32)];
+        let result = match instance.invoke_export("reduce", &args, &mut NopExternals) {
+            Ok(Some(RuntimeValue::I64(result))) => result as u64,
+            Ok(_) => return Err(String::from("TypeError: reduce should return i64")),
+            Err(err) => return Err(format!("{}", err)),
+        };
+        let (ptr, len) = ((result >> 32) as u32, (result & 0xffff_ffff) as usize);
+        if len == 0 {
+            return Ok(None);
+        }
+        let json = memory.get(ptr, len).map_err(|err| format!("{}", err))?;
+        match serde_json::from_slice(&json) {
+            Ok(JsonValue::Object(state)) => Ok(Some(state)),
+            _ => Err(format!("TypeError: invalid return value {}, expected an object", String::from_utf8_lossy(&json))),
+        }
+    }
+}
+
+impl<R: Record> Clone for WasmReducer<R> {
+    /// Clones the reducer
+    ///
+    /// Unlike JavaScript reducers, module's own state is not transferred:
+    /// a clone starts with freshly instantiated modules.
+    fn clone(&self) -> Self {
+        let mut modules = vec![];
+        for filename in self.filenames.iter() {
+            let mut buf = vec![];
+            fs::File::open(filename).and_then(|mut f| f.read_to_end(&mut buf)).expect("can't read module");
+            modules.push(Module::from_buffer(&buf).expect("module loaded before"));
+        }
+        let mut reducer = WasmReducer {
+            filenames: self.filenames.clone(),
+            modules,
+            instances: vec![],
+            phantom_data: PhantomData,
+        };
+        reducer.reset_state();
+        reducer
+    }
+}
+
+impl<R: Record> Reducer for WasmReducer<R> {
+    type State = Map<String, JsonValue>;
+    type Item = R;
+
+    fn reduce(&mut self, mut state: Self::State, item: &Self::Item) -> Self::State {
+        if self.instances.is_empty() {
+            return state;
+        }
+        let record = encode_record(item);
+        for (i, instance) in self.instances.iter().enumerate() {
+            let json = serde_json::to_vec(&JsonValue::Object(state.clone())).unwrap();
+            match Self::reduce_with(instance, &json, &record) {
+                Ok(Some(new_state)) => state = new_state,
+                Ok(None) => (),
+                Err(err) => {
+                    let arr = state.entry(String::from("errors")).or_insert(JsonValue::Array(vec![]));
+                    let mut error = Map::new();
+                    error.insert("file".into(), JsonValue::String(self.filenames[i].to_str().unwrap().into()));
+                    error.insert("error".into(), JsonValue::String(err));
+                    arr.as_array_mut().unwrap().push(JsonValue::Object(error));
+                    return state;
+                },
+            }
+        }
+        state
+    }
+}
+
+impl<R: Record> StatefulReducer for WasmReducer<R> {
+    /// Modules' memory is not captured, so the state can only be taken if there are no modules
+    fn private_state(&self) -> Option<JsonValue> {
+        if self.instances.is_empty() {
+            Some(JsonValue::Null)
+        } else {
+            None
+        }
+    }
+
+    fn set_private_state(&mut self, state: JsonValue) -> bool {
+        self.instances.is_empty() && state.is_null()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use ::Repository;
+    use record::{RecordOwningContainer, RecordContainerReduction};
+    use path::HasPath;
+    use std::io::Write;
+
+    // Ignores the state passed in and always returns `{"wasm":true}` (stored at offset 0)
+    //
+    // (module (memory (export "memory") 1)
+    //   (global $top (mut i32) (i32.const 1024))
+    //   (data (i32.const 0) "{\"wasm\":true}")
+    //   (func (export "alloc") (param $size i32) (result i32)
+    //     (local $ptr i32)
+    //     (set_local $ptr (get_global $top))
+    //     (set_global $top (i32.add (get_global $top) (get_local $size)))
+    //     (get_local $ptr))
+    //   (func (export "reduce") (param i32 i32 i32 i32) (result i64)
+    //     (i64.const 13)))
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic, version
+        // types
+        0x01, 0x0e, 0x02,
+        0x60, 0x01, 0x7f, 0x01, 0x7f,
+        0x60, 0x04, 0x7f, 0x7f, 0x7f, 0x7f, 0x01, 0x7e,
+        // functions
+        0x03, 0x03, 0x02, 0x00, 0x01,
+        // memory
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        // globals
+        0x06, 0x07, 0x01, 0x7f, 0x01, 0x41, 0x80, 0x08, 0x0b,
+        // exports
+        0x07, 0x1b, 0x03,
+        0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00,
+        0x05, 0x61, 0x6c, 0x6c, 0x6f, 0x63, 0x00, 0x00,
+        0x06, 0x72, 0x65, 0x64, 0x75, 0x63, 0x65, 0x00, 0x01,
+        // code
+        0x0a, 0x18, 0x02,
+        0x11, 0x01, 0x01, 0x7f, 0x23, 0x00, 0x21, 0x01, 0x23, 0x00, 0x20, 0x00, 0x6a, 0x24, 0x00, 0x20, 0x01, 0x0b,
+        0x04, 0x00, 0x42, 0x0d, 0x0b,
+        // data
+        0x0b, 0x13, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x0d,
+        0x7b, 0x22, 0x77, 0x61, 0x73, 0x6d, 0x22, 0x3a, 0x74, 0x72, 0x75, 0x65, 0x7d,
+    ];
+
+    #[test]
+    fn wasm_reducer() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/test.wasm")).unwrap();
+        f.write(MODULE).unwrap();
+        // JavaScript reducers are not picked up
+        let mut f = fs::File::create(repo.path().join("reducers/test.js")).unwrap();
+        f.write(b"module.exports = function(state, record) { return {js: true} }").unwrap();
+
+        let _record = repo.new_record(vec![(".type/SummaryChanged", &b""[..]), ("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut WasmReducer::new(&repo).unwrap()).unwrap();
+
+        assert_eq!(state.get("wasm").unwrap(), &JsonValue::Bool(true));
+        assert!(state.get("js").is_none());
+    }
+
+    #[test]
+    fn invalid_module() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        let mut f = fs::File::create(repo.path().join("reducers/test.wasm")).unwrap();
+        f.write(b"not a module").unwrap();
+        assert_matches!(WasmReducer::<::repository::Record>::new(&repo), Err(Error::CompileError { .. }));
+    }
+
+    #[test]
+    fn record_encoding() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        let record = repo.new_record(vec![("a", &b"xyz"[..])].into_iter(), false).unwrap();
+        let encoded = encode_record(&record);
+        let hash = record.encoded_hash();
+        let hash_len = hash.len();
+        assert_eq!(&encoded[0..4], &[hash_len as u8, 0, 0, 0]);
+        assert_eq!(&encoded[4..4 + hash_len], hash.as_bytes());
+        assert_eq!(&encoded[4 + hash_len..], &[1, 0, 0, 0, 1, 0, 0, 0, b'a', 3, 0, 0, 0, b'x', b'y', b'z']);
+    }
+}
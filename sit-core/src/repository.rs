@@ -18,10 +18,13 @@ use serde_json;
 
 use super::hash::HashingAlgorithm;
 use super::encoding::Encoding;
+use super::policy::{Policy, Verifier, POLICY_FILE};
 #[cfg(feature = "deprecated-item-api")]
 use super::id::IdGenerator;
 
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
 
 /// Current repository format version
 const VERSION: &str = "1";
@@ -44,6 +47,20 @@ const DEPRECATED_ITEMS_PATH: &str = "items";
 const RECORDS_PATH: &str = "records";
 /// Repository's modules path
 const MODULES_PATH: &str = "modules";
+/// Repository's recipients (public keys for encrypted records) path
+const RECIPIENTS_PATH: &str = "recipients";
+/// Repository's reduction checkpoints path
+const CHECKPOINTS_PATH: &str = "checkpoints";
+/// Repository's full-text search index path
+const SEARCH_INDEX_PATH: &str = "search";
+/// Repository's quarantine path
+const QUARANTINE_PATH: &str = "quarantine";
+/// Prefix of temporary directories records are prepared in
+pub(crate) const TEMP_PREFIX: &str = "sit";
+/// Directory in redaction records that lists redacted records
+const REDACTS_PATH: &str = ".redacts";
+/// Prefix of the content that replaces redacted files
+pub const TOMBSTONE_PREFIX: &str = "SIT-REDACTED ";
 
 
 /// Repository is the container for all SIT artifacts
@@ -72,8 +89,19 @@ pub struct Repository<MI> {
     module_iterator: MI,
     /// Integrity check
     integrity_check: bool,
+    /// Signature verifier for redaction records
+    verifier: Option<Verifier>,
+    /// Redactions, collected on first use along with the stamp of records
+    /// they were collected from (see `redactions`)
+    redactions: Arc<Mutex<Option<(RecordsStamp, Arc<Redactions>)>>>,
 }
 
+/// Hashes that redacted records are allowed to have, keyed by their original hashes
+pub(crate) type Redactions = HashMap<Vec<u8>, Vec<Vec<u8>>>;
+
+/// Modification times of directories records are placed in (see `records_stamp`)
+type RecordsStamp = Vec<(PathBuf, Option<SystemTime>)>;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ModuleDirectory<P: AsRef<Path>>(P);
 
@@ -295,6 +323,8 @@ impl Repository<ModuleDirectory<PathBuf>> {
                 modules_path,
                 module_iterator,
                 integrity_check: true,
+                verifier: None,
+                redactions: Default::default(),
             };
             repo.save()?;
             Ok(repo)
@@ -391,6 +421,8 @@ impl Repository<ModuleDirectory<PathBuf>> {
             modules_path,
             module_iterator,
             integrity_check: true,
+            verifier: None,
+            redactions: Default::default(),
         };
         if upgraded {
             repository.save()?;
@@ -453,6 +485,8 @@ impl<MI> Repository<MI> {
             config: self.config,
             module_iterator: (self.module_iterator, module_iterator),
             integrity_check: self.integrity_check,
+            verifier: self.verifier,
+            redactions: self.redactions,
         }
     }
 
@@ -468,6 +502,8 @@ impl<MI> Repository<MI> {
             config: self.config,
             module_iterator,
             integrity_check: self.integrity_check,
+            verifier: self.verifier,
+            redactions: self.redactions,
         }
     }
 
@@ -494,9 +530,33 @@ impl<MI> Repository<MI> {
             config: self.config,
             module_iterator: self.module_iterator,
             integrity_check: value,
+            verifier: self.verifier,
+            redactions: self.redactions,
         }
     }
 
+    /// Returns the verifier of redaction records' signatures
+    pub fn verifier(&self) -> Option<&Verifier> {
+        self.verifier.as_ref()
+    }
+
+    /// Mutably changes the verifier of redaction records' signatures
+    ///
+    /// Redaction records are only honoured if they are signed and the signature
+    /// can be verified, so without a verifier no redactions are honoured.
+    pub fn set_verifier(&mut self, verifier: Option<Verifier>) {
+        self.verifier = verifier;
+        self.redactions = Default::default();
+    }
+
+    /// Creates a new instance of `Repository` with a changed verifier of redaction records' signatures
+    ///
+    /// See [`set_verifier`](#method.set_verifier)
+    pub fn with_verifier(mut self, verifier: Option<Verifier>) -> Self {
+        self.set_verifier(verifier);
+        self
+    }
+
 
     /// Saves the repository. Ensures the directory exists and the configuration has
     /// been saved.
@@ -579,12 +639,15 @@ impl<MI> Repository<MI> {
             if hash.is_err() {
                 return None
             }
+            let hash = hash.unwrap();
+            let redactions = self.redactions().get(&hash).cloned().unwrap_or_default();
             let record = Record {
-                hash: hash.unwrap(),
+                hash,
                 encoding: self.config.encoding.clone(),
                 path,
                 #[cfg(feature = "deprecated-item-api")]
                 item: "".into(),
+                redactions,
             };
             Some(record)
         } else {
@@ -622,17 +685,89 @@ impl<MI> Repository<MI> {
         &self.modules_path
     }
 
+    /// Returns path to reduction checkpoints. The target directory may not exist.
+    ///
+    /// Checkpoints are local to this copy of the repository (see [`checkpoint`])
+    ///
+    /// [`checkpoint`]: ../checkpoint/index.html
+    pub fn checkpoints_path(&self) -> PathBuf {
+        self.path.join(CHECKPOINTS_PATH)
+    }
+
+    /// Returns path to the full-text search index. The target directory may not exist.
+    ///
+    /// The index is local to this copy of the repository and can be rebuilt at
+    /// any time (see [`search`])
+    ///
+    /// [`search`]: ../search/index.html
+    pub fn search_index_path(&self) -> PathBuf {
+        self.path.join(SEARCH_INDEX_PATH)
+    }
+
+    /// Returns path to the quarantine. The target directory may not exist.
+    ///
+    /// Quarantine is where problematic files are moved to upon repair (see [`fsck`])
+    ///
+    /// [`fsck`]: ../fsck/index.html
+    pub fn quarantine_path(&self) -> PathBuf {
+        self.path.join(QUARANTINE_PATH)
+    }
+
+    /// Returns path to recipients' public keys. The target directory may not exist.
+    pub fn recipients_path(&self) -> PathBuf {
+        self.path.join(RECIPIENTS_PATH)
+    }
+
+    /// Returns public key files (`*.asc`) of the recipients of encrypted records
+    pub fn recipients(&self) -> Result<Vec<PathBuf>, Error> {
+        let path = self.recipients_path();
+        if !path.is_dir() {
+            return Ok(vec![]);
+        }
+        let asc_ext = Some(::std::ffi::OsStr::new("asc"));
+        let mut recipients: Vec<_> = fs::read_dir(path)?.filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension() == asc_ext)
+            .collect();
+        recipients.sort();
+        Ok(recipients)
+    }
+
+    /// Returns repository's authorization policy
+    ///
+    /// If there's no policy file, an empty (non-restrictive) policy is returned.
+    /// A policy listing keys that are neither long key IDs nor full fingerprints
+    /// is rejected.
+    pub fn policy(&self) -> Result<Policy, Error> {
+        let path = self.path.join(POLICY_FILE);
+        if !path.is_file() {
+            return Ok(Policy::default());
+        }
+        let policy: Policy = serde_json::from_reader(fs::File::open(path)?)?;
+        policy.validate().map_err(|err| Error::OtherError(format!("invalid policy: {}", err)))?;
+        Ok(policy)
+    }
+
+    /// Returns names of parent link files (`.prev/HASH`) that a new record gets
+    /// when it is created with `link_parents`
+    ///
+    /// Signed records have to include them before signing, since the signature
+    /// covers all files but `.signature`.
+    pub fn parent_links(&self) -> Result<Vec<String>, Error> {
+        let records = self.record_iter()?.last().unwrap_or(vec![]);
+        Ok(records.iter().map(|rec| format!(".prev/{}", rec.encoded_hash())).collect())
+    }
+
     pub fn new_record_in<'f, P: AsRef<Path>, F: File + 'f, I: Into<OrderedFiles<'f, F>>>(&self, path: P, files: I, link_parents: bool) ->
     Result<Record, Error> where F::Read: 'f {
-        let tempdir = TempDir::new_in(&self.path, "sit")?;
+        let tempdir = TempDir::new_in(&self.path, TEMP_PREFIX)?;
         let mut hasher = self.config.hashing_algorithm.hasher();
 
         let files: OrderedFiles<F> = files.into();
 
         // Link parents if requested
         let files = if link_parents {
-            let records = self.record_iter()?.last().unwrap_or(vec![]);
-            let parents: OrderedFiles<_> = records.iter().map(|rec| (format!(".prev/{}", rec.encoded_hash()), &b""[..])).into();
+            let parents: OrderedFiles<_> = self.parent_links()?.into_iter().map(|name| (name, &b""[..])).into();
             files + parents
         } else {
             files.boxed()
@@ -679,14 +814,235 @@ impl<MI> Repository<MI> {
             }
             fs::rename(tempdir.into_path(), &path)?;
         }
+        if path.join(REDACTS_PATH).is_dir() {
+            *self.redactions.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        }
         Ok(Record {
             hash,
             #[cfg(feature = "deprecated-item-api")]
             item: "".into(),
             path,
             encoding: self.config.encoding.clone(),
+            redactions: vec![],
         })
     }
+
+    /// Prepares a redaction of `files` in a given record
+    ///
+    /// Nothing is changed on disk by this function. The returned [`Redaction`] is
+    /// expected to be recorded as a redaction record (see [`Redaction::files`])
+    /// before it is applied with [`redact`].
+    ///
+    /// Fails with `Error::NotFound` if any of the files is not present in the record.
+    ///
+    /// [`Redaction`]: struct.Redaction.html
+    /// [`Redaction::files`]: struct.Redaction.html#method.files
+    /// [`redact`]: struct.Repository.html#method.redact
+    pub fn redaction<S: AsRef<str>>(&self, record: &Record, files: &[S]) -> Result<Redaction, Error> {
+        use std::io::{Read, Cursor};
+        let mut tombstones = vec![];
+        for file in files {
+            let name = RelativePath::new(&file.as_ref().replace("\\", "/")).normalize().as_str().to_string();
+            if name.starts_with(".prev/") {
+                return Err(Error::OtherError(format!("parent link {} can't be redacted", name)));
+            }
+            let mut reader = match record.file_iter().find(|&(ref n, _)| n == &name) {
+                None => return Err(Error::NotFound),
+                Some((_, reader)) => reader,
+            };
+            let mut content = vec![];
+            reader.read_to_end(&mut content)?;
+            let mut hasher = self.config.hashing_algorithm.hasher();
+            hasher.process(&content);
+            let tombstone = format!("{}{}", TOMBSTONE_PREFIX, self.config.encoding.encode(&hasher.result_box()));
+            tombstones.push((name, tombstone.into_bytes()));
+        }
+        let mut hasher = self.config.hashing_algorithm.hasher();
+        {
+            let files: OrderedFiles<_> = record.file_iter().map(|(name, reader)| {
+                let reader = match tombstones.iter().find(|&&(ref n, _)| n == &name) {
+                    Some(&(_, ref tombstone)) => Box::new(Cursor::new(tombstone.clone())) as Box<Read>,
+                    None => Box::new(reader) as Box<Read>,
+                };
+                (name, reader)
+            }).into();
+            files.hash(&mut *hasher)?;
+        }
+        Ok(Redaction {
+            record: record.clone(),
+            tombstones,
+            redacted_hash: hasher.result_box(),
+        })
+    }
+
+    /// Replaces redacted files with their tombstones
+    ///
+    /// Checkpoints are removed, as they may contain state reduced from the redacted content.
+    pub fn redact(&self, redaction: &Redaction) -> Result<(), Error> {
+        for &(ref name, ref tombstone) in redaction.tombstones.iter() {
+            let path = RelativePath::new(name).to_path(redaction.record.path());
+            let mut f = fs::File::create(path)?;
+            f.write_all(tombstone)?;
+        }
+        let checkpoints = self.checkpoints_path();
+        if checkpoints.is_dir() {
+            fs::remove_dir_all(checkpoints)?;
+        }
+        Ok(())
+    }
+
+    /// Returns original hashes of records redacted by verified redaction records
+    ///
+    /// Hashes are sorted, so the result can be used to fingerprint the set of redactions.
+    pub fn redacted_hashes(&self) -> Vec<Vec<u8>> {
+        let mut hashes: Vec<_> = self.redactions().keys().cloned().collect();
+        hashes.sort();
+        hashes
+    }
+
+    /// Returns hashes that redacted records are allowed to have, keyed by their
+    /// original hashes
+    ///
+    /// They are collected once and reused for as long as no records are added
+    /// to the repository, whether by this or any other process.
+    fn redactions(&self) -> Arc<Redactions> {
+        let stamp = self.records_stamp();
+        let mut cache = self.redactions.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((ref cached_stamp, ref redactions)) = *cache {
+            if cached_stamp == &stamp {
+                return redactions.clone();
+            }
+        }
+        let redactions = Arc::new(match self.verifier {
+            Some(ref verifier) => self.collect_redactions(Some(verifier)),
+            None => Redactions::new(),
+        });
+        *cache = Some((stamp, redactions.clone()));
+        redactions
+    }
+
+    /// Returns modification times of the records directory and directories under it
+    /// that records are placed in
+    ///
+    /// Adding a record (even by copying it in) changes at least one of them.
+    fn records_stamp(&self) -> RecordsStamp {
+        let path = self.records_path().resolve_dir().unwrap_or(self.records_path().into());
+        let depth = records_depth(&self.config.hashing_algorithm, &self.config.encoding);
+        let mut stamp: RecordsStamp = walkdir::WalkDir::new(&path).max_depth(depth - 1).into_iter()
+            .filter_map(Result::ok)
+            .map(|entry| (entry.path().to_path_buf(), entry.metadata().ok().and_then(|metadata| metadata.modified().ok())))
+            .collect();
+        stamp.sort();
+        stamp
+    }
+
+    /// Returns hashes that redacted records would be allowed to have if all
+    /// (intact) redaction records were honoured, signed or not
+    ///
+    /// This is only useful to tell redacted records from corrupt ones when
+    /// signatures can't be verified.
+    pub(crate) fn unverified_redactions(&self) -> Redactions {
+        self.collect_redactions(None)
+    }
+
+    /// Collects redactions from redaction records
+    ///
+    /// Only redaction records that are intact themselves, signed (the signature is checked
+    /// with `verifier`) and authorized by the policy (if it restricts `Redacted`)
+    /// are taken into account. Without `verifier`, all intact redaction records are.
+    fn collect_redactions(&self, verifier: Option<&Verifier>) -> Redactions {
+        use std::io::Read;
+        let mut redactions = Redactions::new();
+        let policy = match self.policy() {
+            Ok(policy) => policy,
+            Err(_) => return redactions,
+        };
+        let path = self.records_path().resolve_dir().unwrap_or(self.records_path().into());
+        let depth = records_depth(&self.config.hashing_algorithm, &self.config.encoding);
+        for entry in walkdir::WalkDir::new(&path).min_depth(depth).max_depth(depth).into_iter().filter_map(Result::ok) {
+            let path = entry.path().resolve_dir().unwrap_or(entry.path().to_path_buf());
+            let redacts = path.join(REDACTS_PATH);
+            if !redacts.is_dir() {
+                continue;
+            }
+            let hash = match entry.file_name().to_str().map(|name| self.config.encoding.decode(name.as_bytes())) {
+                Some(Ok(hash)) => hash,
+                _ => continue,
+            };
+            let record = Record {
+                hash,
+                #[cfg(feature = "deprecated-item-api")]
+                item: "".into(),
+                path,
+                encoding: self.config.encoding.clone(),
+                redactions: vec![],
+            };
+            if !record.integrity_intact(&self.config.hashing_algorithm) {
+                continue;
+            }
+            if let Some(verifier) = verifier {
+                if verifier.signed_by(&record).is_none() || policy.check(&record, Some(verifier)).is_err() {
+                    continue;
+                }
+            }
+            for file in fs::read_dir(&redacts).into_iter().flat_map(|dir| dir).filter_map(Result::ok) {
+                let original = match file.file_name().to_str().map(|name| self.config.encoding.decode(name.as_bytes())) {
+                    Some(Ok(hash)) => hash,
+                    _ => continue,
+                };
+                let mut s = String::new();
+                if fs::File::open(file.path()).and_then(|mut f| f.read_to_string(&mut s)).is_err() {
+                    continue;
+                }
+                if let Ok(redacted) = self.config.encoding.decode(s.trim().as_bytes()) {
+                    redactions.entry(original).or_insert_with(Vec::new).push(redacted);
+                }
+            }
+        }
+        redactions
+    }
+}
+
+/// Redaction of files in a record
+///
+/// Produced by [`Repository::redaction`]
+///
+/// [`Repository::redaction`]: struct.Repository.html#method.redaction
+#[derive(Debug)]
+pub struct Redaction {
+    record: Record,
+    tombstones: Vec<(String, Vec<u8>)>,
+    redacted_hash: Vec<u8>,
+}
+
+impl Redaction {
+    /// Returns the record being redacted
+    pub fn record(&self) -> &Record {
+        &self.record
+    }
+
+    /// Returns names of the files being redacted
+    pub fn file_names(&self) -> Vec<&str> {
+        self.tombstones.iter().map(|&(ref name, _)| name.as_str()).collect()
+    }
+
+    /// Returns the hash of the record's content after the redaction
+    pub fn redacted_hash(&self) -> &[u8] {
+        &self.redacted_hash
+    }
+
+    /// Returns files of the redaction record
+    ///
+    /// It consists of `.type/Redacted` and `.redacts/<original hash>` file containing
+    /// the encoded hash of the record after the redaction. Any auxiliary files (authorship,
+    /// timestamp, signature) can be added to these.
+    pub fn files(&self) -> OrderedFiles<'static, (String, ::std::io::Cursor<Vec<u8>>)> {
+        use std::io::Cursor;
+        let encoding = &self.record.encoding;
+        vec![(String::from(".type/Redacted"), Cursor::new(vec![])),
+             (format!("{}/{}", REDACTS_PATH, encoding.encode(&self.record.hash)),
+              Cursor::new(encoding.encode(&self.redacted_hash).into_bytes()))].into()
+    }
 }
 
 impl<MI> RecordOwningContainer for Repository<MI> {
@@ -723,6 +1079,7 @@ impl<MI> RecordContainer for Repository<MI> {
         Ok(RepositoryRecordIterator {
             iter,
             integrity_check: self.integrity_check,
+            redactions: self.redactions(),
         })
     }
 
@@ -732,6 +1089,7 @@ impl<MI> RecordContainer for Repository<MI> {
 pub struct RepositoryRecordIterator {
     iter: GenericRecordIterator,
     integrity_check: bool,
+    redactions: Arc<Redactions>,
 }
 
 impl Iterator for RepositoryRecordIterator {
@@ -741,6 +1099,7 @@ impl Iterator for RepositoryRecordIterator {
         self.iter.next().map(|vec| {
             vec.into_iter().map(|(path, hash)|
                 Record {
+                    redactions: self.redactions.get(&hash).cloned().unwrap_or_default(),
                     hash,
                     #[cfg(feature = "deprecated-item-api")]
                     item: "".into(),
@@ -833,6 +1192,7 @@ impl<'a, MI: 'a> RecordContainer for Item<'a, MI> {
             iter,
             item: self.id.clone(),
             integrity_check: self.integrity_check,
+            redactions: self.repository.redactions(),
         })
     }
 
@@ -873,6 +1233,7 @@ pub struct ItemRecordIterator {
     iter: GenericRecordIterator,
     item: OsString,
     integrity_check: bool,
+    redactions: Arc<Redactions>,
 }
 
 #[cfg(feature = "deprecated-item-api")]
@@ -883,6 +1244,7 @@ impl Iterator for ItemRecordIterator {
         self.iter.next().map(|vec| {
             vec.into_iter().map(|(path, hash)|
                 Record {
+                    redactions: self.redactions.get(&hash).cloned().unwrap_or_default(),
                     hash,
                     item: self.item.clone(),
                     path,
@@ -897,6 +1259,15 @@ impl Iterator for ItemRecordIterator {
 
 use walkdir;
 
+/// Returns the depth at which records are located under the records path
+pub(crate) fn records_depth(hashing_algorithm: &HashingAlgorithm, encoding: &Encoding) -> usize {
+    let mut depth = hashing_algorithm.len() * 4 / encoding.bit_width();
+    if hashing_algorithm.len() * 4 % encoding.bit_width() != 0 {
+        depth +=1;
+    }
+    depth
+}
+
 /// An iterator over records
 struct GenericRecordIterator {
     hashing_algorithm: HashingAlgorithm,
@@ -909,13 +1280,7 @@ struct GenericRecordIterator {
 impl GenericRecordIterator {
     fn new(hashing_algorithm: HashingAlgorithm, encoding: Encoding, path: PathBuf,
            depth: Option<usize>) -> Self {
-        let depth = depth.or_else(|| {
-            let mut depth = hashing_algorithm.len() * 4 / encoding.bit_width();
-            if hashing_algorithm.len() * 4 % encoding.bit_width() != 0 {
-                depth +=1;
-            }
-            Some(depth)
-        }).unwrap();
+        let depth = depth.unwrap_or_else(|| records_depth(&hashing_algorithm, &encoding));
         let dir: Vec<_> = walkdir::WalkDir::new(&path).min_depth(depth).max_depth(depth)
             .into_iter().filter_map(Result::ok).collect();
         GenericRecordIterator {
@@ -1034,6 +1399,16 @@ pub struct Record {
     item: OsString,
     encoding: Encoding,
     path: PathBuf,
+    /// Hashes this record is known to have after redactions
+    redactions: Vec<Vec<u8>>,
+}
+
+impl Record {
+    /// Returns the same record, known to have given hashes after redactions
+    pub(crate) fn with_redactions(mut self, redactions: Vec<Vec<u8>>) -> Self {
+        self.redactions = redactions;
+        self
+    }
 }
 
 impl HasPath for Record {
@@ -1076,6 +1451,24 @@ impl RecordTrait for Record {
         self.encoding.encode(&self.hash)
     }
 
+    /// Returns true if the integrity of the record is intact
+    ///
+    /// Records that had some of their files redacted are considered
+    /// intact if their content matches a hash declared by a redaction record.
+    fn integrity_intact(&self, hashing_algorithm: &HashingAlgorithm) -> bool {
+        let mut hasher = hashing_algorithm.hasher();
+        let ordered_files = OrderedFiles::from(self.file_iter());
+        match ordered_files.hash(&mut *hasher) {
+            Ok(_) => {
+                let hash = hasher.result_box();
+                self.hash == hash || self.redactions.iter().any(|h| h == &hash)
+            },
+            _ => {
+                false
+            }
+        }
+    }
+
     fn file_iter(&self) -> Self::Iter {
         let path = self.path();
         let glob_pattern = format!("{}/**/*", path.to_str().unwrap());
@@ -1432,6 +1825,77 @@ mod tests {
         assert_eq!(records.pop().unwrap().hash(), record.hash());
     }
 
+    #[test]
+    fn unsigned_redaction() {
+        use policy::Verifier;
+        use encryption::GnuPG;
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(&tmp).unwrap();
+        // create a record
+        let record = repo.new_record(vec![("test", &b"secret"[..]), ("other", &b"data"[..])].into_iter(), true).unwrap();
+        // redact it without signing the redaction record
+        let redaction = repo.redaction(&record, &["test"]).unwrap();
+        let redaction_record = repo.new_record(redaction.files(), true).unwrap();
+        repo.redact(&redaction).unwrap();
+        let mut s = String::new();
+        use std::io::Read;
+        fs::File::open(record.path().join("test")).unwrap().read_to_string(&mut s).unwrap();
+        assert!(s.starts_with(TOMBSTONE_PREFIX));
+        // the redaction is ignored, so the redacted record is no longer intact
+        let records: Vec<Record> = repo.record_iter().unwrap().flat_map(|v| v).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].hash(), redaction_record.hash());
+        assert!(!repo.record(record.encoded_hash()).unwrap().integrity_intact(repo.config().hashing_algorithm()));
+        // even if signatures can be verified
+        let verifier = Verifier::new(GnuPG::new("gpg"), repo.config().hashing_algorithm().clone(),
+                                     repo.config().encoding().clone());
+        let repo = repo.with_verifier(Some(verifier));
+        let records: Vec<Record> = repo.record_iter().unwrap().flat_map(|v| v).collect();
+        assert_eq!(records.len(), 1);
+        assert!(!repo.record(record.encoded_hash()).unwrap().integrity_intact(repo.config().hashing_algorithm()));
+    }
+
+    #[test]
+    fn redactions_recollected() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(&tmp).unwrap();
+        repo.new_record(vec![("test", &b"one"[..])].into_iter(), false).unwrap();
+        repo.redactions();
+        let stamp = repo.redactions.lock().unwrap().as_ref().unwrap().0.clone();
+        assert_eq!(repo.records_stamp(), stamp);
+        // records added by another process invalidate collected redactions
+        let other = Repository::open(&tmp).unwrap();
+        other.new_record(vec![("test", &b"two"[..])].into_iter(), false).unwrap();
+        assert_ne!(repo.records_stamp(), stamp);
+        repo.redactions();
+        assert_ne!(repo.redactions.lock().unwrap().as_ref().unwrap().0, stamp);
+    }
+
+    #[test]
+    fn redaction_without_record() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(&tmp).unwrap();
+        // create a record
+        let record = repo.new_record(vec![("test", &b"secret"[..])].into_iter(), true).unwrap();
+        let redaction = repo.redaction(&record, &["test"]).unwrap();
+        // apply redaction without recording it
+        repo.redact(&redaction).unwrap();
+        let records: Vec<Record> = repo.record_iter().unwrap().flat_map(|v| v).collect();
+        assert_eq!(records.len(), 0);
+    }
+
+    #[test]
+    fn redaction_of_missing_file() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(&tmp).unwrap();
+        let record = repo.new_record(vec![("test", &b"secret"[..])].into_iter(), true).unwrap();
+        assert_matches!(repo.redaction(&record, &["missing"]), Err(Error::NotFound));
+    }
+
     #[test]
     #[cfg(feature = "deprecated-item-api")]
     fn item_record_integrity_check_propagates_from_repository() {
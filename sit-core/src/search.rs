@@ -0,0 +1,401 @@
+//! Full-text search
+//!
+//! [`Index`] is an inverted index of textual record files (such as `text`):
+//! every file that is not hidden (doesn't start with `.`) and is valid UTF-8 is
+//! split into lower-cased alphanumeric terms, and each term points to records
+//! containing it.
+//!
+//! The index is merely a cache: it is stored in a directory that is not meant to be
+//! shared (see [`Repository::search_index_path`]), can be updated incrementally as
+//! new records appear (see [`Index::update`]) and rebuilt from scratch at any time.
+//! Since records' contents can change after they were indexed (when redacted),
+//! records whose redacted files changed are re-indexed upon update and matches
+//! are always verified against current contents (see [`Index::search`]).
+//!
+//! Every match also lists roots of the record's history (records that don't have
+//! any parents), as that's what identifies the item it belongs to.
+//!
+//! [`Index`]: struct.Index.html
+//! [`Index::update`]: struct.Index.html#method.update
+//! [`Index::search`]: struct.Index.html#method.search
+//! [`Repository::search_index_path`]: ../repository/struct.Repository.html#method.search_index_path
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use serde_json;
+
+use record::{Record, RecordContainer};
+use repository::TOMBSTONE_PREFIX;
+
+/// Index file name
+pub const INDEX_FILE: &str = "index.json";
+
+/// Number of characters of context on each side of the match in snippets
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Indexed record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct IndexedRecord {
+    /// Order in which the record was indexed
+    seq: u64,
+    /// Parent records (from `.prev/*`)
+    parents: Vec<String>,
+    /// Files that were redacted (replaced with tombstones) when the record was indexed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    redacted: Vec<String>,
+}
+
+/// Full-text search index
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Index {
+    records: BTreeMap<String, IndexedRecord>,
+    terms: BTreeMap<String, BTreeSet<String>>,
+    seq: u64,
+}
+
+/// Search result
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchResult {
+    /// Matching record
+    pub record: String,
+    /// Roots of record's history
+    pub roots: Vec<String>,
+    /// Name of the first matching file
+    pub file: String,
+    /// Excerpt of the file around the first match
+    pub snippet: String,
+}
+
+/// Splits text into lower-cased terms, along with their byte ranges
+fn tokenize(text: &str) -> Vec<(usize, usize, String)> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(Some((text.len(), ' '))) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                tokens.push((s, i, text[s..i].to_lowercase()));
+                start = None;
+            },
+            _ => (),
+        }
+    }
+    tokens
+}
+
+/// Returns record's textual files and parents
+fn contents<R: Record>(record: &R) -> (Vec<(String, String)>, Vec<String>) {
+    let mut files = vec![];
+    let mut parents = vec![];
+    for (name, mut reader) in record.file_iter() {
+        let name = name.as_ref();
+        if name.starts_with(".prev/") {
+            parents.push(name[".prev/".len()..].to_owned());
+            continue;
+        }
+        if name.starts_with('.') {
+            continue;
+        }
+        let mut text = String::new();
+        if reader.read_to_string(&mut text).is_ok() && !text.starts_with(TOMBSTONE_PREFIX) {
+            files.push((name.to_owned(), text));
+        }
+    }
+    (files, parents)
+}
+
+/// Returns names of record's files that were redacted (replaced with tombstones)
+fn redacted<R: Record>(record: &R) -> Vec<String> {
+    let mut redacted = vec![];
+    for (name, reader) in record.file_iter() {
+        let name = name.as_ref();
+        if name.starts_with('.') {
+            continue;
+        }
+        let mut prefix = vec![];
+        if reader.take(TOMBSTONE_PREFIX.len() as u64).read_to_end(&mut prefix).is_ok() && prefix == TOMBSTONE_PREFIX.as_bytes() {
+            redacted.push(name.to_owned());
+        }
+    }
+    redacted.sort();
+    redacted
+}
+
+/// Returns an excerpt of `text` between byte offsets `start` and `end`, with some context
+fn snippet(text: &str, start: usize, end: usize) -> String {
+    let mut before: Vec<_> = text[..start].chars().rev().take(SNIPPET_CONTEXT + 1).collect();
+    let mut after: Vec<_> = text[end..].chars().take(SNIPPET_CONTEXT + 1).collect();
+    let mut snippet = String::new();
+    if before.len() > SNIPPET_CONTEXT {
+        before.truncate(SNIPPET_CONTEXT);
+        snippet.push_str("...");
+    }
+    snippet.extend(before.into_iter().rev());
+    snippet.push_str(&text[start..end]);
+    let truncated = after.len() > SNIPPET_CONTEXT;
+    after.truncate(SNIPPET_CONTEXT);
+    snippet.extend(after);
+    if truncated {
+        snippet.push_str("...");
+    }
+    snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl Index {
+    /// Loads the index from a directory
+    ///
+    /// Returns an empty index if there's none or it can't be read
+    /// (it will be rebuilt upon [`update`])
+    ///
+    /// [`update`]: #method.update
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        fs::File::open(path.as_ref().join(INDEX_FILE)).ok()
+            .and_then(|file| serde_json::from_reader(file).ok())
+            .unwrap_or_default()
+    }
+
+    /// Saves the index to a directory
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+        // the index is local to this copy of the repository
+        let gitignore = path.join(".gitignore");
+        if !gitignore.exists() {
+            fs::File::create(gitignore)?.write_all(b"*\n")?;
+        }
+        let file = fs::File::create(path.join(INDEX_FILE))?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    /// Returns the number of indexed records
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns `true` if no records are indexed
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Indexes records that weren't indexed yet, re-indexes records that were
+    /// redacted since and forgets records that are gone
+    ///
+    /// Returns the number of records (re-)indexed.
+    pub fn update<RC: RecordContainer>(&mut self, container: &RC) -> Result<usize, RC::Error> {
+        let mut present = HashSet::new();
+        let mut indexed = 0;
+        let mut evicted = false;
+        for generation in container.record_iter()? {
+            for record in generation {
+                let hash = record.encoded_hash().as_ref().to_owned();
+                present.insert(hash.clone());
+                let redacted = redacted(&record);
+                let previous = self.records.get(&hash).map(|record| (record.seq, record.redacted == redacted));
+                let seq = match previous {
+                    Some((_, true)) => continue,
+                    Some((seq, false)) => {
+                        // forget terms of the previous contents
+                        for records in self.terms.values_mut() {
+                            records.remove(&hash);
+                        }
+                        evicted = true;
+                        seq
+                    },
+                    None => {
+                        self.seq += 1;
+                        self.seq - 1
+                    },
+                };
+                let (files, parents) = contents(&record);
+                for (_, text) in files {
+                    for (_, _, term) in tokenize(&text) {
+                        self.terms.entry(term).or_insert_with(BTreeSet::new).insert(hash.clone());
+                    }
+                }
+                self.records.insert(hash, IndexedRecord { seq, parents, redacted });
+                indexed += 1;
+            }
+        }
+        if evicted {
+            let terms = ::std::mem::replace(&mut self.terms, BTreeMap::new());
+            self.terms = terms.into_iter().filter(|&(_, ref records)| !records.is_empty()).collect();
+        }
+        if present.len() != self.records.len() {
+            let records = ::std::mem::replace(&mut self.records, BTreeMap::new());
+            self.records = records.into_iter().filter(|&(ref hash, _)| present.contains(hash)).collect();
+            let terms = ::std::mem::replace(&mut self.terms, BTreeMap::new());
+            self.terms = terms.into_iter()
+                .map(|(term, records)| (term, records.into_iter().filter(|hash| present.contains(hash)).collect::<BTreeSet<_>>()))
+                .filter(|&(_, ref records)| !records.is_empty())
+                .collect();
+        }
+        Ok(indexed)
+    }
+
+    /// Returns roots of record's history
+    fn roots(&self, hash: &str) -> Vec<String> {
+        let mut roots = BTreeSet::new();
+        let mut visited = HashSet::new();
+        let mut queue = vec![hash.to_owned()];
+        while let Some(hash) = queue.pop() {
+            if !visited.insert(hash.clone()) {
+                continue;
+            }
+            match self.records.get(&hash) {
+                Some(record) if !record.parents.is_empty() => queue.extend(record.parents.iter().cloned()),
+                _ => {
+                    roots.insert(hash);
+                },
+            }
+        }
+        roots.into_iter().collect()
+    }
+
+    /// Finds records containing all terms of the query, most recently indexed first
+    ///
+    /// `record` is used to retrieve records by their (encoded) hash; results are only
+    /// returned for records whose current contents still contain all terms.
+    pub fn search<F, R>(&self, query: &str, record: F) -> Vec<SearchResult>
+        where F: Fn(&str) -> Option<R>, R: Record {
+        let terms: BTreeSet<_> = tokenize(query).into_iter().map(|(_, _, term)| term).collect();
+        let mut candidates: Option<BTreeSet<&String>> = None;
+        for term in terms.iter() {
+            let records: BTreeSet<_> = self.terms.get(term).map(|records| records.iter().collect()).unwrap_or_default();
+            candidates = Some(match candidates {
+                None => records,
+                Some(candidates) => candidates.intersection(&records).cloned().collect(),
+            });
+        }
+        let mut candidates: Vec<_> = candidates.unwrap_or_default().into_iter().collect();
+        candidates.sort_by_key(|hash| ::std::cmp::Reverse(self.records[*hash].seq));
+
+        let mut results = vec![];
+        for hash in candidates {
+            let rec = match record(hash.as_str()) {
+                Some(rec) => rec,
+                None => continue,
+            };
+            let (files, _) = contents(&rec);
+            let mut found = BTreeSet::new();
+            let mut first = None;
+            for (name, text) in files.iter() {
+                for (start, end, term) in tokenize(text) {
+                    if terms.contains(&term) {
+                        if first.is_none() {
+                            first = Some(SearchResult {
+                                record: hash.clone(),
+                                roots: self.roots(hash),
+                                file: name.clone(),
+                                snippet: snippet(text, start, end),
+                            });
+                        }
+                        found.insert(term);
+                    }
+                }
+            }
+            if found.len() == terms.len() {
+                results.extend(first);
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use record::RecordOwningContainer;
+    use repository::Repository;
+
+    #[test]
+    fn tokenization() {
+        let terms: Vec<_> = tokenize("Flaky test, again!").into_iter().map(|(_, _, t)| t).collect();
+        assert_eq!(terms, vec!["flaky", "test", "again"]);
+        assert_eq!(tokenize("  Ünïcode ")[0], (2, 11, "ünïcode".to_owned()));
+    }
+
+    #[test]
+    fn snippets() {
+        assert_eq!(snippet("a\nflaky test", 2, 7), "a flaky test");
+        let text = format!("{}flaky{}", "x ".repeat(30), " y".repeat(30));
+        let s = snippet(&text, 60, 65);
+        assert!(s.starts_with("...") && s.ends_with("..."));
+        assert!(s.contains("flaky"));
+    }
+
+    #[test]
+    fn search() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        let root = repo.new_record(vec![("text", &b"Tests are flaky"[..])].into_iter(), false).unwrap();
+        let comment = repo.new_record(vec![("text", &b"That flaky test again"[..]), (".type/Commented", &b""[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![("image", &b"\xff\xfe"[..])].into_iter(), true).unwrap();
+
+        let mut index = Index::default();
+        assert_eq!(index.update(&repo).unwrap(), 3);
+        assert_eq!(index.update(&repo).unwrap(), 0);
+
+        let results = index.search("FLAKY", |hash| repo.record(hash));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].record, comment.encoded_hash());
+        assert_eq!(results[0].roots, vec![root.encoded_hash()]);
+        assert_eq!(results[0].file, "text");
+        assert_eq!(results[0].snippet, "That flaky test again");
+        assert_eq!(results[1].record, root.encoded_hash());
+
+        // all terms must match
+        let results = index.search("flaky again", |hash| repo.record(hash));
+        assert_eq!(results.len(), 1);
+        assert!(index.search("flaky nothing", |hash| repo.record(hash)).is_empty());
+        // hidden files are not indexed
+        assert!(index.search("commented", |hash| repo.record(hash)).is_empty());
+    }
+
+    #[test]
+    fn persistence() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        repo.new_record(vec![("text", &b"Hello"[..])].into_iter(), false).unwrap();
+        let path = repo.search_index_path();
+        assert!(Index::load(&path).is_empty());
+        let mut index = Index::default();
+        index.update(&repo).unwrap();
+        index.save(&path).unwrap();
+        assert_eq!(Index::load(&path), index);
+    }
+
+    #[test]
+    fn redacted_terms() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        // redacted records are only listed when redactions are verified
+        let repo = Repository::new(tmp).unwrap().with_integrity_check(false);
+        let record = repo.new_record(vec![("text", &b"Secret password"[..]), ("other", &b"Public"[..])].into_iter(), false).unwrap();
+        let path = repo.search_index_path();
+        let mut index = Index::default();
+        index.update(&repo).unwrap();
+        index.save(&path).unwrap();
+        assert!(index.terms.contains_key("password"));
+
+        let redaction = repo.redaction(&record, &["text"]).unwrap();
+        repo.new_record(redaction.files(), true).unwrap();
+        repo.redact(&redaction).unwrap();
+
+        let mut index = Index::load(&path);
+        // the redaction record and the redacted record
+        assert_eq!(index.update(&repo).unwrap(), 2);
+        index.save(&path).unwrap();
+        let index = Index::load(&path);
+        assert!(!index.terms.contains_key("secret"));
+        assert!(!index.terms.contains_key("password"));
+        assert_eq!(index.terms["public"], vec![record.encoded_hash()].into_iter().collect());
+        assert_eq!(index.records[&record.encoded_hash()].redacted, vec![String::from("text")]);
+    }
+}
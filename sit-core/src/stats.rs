@@ -0,0 +1,154 @@
+//! Aggregations and statistics
+//!
+//! Values (such as reduced items or records) are grouped by a JMESPath key
+//! expression and counted. If the key evaluates to an array (for example, labels),
+//! the value is counted in every group the array lists.
+//!
+//! Optionally, a numeric metric (another JMESPath expression) is aggregated for every group.
+//! Values the metric is not a number for are not included into the metric. Time-based metrics
+//! can be expressed using SIT's query functions (see [`query`]), for example:
+//!
+//! * records per week: grouping records by `week(files.".timestamp")`
+//! * mean time to close: grouping items by `state` with `days_between(created_at, closed_at)` metric
+//!
+//! [`query`]: ../query/index.html
+
+use std::cmp::Ordering;
+
+use jmespath::{Expression, JmespathError, Variable};
+use serde_json::{self, Value as JsonValue};
+
+use query;
+
+/// Aggregated metric
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metric {
+    /// Number of values the metric was computed for
+    pub count: usize,
+    /// Sum
+    pub sum: f64,
+    /// Arithmetic mean
+    pub mean: f64,
+    /// Median
+    pub median: f64,
+    /// Minimum
+    pub min: f64,
+    /// Maximum
+    pub max: f64,
+}
+
+impl Metric {
+    fn new(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let count = values.len();
+        let sum: f64 = values.iter().sum();
+        let median = if count % 2 == 0 {
+            (values[count / 2 - 1] + values[count / 2]) / 2.0
+        } else {
+            values[count / 2]
+        };
+        Some(Metric {
+            count,
+            sum,
+            mean: sum / count as f64,
+            median,
+            min: values[0],
+            max: values[count - 1],
+        })
+    }
+}
+
+/// Group of values sharing the same key
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Group {
+    /// Key
+    pub key: JsonValue,
+    /// Number of values in the group
+    pub count: usize,
+    /// Aggregated metric (if requested and it was a number for any of the values)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metric: Option<Metric>,
+}
+
+/// Groups values by `key` and aggregates `metric` (if any) for every group
+///
+/// Groups are ordered by their keys (see [`query::compare`]).
+///
+/// [`query::compare`]: ../query/fn.compare.html
+pub fn aggregate<'a, I>(values: I, key: &Expression, metric: Option<&Expression>) -> Result<Vec<Group>, JmespathError>
+    where I: IntoIterator<Item = &'a Variable> {
+    let mut groups: Vec<(Variable, usize, Vec<f64>)> = vec![];
+    for value in values {
+        let keys = key.search(value)?;
+        let keys = match keys.as_array() {
+            Some(keys) => keys.iter().map(|key| (**key).clone()).collect(),
+            None => vec![(*keys).clone()],
+        };
+        let number = match metric {
+            Some(metric) => metric.search(value)?.as_number(),
+            None => None,
+        };
+        for key in keys {
+            let position = groups.iter().position(|&(ref k, _, _)| *k == key);
+            let group = match position {
+                Some(position) => &mut groups[position],
+                None => {
+                    groups.push((key, 0, vec![]));
+                    groups.last_mut().unwrap()
+                },
+            };
+            group.1 += 1;
+            group.2.extend(number);
+        }
+    }
+    groups.sort_by(|&(ref a, _, _), &(ref b, _, _)| query::compare(a, b));
+    Ok(groups.into_iter().map(|(key, count, numbers)| Group {
+        key: serde_json::to_value(&key).unwrap(),
+        count,
+        metric: Metric::new(numbers),
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(json: &str) -> Vec<Variable> {
+        serde_json::from_str::<Vec<JsonValue>>(json).unwrap().into_iter().map(Variable::from).collect()
+    }
+
+    #[test]
+    fn counts() {
+        let items = values(r#"[{"state": "open"}, {"state": "closed"}, {"state": "open"}, {}]"#);
+        let groups = aggregate(&items, &query::compile("state").unwrap(), None).unwrap();
+        assert_eq!(groups.iter().map(|g| (g.key.clone(), g.count)).collect::<Vec<_>>(),
+                   vec![(JsonValue::from("closed"), 1), (JsonValue::from("open"), 2), (JsonValue::Null, 1)]);
+        assert!(groups.iter().all(|g| g.metric.is_none()));
+    }
+
+    #[test]
+    fn arrays() {
+        let items = values(r#"[{"labels": ["bug", "ui"]}, {"labels": ["bug"]}, {"labels": []}]"#);
+        let groups = aggregate(&items, &query::compile("labels").unwrap(), None).unwrap();
+        assert_eq!(groups.iter().map(|g| (g.key.clone(), g.count)).collect::<Vec<_>>(),
+                   vec![(JsonValue::from("bug"), 2), (JsonValue::from("ui"), 1)]);
+    }
+
+    #[test]
+    fn metrics() {
+        let items = values(r#"[
+          {"state": "closed", "created_at": "2018-01-01", "closed_at": "2018-01-02"},
+          {"state": "closed", "created_at": "2018-01-01", "closed_at": "2018-01-04"},
+          {"state": "closed", "created_at": "2018-01-01", "closed_at": "2018-01-11"},
+          {"state": "open", "created_at": "2018-01-01"}
+        ]"#);
+        let groups = aggregate(&items, &query::compile("state").unwrap(),
+                               Some(&query::compile("days_between(created_at, closed_at)").unwrap())).unwrap();
+        assert_eq!(groups[0].metric, Some(Metric { count: 3, sum: 14.0, mean: 14.0 / 3.0, median: 3.0, min: 1.0, max: 10.0 }));
+        assert_eq!(groups[1].count, 1);
+        assert_eq!(groups[1].metric, None);
+    }
+}
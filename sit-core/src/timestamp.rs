@@ -0,0 +1,183 @@
+//! Timestamps
+//!
+//! Records' `.timestamp` files contain ISO-8601 timestamps; they are parsed into
+//! milliseconds since the epoch (as used by JavaScript's `Date`).
+
+/// Parses an ISO-8601 timestamp into milliseconds since the epoch
+///
+/// Supports `YYYY-MM-DD`, optionally followed by `THH:MM[:SS[.FRACTION]]` and
+/// a time zone (`Z` or `±HH[:]MM`). Timestamps without time zone are assumed to be in UTC.
+pub fn parse(timestamp: &str) -> Option<i64> {
+    let s = timestamp.trim().as_bytes();
+    let mut pos = 0;
+    let number = |pos: &mut usize, digits: usize| -> Option<i64> {
+        let part = s.get(*pos..*pos + digits)?;
+        if !part.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        *pos += digits;
+        Some(part.iter().fold(0, |n, d| n * 10 + (d - b'0') as i64))
+    };
+    fn expect(s: &[u8], pos: &mut usize, c: u8) -> Option<()> {
+        if s.get(*pos) == Some(&c) {
+            *pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+    let year = number(&mut pos, 4)?;
+    expect(s, &mut pos, b'-')?;
+    let month = number(&mut pos, 2)?;
+    expect(s, &mut pos, b'-')?;
+    let day = number(&mut pos, 2)?;
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let (mut hour, mut minute, mut second, mut millis, mut offset) = (0, 0, 0, 0, 0);
+    if s.get(pos) == Some(&b'T') || s.get(pos) == Some(&b't') || s.get(pos) == Some(&b' ') {
+        pos += 1;
+        hour = number(&mut pos, 2)?;
+        expect(s, &mut pos, b':')?;
+        minute = number(&mut pos, 2)?;
+        if expect(s, &mut pos, b':').is_some() {
+            second = number(&mut pos, 2)?;
+            if expect(s, &mut pos, b'.').is_some() || expect(s, &mut pos, b',').is_some() {
+                let start = pos;
+                while s.get(pos).map(u8::is_ascii_digit).unwrap_or(false) {
+                    pos += 1;
+                }
+                if pos == start {
+                    return None;
+                }
+                // only milliseconds are kept
+                millis = s[start..pos].iter().chain(b"00".iter()).take(3)
+                    .fold(0, |n, d| n * 10 + (d - b'0') as i64);
+            }
+        }
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        match s.get(pos) {
+            Some(&b'Z') | Some(&b'z') => pos += 1,
+            Some(&sign) if sign == b'+' || sign == b'-' => {
+                pos += 1;
+                let hours = number(&mut pos, 2)?;
+                let _ = expect(s, &mut pos, b':');
+                let minutes = number(&mut pos, 2)?;
+                offset = (hours * 60 + minutes) * if sign == b'-' { -1 } else { 1 };
+            },
+            _ => (),
+        }
+    }
+    if pos != s.len() {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86400 + hour * 3600 + (minute - offset) * 60 + second;
+    Some(seconds * 1000 + millis)
+}
+
+/// Returns current time in milliseconds since the epoch
+pub fn now() -> i64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() * 1000) as i64 + duration.subsec_millis() as i64,
+        Err(err) => -((err.duration().as_secs() * 1000) as i64 + err.duration().subsec_millis() as i64),
+    }
+}
+
+/// Returns ISO-8601 week (`YYYY-Www`) of a timestamp (in milliseconds since the epoch)
+pub fn week(timestamp: i64) -> String {
+    let days = if timestamp >= 0 { timestamp / 86_400_000 } else { (timestamp - 86_399_999) / 86_400_000 };
+    // 1970-01-01 was Thursday (Monday is 0)
+    let weekday = (days % 7 + 7 + 3) % 7;
+    // ISO week belongs to the year its Thursday is in
+    let thursday = days - weekday + 3;
+    let (year, _, _) = civil_from_days(thursday);
+    format!("{:04}-W{:02}", year, (thursday - days_from_civil(year, 1, 1)) / 7 + 1)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 (see http://howardhinnant.github.io/date_algorithms.html)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Year, month and day of a day since 1970-01-01 (see [`days_from_civil`])
+///
+/// [`days_from_civil`]: fn.days_from_civil.html
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse("1970-01-01"), Some(0));
+        assert_eq!(parse("1970-01-01T00:00:01Z"), Some(1000));
+        assert_eq!(parse("2018-06-14T09:35:11.123456789Z"), Some(1528968911123));
+        assert_eq!(parse(" 2018-06-14T09:35:11.1Z\n"), Some(1528968911100));
+        assert_eq!(parse("2018-06-14T11:35:11+02:00"), Some(1528968911000));
+        assert_eq!(parse("2018-06-14T04:35-0500"), Some(1528968900000));
+        assert_eq!(parse("2000-02-29T00:00:00Z"), Some(951782400000));
+        assert_eq!(parse("1969-12-31T23:59:59Z"), Some(-1000));
+    }
+
+    #[test]
+    fn weeks() {
+        assert_eq!(week(parse("2018-01-01").unwrap()), "2018-W01");
+        assert_eq!(week(parse("2018-01-07T23:59:59Z").unwrap()), "2018-W01");
+        assert_eq!(week(parse("2018-01-08").unwrap()), "2018-W02");
+        assert_eq!(week(parse("2016-01-01").unwrap()), "2015-W53");
+        assert_eq!(week(parse("2018-12-31").unwrap()), "2019-W01");
+        assert_eq!(week(parse("1969-12-31").unwrap()), "1970-W01");
+    }
+
+    #[test]
+    fn civil() {
+        for &days in &[-1, 0, 59, 10_957, 17_532] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn invalid_timestamps() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("yesterday"), None);
+        assert_eq!(parse("2018-13-01"), None);
+        assert_eq!(parse("2019-02-29"), None);
+        assert_eq!(parse("2018-06-14T25:00:00Z"), None);
+        assert_eq!(parse("2018-06-14T09:35:11.Z"), None);
+        assert_eq!(parse("2018-06-14T09:35:11Z trailing"), None);
+    }
+}
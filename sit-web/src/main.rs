@@ -17,7 +17,7 @@ extern crate serde_json;
 #[macro_use] extern crate serde_derive;
 
 extern crate config;
-use sit_core::cfg;
+use sit_core::{cfg, encryption::GnuPG, policy::Verifier};
 
 extern crate directories;
 
@@ -122,8 +122,13 @@ fn main() {
     let repo_path = matches.value_of("repository").map(PathBuf::from)
         .or_else(|| sit_core::Repository::find_in_or_above(".sit",&working_dir))
         .expect("Can't find a repository");
-    let repo = sit_core::Repository::open(&repo_path)
+    let mut repo = sit_core::Repository::open(&repo_path)
         .expect("can't open repository");
+    // redaction records are only honoured if their signatures can be verified
+    let verifier = gnupg(&config).ok().map(|program| Verifier::new(GnuPG::new(program),
+                                                                   repo.config().hashing_algorithm().clone(),
+                                                                   repo.config().encoding().clone()));
+    repo.set_verifier(verifier);
 
     let listen = matches.value_of("listen").unwrap();
     let readonly = matches.is_present("readonly");
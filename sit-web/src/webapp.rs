@@ -64,8 +64,9 @@ use std::path::PathBuf;
 use std::fs;
 use std::net::ToSocketAddrs;
 
-use sit_core::{Repository, repository, reducers::duktape::{self, DuktapeReducer}, record::OrderedFiles,
-               record::{RecordContainer, RecordContainerReduction, RecordOwningContainer}, path::{HasPath, ResolvePath}};
+use sit_core::{Repository, repository, reducers::duktape::{self, DuktapeReducer, Logging, LogEntry}, record::OrderedFiles,
+               record::{RecordContainer, RecordContainerReduction, RecordOwningContainer}, path::{HasPath, ResolvePath},
+               encryption::GnuPG, policy::{Policy, Verifier, AuthorizedReducer}};
 use std::io::Cursor;
 
 use mime_guess::get_mime_type_str;
@@ -153,8 +154,20 @@ fn new_record<C: RecordOwningContainer, MI>(container: &C, request: &Request, re
         }
     }
 
+    // parent links are added upfront, so that the signature covers them
+    let parents = if link {
+        repo.parent_links().map_err(|err| format!("can't list parent records: {:?}", err))?
+    } else {
+        vec![]
+    };
+    let parent_files = || -> OrderedFiles<(String, &[u8])> {
+        parents.iter().map(|name| (name.clone(), &b""[..])).into()
+    };
+
     let files: OrderedFiles<_> = used_files.iter().map(|(n, p)| (n.clone(), fs::File::open(p).expect("can't open saved file"))).into();
+    let files = files + parent_files();
     let files_: OrderedFiles<_> = used_files.iter().map(|(n, p)| (n.clone(), fs::File::open(p).expect("can't open saved file"))).into();
+    let files_ = files_ + parent_files();
 
     let files: OrderedFiles<_> = if config.signing.enabled {
         use std::ffi::OsString;
@@ -202,10 +215,10 @@ fn new_record<C: RecordOwningContainer, MI>(container: &C, request: &Request, re
         }
 
     } else {
-        files.boxed()
+        files
     };
 
-    let record = container.new_record(files, link).expect("can't create record");
+    let record = container.new_record(files, false).expect("can't create record");
 
     for (_, file) in used_files {
         fs::remove_file(file).expect("can't remove file");
@@ -214,9 +227,19 @@ fn new_record<C: RecordOwningContainer, MI>(container: &C, request: &Request, re
     Ok(record)
 }
 
+/// Reduces records of the container
+///
+/// Encrypted records are never decrypted, as that would hand their content
+/// to any HTTP client with the keys of whoever runs the server.
 fn reduce<MI, RCR: RecordContainerReduction<Record = repository::Record>>
-    (repo: &Repository<MI>, container: &RCR, request: &Request, query_expr: String) -> Response
+    (repo: &Repository<MI>, container: &RCR, request: &Request, query_expr: String,
+     limits: &sit_core::cfg::Reducers) -> Response
     where MI: repository::ModuleIterator<PathBuf, repository::Error> {
+    let policy = match repo.policy() {
+        Ok(policy) => policy,
+        Err(_) => return Response::text("can't read authorization policy").with_status_code(500),
+    };
+    let verifier = repo.verifier().cloned();
     if let Some(vals) = request.get_param("reducers") {
         let reducers_path = repo.path().join("reducers");
         let reducers = vals.split(",").map(PathBuf::from)
@@ -228,26 +251,40 @@ fn reduce<MI, RCR: RecordContainerReduction<Record = repository::Record>>
             } else {
                 p
             });
-        return reduce__(container, request, query_expr, reducers)
+        return reduce__(container, request, query_expr, reducers, limits, policy, verifier)
     } else {
-        return reduce__(container, request, query_expr, repo)
+        return reduce__(container, request, query_expr, repo, limits, policy, verifier)
     }
     // implementation
     fn reduce__<RCR: RecordContainerReduction<Record = repository::Record>, SF: duktape::SourceFiles>
-        (container: &RCR, request: &Request, query_expr: String, source_files: SF) -> Response {
+        (container: &RCR, request: &Request, query_expr: String, source_files: SF,
+         limits: &sit_core::cfg::Reducers, policy: Policy, verifier: Option<Verifier>) -> Response {
             use jmespath;
-            let reducer = sit_core::reducers::duktape::DuktapeReducer::new(source_files).unwrap();
-            let query = match jmespath::compile(&query_expr) {
+            // `logs` parameter requests reducers' console output along with the result
+            let logging = if request.get_param("logs").is_some() { Logging::Collect } else { Logging::Disabled };
+            let reducer = sit_core::reducers::duktape::DuktapeReducer::new(source_files).unwrap()
+                .with_limits(limits).with_logging(logging);
+            let reducer = policy.reducer(reducer, verifier);
+            let query = match sit_core::query::compile(&query_expr) {
                 Ok(query) => query,
                 _ => return Response::empty_400(),
             };
+            #[derive(Serialize)]
+            struct Logged {
+                result: jmespath::Variable,
+                logs: Vec<LogEntry>,
+            }
             fn reduce_<RCR: RecordContainerReduction<Record = repository::Record>>
-                (container: &RCR, query: jmespath::Expression, mut reducer: duktape::DuktapeReducer<repository::Record>, state: serde_json::Value)-> Response {
+                (container: &RCR, query: jmespath::Expression, mut reducer: AuthorizedReducer<duktape::DuktapeReducer<repository::Record>>, state: serde_json::Value)-> Response {
                     let state = container.initialize_state(state.as_object().unwrap().to_owned());
                     let reduced = container.reduce_with_reducer_and_state(&mut reducer, state).unwrap();
                     let data = jmespath::Variable::from(serde_json::Value::Object(reduced));
                     let result = query.search(&data).unwrap();
-                    Response::json(&result)
+                    if reducer.reducer().logging() == Logging::Collect {
+                        Response::json(&Logged { result: (*result).clone(), logs: reducer.reducer_mut().take_logs() })
+                    } else {
+                        Response::json(&result)
+                    }
             }
             if let Some(state) = request.get_param("state") {
                 reduce_(container, query, reducer, serde_json::from_str(&state).unwrap())
@@ -282,6 +319,8 @@ pub fn start<A: ToSocketAddrs, MI: 'static + Send + Sync>(addr: A, config: sit_c
     let repo_config = Config {
       readonly,
     };
+    // full-text search index is shared by all requests
+    let search_index = Mutex::new(sit_core::search::Index::load(repo.search_index_path()));
     start_server(addr, move |request|
         router!(request,
         (GET) (/user/config) => {
@@ -294,45 +333,95 @@ pub fn start<A: ToSocketAddrs, MI: 'static + Send + Sync>(addr: A, config: sit_c
         #[cfg(feature = "deprecated-items")] {
             use jmespath;
             use sit_core::record::RecordContainerReduction;
-            let items: Vec<_> = repo.item_iter().expect("can't list items").collect();
-            let mut reducer = Arc::new(Mutex::new(sit_core::reducers::duktape::DuktapeReducer::new(&repo).unwrap()));
-            let tl_reducer: ThreadLocal<RefCell<DuktapeReducer<sit_core::repository::Record>>>= ThreadLocal::new();
+            use sit_core::{Item, Reducer};
+            use sit_core::reducers::{ChainedReducer, native::NativeReducers};
+            let mut items: Vec<_> = repo.item_iter().expect("can't list items").collect();
+            // items are listed in a stable order so that cursors are meaningful
+            items.sort_by(|a, b| a.id().cmp(b.id()));
+            // `cursor` parameter continues listing after the item it names
+            if let Some(cursor) = request.get_param("cursor") {
+                items.retain(|item| item.id() > cursor.as_str());
+            }
+            // `limit` parameter requests a page of results
+            let limit = match request.get_param("limit").map(|limit| limit.parse::<usize>()) {
+                None => None,
+                Some(Ok(limit)) if limit > 0 => Some(limit),
+                _ => return Response::empty_400(),
+            };
+            let policy = match repo.policy() {
+                Ok(policy) => policy,
+                Err(_) => return Response::text("can't read authorization policy").with_status_code(500),
+            };
+            let verifier = super::gnupg(&config).ok().map(|program| Verifier::new(GnuPG::new(program), repo.config().hashing_algorithm().clone(),
+                                                                                  repo.config().encoding().clone()));
+            let reducer = sit_core::reducers::duktape::DuktapeReducer::new(&repo).unwrap().with_limits(&config.reducers);
+            // natively reduced types are handled before JavaScript reducers
+            let reducer = NativeReducers::from_config(repo.config()).chain(reducer);
+            // records not authorized by the policy are skipped
+            let mut reducer = Arc::new(Mutex::new(policy.reducer(reducer, verifier)));
+            let tl_reducer: ThreadLocal<RefCell<AuthorizedReducer<ChainedReducer<NativeReducers<sit_core::repository::Record>,
+                                                                                 DuktapeReducer<sit_core::repository::Record>>>>>= ThreadLocal::new();
 
             let filter_defined = filter_expr != "";
             let filter = if filter_defined {
-                match jmespath::compile(&filter_expr) {
+                match sit_core::query::compile(&filter_expr) {
                   Ok(filter) => filter,
                   _ => return Response::empty_400(),
                 }
             } else {
-                jmespath::compile("`true`").unwrap()
+                sit_core::query::compile("`true`").unwrap()
             };
-            let query = match jmespath::compile(&query_expr) {
+            let query = match sit_core::query::compile(&query_expr) {
                 Ok(query) => query,
                 _ => return Response::empty_400(),
             };
 
-            let result: Vec<_> =
-            items.into_par_iter()
-                  .map(|item| {
-                     let mut reducer = tl_reducer.get_or(|| Box::new(RefCell::new(reducer.lock().unwrap().clone()))).borrow_mut();
-                     reducer.reset_state();
-                     item.reduce_with_reducer(&mut *reducer).unwrap()
-                  }).map(|json| {
-                     let data = jmespath::Variable::from(serde_json::Value::Object(json));
-                     let result = if filter_defined {
-                        let res = filter.search(&data).unwrap();
-                        res.is_boolean() && res.as_boolean().unwrap()
-                     } else {
-                        true
-                     };
-                     if result {
-                        Some(query.search(&data).unwrap())
-                     } else {
-                        None
-                     }
-                  })
-                 .filter(Option::is_some).collect();
+            // when paginating, items are reduced in batches until the page is filled
+            let batch = limit.unwrap_or(items.len()).max(1);
+            let mut result = vec![];
+            let mut next = None;
+            while !items.is_empty() {
+                let len = batch.min(items.len());
+                let chunk: Vec<_> = items.drain(..len).collect();
+                let views: Vec<_> = chunk.into_par_iter()
+                      .map(|item| {
+                         let mut reducer = tl_reducer.get_or(|| Box::new(RefCell::new(reducer.lock().unwrap().clone()))).borrow_mut();
+                         reducer.reducer_mut().second_mut().reset_state();
+                         (item.id().to_owned(), item.reduce_with_reducer(&mut *reducer).unwrap())
+                      }).filter_map(|(id, json)| {
+                         let data = jmespath::Variable::from(serde_json::Value::Object(json));
+                         let result = if filter_defined {
+                            let res = filter.search(&data).unwrap();
+                            res.is_boolean() && res.as_boolean().unwrap()
+                         } else {
+                            true
+                         };
+                         if result {
+                            Some((id, query.search(&data).unwrap()))
+                         } else {
+                            None
+                         }
+                      }).collect();
+                result.extend(views);
+                if let Some(limit) = limit {
+                    if result.len() >= limit {
+                        if result.len() > limit || !items.is_empty() {
+                            next = Some(result[limit - 1].0.clone());
+                        }
+                        result.truncate(limit);
+                        break;
+                    }
+                }
+            }
+            let result: Vec<_> = result.into_iter().map(|(_, view)| view).collect();
+            if limit.is_some() {
+                #[derive(Serialize)]
+                struct Page {
+                    items: Vec<jmespath::Rcvar>,
+                    next: Option<String>,
+                }
+                return Response::json(&Page { items: result, next });
+            }
             Response::json(&result)
           }
         #[cfg(not(feature = "deprecated-items"))] {
@@ -343,9 +432,20 @@ pub fn start<A: ToSocketAddrs, MI: 'static + Send + Sync>(addr: A, config: sit_c
         #[cfg(feature = "deprecated-items")] {
             use jmespath;
             use sit_core::record::RecordContainerReduction;
-            use sit_core::Item;
-            let mut reducer = sit_core::reducers::duktape::DuktapeReducer::new(&repo).unwrap();
-            let query = match jmespath::compile(&query_expr) {
+            use sit_core::{Item, Reducer};
+            use sit_core::reducers::native::NativeReducers;
+            let policy = match repo.policy() {
+                Ok(policy) => policy,
+                Err(_) => return Response::text("can't read authorization policy").with_status_code(500),
+            };
+            let verifier = super::gnupg(&config).ok().map(|program| Verifier::new(GnuPG::new(program), repo.config().hashing_algorithm().clone(),
+                                                                                  repo.config().encoding().clone()));
+            let reducer = sit_core::reducers::duktape::DuktapeReducer::new(&repo).unwrap().with_limits(&config.reducers);
+            // natively reduced types are handled before JavaScript reducers
+            let reducer = NativeReducers::from_config(repo.config()).chain(reducer);
+            // records not authorized by the policy are skipped
+            let mut reducer = policy.reducer(reducer, verifier);
+            let query = match sit_core::query::compile(&query_expr) {
                 Ok(query) => query,
                 _ => return Response::empty_400(),
             };
@@ -364,10 +464,26 @@ pub fn start<A: ToSocketAddrs, MI: 'static + Send + Sync>(addr: A, config: sit_c
         },
         (GET) (/api/{roots: String}/reduce/{query_expr: String}) => {
             let container = repo.fixed_roots(roots.split(","));
-            reduce(&repo, &container, &request, query_expr)
+            reduce(&repo, &container, &request, query_expr, &config.reducers)
+        },
+        (GET) (/api/search) => {
+            // `q` parameter contains terms to search for
+            let terms = match request.get_param("q") {
+                Some(terms) => terms,
+                None => return Response::empty_400(),
+            };
+            let mut index = search_index.lock().unwrap();
+            match index.update(&repo) {
+                Ok(n) if n > 0 => if let Err(err) = index.save(repo.search_index_path()) {
+                    eprintln!("Can't save search index: {}", err);
+                },
+                Ok(_) => (),
+                Err(_) => return Response::text("can't index records").with_status_code(500),
+            }
+            Response::json(&index.search(&terms, |hash| repo.record(hash)))
         },
         (GET) (/api/reduce/{query_expr: String}) => {
-            reduce(&repo, &repo, &request, query_expr)
+            reduce(&repo, &repo, &request, query_expr, &config.reducers)
         },
         (GET) (/api/item/{id: String}/{record: String}/files) => { // DEPRECATED
         #[cfg(feature = "deprecated-items")] {
@@ -496,3 +612,34 @@ pub fn start<A: ToSocketAddrs, MI: 'static + Send + Sync>(addr: A, config: sit_c
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use std::io::Read;
+
+    /// Encrypted records are not decrypted for HTTP clients
+    #[test]
+    fn reduce_encrypted() {
+        let tmp = TempDir::new("sit-web").unwrap();
+        let mut repo = Repository::new(tmp.path().join(".sit")).unwrap();
+        // `echo` "decrypts" anything into its arguments, so any decryption would show
+        let verifier = Verifier::new(GnuPG::new("echo"), repo.config().hashing_algorithm().clone(),
+                                     repo.config().encoding().clone());
+        repo.set_verifier(Some(verifier));
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers").join("text.js"), r#"
+        module.exports = function(state, record) {
+            return Object.assign(state, {text: sit.decodeText(record.files.text)});
+        }
+        "#).unwrap();
+        repo.new_record(vec![(".encrypted", &b""[..]), ("text", &b"ciphertext"[..])].into_iter(), false).unwrap();
+
+        let request = Request::fake_http("GET", "/api/reduce/text", vec![], vec![]);
+        let response = reduce(&repo, &repo, &request, String::from("text"), &Default::default());
+        assert_eq!(response.status_code, 200);
+        let mut body = String::new();
+        response.data.into_reader_and_size().0.read_to_string(&mut body).unwrap();
+        assert_eq!(body, r#""ciphertext""#);
+    }
+}
@@ -1,4 +1,4 @@
-use jmespath;
+use sit_core;
 use serde_json;
 use serde::Serialize;
 
@@ -6,7 +6,7 @@ pub fn command<T: Serialize>(cfg: &T, query: Option<&str>) {
     match query {
         None => println!("{}", serde_json::to_string_pretty(cfg).unwrap()),
         Some(query_expr) => {
-            let query = jmespath::compile(query_expr).expect("can't compile query expression");
+            let query = sit_core::query::compile(query_expr).expect("can't compile query expression");
             let view = query.search(&cfg).unwrap();
             if view.is_string() {
                 println!("{}", view.as_string().unwrap().clone())
@@ -0,0 +1,33 @@
+use clap::ArgMatches;
+use sit_core::Repository;
+use sit_core::fsck::{self, Problem};
+use std::time::Duration;
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>) -> i32 {
+    let hours: u64 = matches.value_of("older-than").unwrap().parse().unwrap();
+    let problems = fsck::check(repo, Duration::from_secs(hours * 3600)).expect("can't check repository");
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        return 0;
+    }
+    if !matches.is_present("repair") {
+        return 1;
+    }
+    if problems.iter().any(Problem::quarantinable) {
+        match fsck::quarantine(repo, &problems) {
+            Ok(path) => eprintln!("Moved into quarantine, see {}", path.join(fsck::REPORT_FILE).to_str().unwrap()),
+            Err(err) => {
+                eprintln!("Error: can't move into quarantine: {:?}", err);
+                return 1;
+            },
+        }
+    }
+    // records with dangling parents are left intact
+    if problems.iter().all(Problem::quarantinable) {
+        0
+    } else {
+        1
+    }
+}
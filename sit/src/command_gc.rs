@@ -0,0 +1,34 @@
+use clap::ArgMatches;
+use sit_core::Repository;
+use sit_core::gc;
+use std::time::Duration;
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>) -> i32 {
+    let hours: u64 = matches.value_of("older-than").unwrap().parse().unwrap();
+    let dry_run = matches.is_present("dry-run");
+
+    let garbage = gc::collect(repo, Duration::from_secs(hours * 3600)).expect("can't collect garbage");
+    for garbage in garbage.iter() {
+        if dry_run {
+            println!("would remove {}", garbage);
+        } else {
+            println!("removing {}", garbage);
+        }
+    }
+    if !dry_run {
+        if let Err(err) = gc::remove(repo, &garbage) {
+            eprintln!("Error: can't remove garbage: {:?}", err);
+            return 1;
+        }
+    }
+
+    #[cfg(feature = "deprecated-items")] {
+        if matches.is_present("unreachable") {
+            for record in gc::unreachable_records(repo).expect("can't list records") {
+                println!("unreachable record {}", record);
+            }
+        }
+    }
+
+    0
+}
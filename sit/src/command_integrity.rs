@@ -1,9 +1,25 @@
-use sit_core::{Record, Repository, record::RecordContainer, hash::HashingAlgorithm};
+use clap::ArgMatches;
+use sit_core::{Record, Repository, record::RecordContainer, hash::HashingAlgorithm, cfg::Configuration};
+use super::verifier;
 #[cfg(feature = "deprecated-items")]
 use rayon::prelude::*;
 
-pub fn command<MI: Send + Sync>(mut repo: Repository<MI>) -> i32 {
+pub fn command<MI: Send + Sync>(matches: &ArgMatches, mut repo: Repository<MI>, config: Configuration) -> i32 {
     repo.set_integrity_check(false);
+    let policy_violations = if matches.is_present("policy") {
+        let policy = repo.policy().expect("can't read policy");
+        let verifier = verifier(matches, &config, &repo);
+        let mut violations = vec![];
+        for record in repo.record_iter().expect("can't list records").flat_map(|v| v) {
+            if let Err(violation) = policy.check(&record, verifier.as_ref()) {
+                eprintln!("{} {}", record.encoded_hash(), violation);
+                violations.push(record.encoded_hash());
+            }
+        }
+        violations
+    } else {
+        vec![]
+    };
     let hashing_algorithm = repo.config().hashing_algorithm().clone();
     #[cfg(not(feature = "deprecated-items"))]
     let invalid_records_in_items: Vec<String> = vec![];
@@ -27,7 +43,10 @@ pub fn command<MI: Send + Sync>(mut repo: Repository<MI>) -> i32 {
     for record in invalid_records.iter().filter(|r| !invalid_records_in_items.iter().any(|r_| r_ == &r.encoded_hash())) {
         println!("{}", record.encoded_hash());
     }
-    if valid_items && valid {
+    for hash in policy_violations.iter().filter(|h| !invalid_records.iter().any(|r| &r.encoded_hash() == *h)) {
+        println!("{}", hash);
+    }
+    if valid_items && valid && policy_violations.is_empty() {
         0
     } else {
         1
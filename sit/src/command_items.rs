@@ -1,8 +1,10 @@
 use clap::ArgMatches;
-use sit_core::{self, reducers::duktape::DuktapeReducer, Repository, record::RecordContainerReduction, cfg::Configuration};
+use sit_core::{self, Repository, record::RecordContainerReduction, cfg::Configuration, policy::AuthorizedReducer,
+               reducers::{ChainedReducer, native::NativeReducers}, Reducer};
 use serde_json;
 use rayon::prelude::*;
-use super::get_named_expression;
+use super::{get_named_expression, decryptor, verifier, limited, console, paginate, JsReducer};
+use format::Printer;
 use jmespath;
 
 use std::sync::{Arc, Mutex};
@@ -10,11 +12,9 @@ use std::cell::RefCell;
 use thread_local::ThreadLocal;
 use std::path::PathBuf;
 
-pub fn command<MI: Send + Sync>(matches: &ArgMatches, repo: &Repository<MI>, config: Configuration) -> i32
+pub fn command<MI: Send + Sync>(matches: &ArgMatches, repo: &Repository<MI>, config: Configuration, verbosity: u64) -> i32
     where MI: sit_core::repository::ModuleIterator<PathBuf, sit_core::repository::Error>
 {
-    let items: Vec<_> = repo.item_iter().expect("can't list items").collect();
-
     let filter_expr = matches.value_of("named-filter")
         .and_then(|name|
             get_named_expression(name, &repo, ".items/filters", &config.items.filters))
@@ -29,37 +29,45 @@ pub fn command<MI: Send + Sync>(matches: &ArgMatches, repo: &Repository<MI>, con
         .or_else(|| matches.value_of("query").or_else(|| Some("id")).map(String::from))
         .unwrap();
 
-    let filter = jmespath::compile(&filter_expr).expect("can't compile filter expression");
-    let query = jmespath::compile(&query_expr).expect("can't compile query expression");
+    let filter = sit_core::query::compile(&filter_expr).expect("can't compile filter expression");
+    let query = sit_core::query::compile(&query_expr).expect("can't compile query expression");
 
-    let tl_reducer : ThreadLocal<RefCell<DuktapeReducer<sit_core::repository::Record>>> = ThreadLocal::new();
-    let reducer = Arc::new(Mutex::new(DuktapeReducer::new(repo).unwrap()));
+    let results: Vec<_> = reduce_items(matches, repo, &config, verbosity).into_iter()
+        .filter(|data| !filter_defined || filter.search(data).unwrap().as_boolean().unwrap())
+        .collect();
 
+    let mut printer = Printer::new(matches.value_of("format").unwrap(), &query);
+    for data in paginate(matches, results, |data| data) {
+        printer.print(&query.search(&data).unwrap());
+    }
+    printer.finish();
+    0
+}
+
+/// Reduces all items (in the order of their IDs)
+pub fn reduce_items<MI: Send + Sync>(matches: &ArgMatches, repo: &Repository<MI>, config: &Configuration, verbosity: u64) -> Vec<jmespath::Variable>
+    where MI: sit_core::repository::ModuleIterator<PathBuf, sit_core::repository::Error>
+{
+    use sit_core::Item;
+    let mut items: Vec<_> = repo.item_iter().expect("can't list items").collect();
+    // list items in a stable order
+    items.sort_by(|a, b| a.id().cmp(b.id()));
+
+    let policy = repo.policy().expect("can't read authorization policy");
+    let tl_reducer : ThreadLocal<RefCell<AuthorizedReducer<ChainedReducer<NativeReducers<sit_core::repository::Record>, JsReducer<sit_core::repository::Record>>>>> = ThreadLocal::new();
+    let reducer = console(limited(JsReducer::new(repo).unwrap().with_decryptor(decryptor(matches, config)), config), verbosity);
+    // natively reduced types are handled before JavaScript reducers
+    let reducer = NativeReducers::from_config(repo.config()).chain(reducer);
+    // records not authorized by the policy are skipped
+    let reducer = Arc::new(Mutex::new(policy.reducer(reducer, verifier(matches, config, repo))));
+
+    // results are collected so that their order doesn't depend on scheduling
     items.into_par_iter()
         .map(|item| {
             let mut reducer = tl_reducer.get_or(|| Box::new(RefCell::new(reducer.lock().unwrap().clone()))).borrow_mut();
-            reducer.reset_state();
+            reducer.reducer_mut().second_mut().reset_state();
             let result = item.reduce_with_reducer(&mut *reducer).expect("can't reduce item");
-            let data = jmespath::Variable::from(serde_json::Value::Object(result));
-            let result = if filter_defined {
-                filter.search(&data).unwrap().as_boolean().unwrap()
-            } else {
-                true
-            };
-            if result {
-                let view = query.search(&data).unwrap();
-                if view.is_string() {
-                    Some(view.as_string().unwrap().clone())
-                } else {
-                    Some(serde_json::to_string_pretty(&view).unwrap())
-                }
-            } else {
-                None
-            }
+            jmespath::Variable::from(serde_json::Value::Object(result))
         })
-        .filter(Option::is_some).map(Option::unwrap)
-        .for_each(|view| {
-            println!("{}", view);
-        });
-    0
+        .collect()
 }
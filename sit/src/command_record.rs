@@ -1,18 +1,21 @@
 use atty;
+use base64;
 use chrono::prelude::*;
 use clap::{self, ArgMatches};
 use dunce;
-use serde_json;
+use serde_json::{self, Value as JsonValue};
 use sit_core::cfg::{self, Configuration};
+use sit_core::encryption::{self, GnuPG};
 use sit_core::{
-    record::{BoxedOrderedFiles, OrderedFiles, RecordOwningContainer},
+    record::{BoxedOrderedFiles, File, OrderedFiles, RecordOwningContainer},
     Record, Repository
 };
 use std::env;
 use std::ffi::OsString;
 use std::fs;
-use std::io::{self, Cursor, ErrorKind, Write};
-use std::path::{Path, PathBuf};
+use std::io::{self, Cursor, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tempdir::TempDir;
 use walkdir::{self as walk, WalkDir};
 use itertools::Itertools;
 
@@ -26,16 +29,92 @@ pub const FILES_ARG_HELP: &str = "Optional item identifier followed by a collect
 #[cfg(not(feature = "deprecated-items"))]
 pub const FILES_ARG_HELP: &str = "Collection of files or folders the record will be built from";
 
-fn record_files(
-    matches: &ArgMatches, offset: usize,
-    utc: DateTime<Utc>,
-    config: &Configuration,
-) -> Result<BoxedOrderedFiles<'static>, io::Error> {
+/// Template of a file composed in the editor
+const EDIT_TEMPLATE: &str = "
+# Please enter the contents of `{name}` for the new record{types}.
+# Lines starting with '#' will be ignored, and an empty file aborts the record.
+";
+
+/// Composes a file in the editor (`$VISUAL`, `$EDITOR` or `vi`)
+///
+/// The file is edited in a temporary directory, so the working directory is left intact.
+/// Returns file's contents without comment lines, or `None` if nothing is left.
+fn compose(name: &str, types: &[&str]) -> Result<Option<Vec<u8>>, io::Error> {
+    let file_name = Path::new(name).file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} is not a valid file name", name)))?;
+    let tmp = TempDir::new("sit-record")?;
+    let path = tmp.path().join(file_name);
+    let types = if types.is_empty() {
+        String::new()
+    } else {
+        format!(" (types: {})", types.join(", "))
+    };
+    fs::File::create(&path)?.write_all(EDIT_TEMPLATE.replace("{name}", name).replace("{types}", &types).as_bytes())?;
+
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| String::from("vi"));
+    let mut editor = editor.split_whitespace();
+    let program = editor.next().unwrap_or("vi");
+    let status = ::std::process::Command::new(program).args(editor).arg(&path).status()?;
+    if !status.success() {
+        return Err(io::Error::new(ErrorKind::Other, format!("{} exited with {}", program, status)));
+    }
+
+    let mut content = String::new();
+    fs::File::open(&path)?.read_to_string(&mut content)?;
+    let lines: Vec<_> = content.lines().filter(|line| !line.starts_with('#')).collect();
+    let content = lines.join("\n");
+    let content = content.trim_end().trim_start_matches('\n');
+    if content.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(format!("{}\n", content).into_bytes()))
+    }
+}
+
+/// Reads files from a JSON object mapping file names to their contents
+///
+/// Contents are either strings or `{"base64": "..."}` objects (for binary files)
+fn json_files<R: Read>(reader: R) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let value: JsonValue = serde_json::from_reader(reader).map_err(|err| err.to_string())?;
+    let object = value.as_object()
+        .ok_or_else(|| String::from("expected an object mapping file names to their contents"))?;
+    object.iter().map(|(name, content)| match content.as_str() {
+        Some(text) => Ok((name.clone(), text.as_bytes().to_vec())),
+        None => match content.get("base64").and_then(JsonValue::as_str) {
+            Some(encoded) => base64::decode(encoded)
+                .map(|content| (name.clone(), content))
+                .map_err(|err| format!("invalid base64 contents of {}: {}", name, err)),
+            None => Err(format!("contents of {} must be a string or {{\"base64\": \"...\"}}", name)),
+        },
+    }).collect()
+}
+
+/// Parses `--file` argument: `name=value` or `name=@path`
+fn file_arg(arg: &str) -> Result<(String, Vec<u8>), String> {
+    let mut split = arg.splitn(2, '=');
+    let name = split.next().unwrap();
+    let value = match split.next() {
+        Some(value) if !name.is_empty() => value,
+        _ => return Err(format!("{} should be either name=value or name=@path", arg)),
+    };
+    if value.starts_with('@') {
+        let path = &value[1..];
+        let mut content = vec![];
+        fs::File::open(path).and_then(|mut file| file.read_to_end(&mut content))
+            .map_err(|err| format!("can't read {}: {}", path, err))?;
+        Ok((String::from(name), content))
+    } else {
+        Ok((String::from(name), value.as_bytes().to_vec()))
+    }
+}
+
+/// Lists names of files (and files in directories) from the working directory
+fn working_directory_names(matches: &ArgMatches, offset: usize) -> Result<Vec<String>, io::Error> {
     let files = matches
         .values_of(FILES_ARG)
         .unwrap_or(clap::Values::default());
 
-    let files: OrderedFiles<_> = files
+    files
         .dropping(offset)
         .into_iter()
         .map(|name| {
@@ -93,11 +172,79 @@ fn record_files(
                 }
             })
         })
-        .collect::<Result<Vec<_>, _>>()?
+        .collect::<Result<Vec<_>, _>>()
+}
+
+/// Collects files and directories from the working directory
+fn working_directory_files(matches: &ArgMatches, offset: usize) -> Result<BoxedOrderedFiles<'static>, io::Error> {
+    let files: OrderedFiles<_> = working_directory_names(matches, offset)?
         .iter()
         .map(|name| (name.clone(), fs::File::open(name).expect("can't open file")))
         .into();
 
+    Ok(files.boxed())
+}
+
+/// Checks that record's files have distinct names and don't replace auxiliary files
+fn check_names(matches: &ArgMatches, offset: usize, contents: &[(String, Vec<u8>)], signing: bool) -> Result<(), String> {
+    let mut names = if matches.is_present("edit") {
+        vec![]
+    } else {
+        working_directory_names(matches, offset).map_err(|err| err.to_string())?
+    };
+    names.extend(contents.iter().map(|&(ref name, _)| name.clone()));
+
+    let mut reserved: Vec<_> = matches.value_of("type").map(|types| types.split(",").map(|t| format!(".type/{}", t)).collect())
+        .unwrap_or_default();
+    if !matches.is_present("no-aux") && !matches.is_present("no-author") {
+        reserved.push(String::from(".authors"));
+    }
+    if !matches.is_present("no-aux") && !matches.is_present("no-timestamp") {
+        reserved.push(String::from(".timestamp"));
+    }
+    if signing {
+        reserved.push(String::from(".signature"));
+    }
+    if matches.is_present("encrypt") {
+        reserved.push(String::from(encryption::ENCRYPTED_FILE));
+    }
+
+    for (i, name) in names.iter().enumerate() {
+        if names[..i].contains(name) {
+            return Err(format!("{} is given more than once", name));
+        }
+        if reserved.contains(name) {
+            return Err(format!("{} is produced by sit and can't be given", name));
+        }
+        // parent links are produced by sit, too
+        let first = Path::new(name).components().find(|c| match *c {
+            Component::Normal(_) => true,
+            _ => false,
+        });
+        if first == Some(Component::Normal(".prev".as_ref())) {
+            return Err(format!("{} is produced by sit and can't be given", name));
+        }
+    }
+    Ok(())
+}
+
+fn record_files(
+    matches: &ArgMatches, offset: usize,
+    utc: DateTime<Utc>,
+    config: &Configuration,
+    contents: &[(String, Vec<u8>)],
+) -> Result<BoxedOrderedFiles<'static>, io::Error> {
+    let contents: OrderedFiles<_> = contents.iter()
+        .map(|&(ref name, ref content)| (name.clone(), Cursor::new(content.clone())))
+        .into();
+
+    // with --edit, FILES are names of composed files rather than paths
+    let files = if matches.is_present("edit") {
+        contents.boxed()
+    } else {
+        working_directory_files(matches, offset)? + contents
+    };
+
     let types: Vec<_> = match matches.value_of("type") {
         Some(types) => types.split(",").collect(),
         None => vec![],
@@ -126,6 +273,49 @@ fn record_files(
     Ok(files + type_files + authorship_files + timestamp)
 }
 
+/// Produces a detached GnuPG signature of the hash of `files`
+pub(crate) fn signature<'a, F: File + 'a, MI>(matches: &ArgMatches, repo: &Repository<MI>, config: &Configuration,
+                                        files: OrderedFiles<'a, F>) -> Result<Vec<u8>, String> {
+    let program = super::gnupg(matches, config).map_err(|_| String::from("can't find GnuPG"))?;
+    let key = match matches.value_of("signing-key").map(String::from).or_else(|| config.signing.key.clone()) {
+        Some(key) => Some(OsString::from(key)),
+        None => None,
+    };
+    let mut command = ::std::process::Command::new(program);
+
+    command
+        .stdin(::std::process::Stdio::piped())
+        .stdout(::std::process::Stdio::piped())
+        .arg("--sign")
+        .arg("--armor")
+        .arg("--detach-sign")
+        .arg("-o")
+        .arg("-");
+
+    if key.is_some() {
+        let _ = command.arg("--default-key").arg(key.unwrap());
+    }
+
+    let mut child = command.spawn().expect("failed spawning gnupg");
+
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        let mut hasher = repo.config().hashing_algorithm().hasher();
+        files.hash(&mut *hasher).expect("failed hashing files");
+        let hash = hasher.result_box();
+        let encoded_hash = repo.config().encoding().encode(&hash);
+        stdin.write_all(encoded_hash.as_bytes()).expect("Failed to write to stdin");
+    }
+
+    let output = child.wait_with_output().expect("failed to read stdout");
+
+    if !output.status.success() {
+        Err(String::from_utf8_lossy(&output.stderr).into())
+    } else {
+        Ok(output.stdout)
+    }
+}
+
 pub fn command<P: AsRef<Path>, P1: AsRef<Path>, MI>(matches: &ArgMatches, repo: &Repository<MI>, mut config: Configuration, working_directory: P, config_path: P1) -> i32 {
     if !matches.is_present("no-aux") && !matches.is_present("no-author") && config.author.is_none() {
         if let Some(author) = cfg::Author::from_gitconfig(working_directory.as_ref().join(".git").join("config")) {
@@ -169,12 +359,15 @@ pub fn command<P: AsRef<Path>, P1: AsRef<Path>, MI>(matches: &ArgMatches, repo:
         }
     }
 
+    // files composed in the editor don't have to exist
+    let edit = matches.is_present("edit");
+
     #[cfg(feature = "deprecated-items")]
     let offset = {
         let item = matches.value_of(FILES_ARG)
             // file with such a name doesn't exist
             .and_then(|maybe_id|
-                if Path::new(maybe_id).exists() {
+                if !edit && Path::new(maybe_id).exists() {
                     None
                 } else {
                     Some(maybe_id)
@@ -184,7 +377,7 @@ pub fn command<P: AsRef<Path>, P1: AsRef<Path>, MI>(matches: &ArgMatches, repo:
 
         let first_is_file = matches.value_of(FILES_ARG)
             .and_then(|name|
-                if Path::new(name).exists() {
+                if !edit && Path::new(name).exists() {
                     Some(name)
                 } else {
                     None
@@ -192,7 +385,7 @@ pub fn command<P: AsRef<Path>, P1: AsRef<Path>, MI>(matches: &ArgMatches, repo:
 
         let val = matches.value_of(FILES_ARG).unwrap_or("<unknown>");
 
-        if matches.value_of(FILES_ARG).is_some() && item.is_none() && first_is_file.is_none() {
+        if !edit && matches.value_of(FILES_ARG).is_some() && item.is_none() && first_is_file.is_none() {
             eprintln!("Item or file {} not found", val);
             return 1;
         }
@@ -209,56 +402,121 @@ pub fn command<P: AsRef<Path>, P1: AsRef<Path>, MI>(matches: &ArgMatches, repo:
     #[cfg(not(feature = "deprecated-items"))]
     let offset = 0;
 
+    // files that don't come from the working directory
+    let mut contents = vec![];
+
+    if edit {
+        let types: Vec<_> = match matches.value_of("type") {
+            Some(types) => types.split(",").collect(),
+            None => vec![],
+        };
+        let names: Vec<_> = matches.values_of(FILES_ARG).unwrap_or(clap::Values::default()).dropping(offset).collect();
+        if names.is_empty() {
+            eprintln!("No files to edit");
+            return 1;
+        }
+        for name in names {
+            match compose(name, &types) {
+                Ok(Some(content)) => contents.push((String::from(name), content)),
+                Ok(None) => {
+                    eprintln!("Aborting record due to empty {}", name);
+                    return 1;
+                },
+                Err(err) => {
+                    eprintln!("Error: can't edit {}: {}", name, err);
+                    return 1;
+                },
+            }
+        }
+    }
+
+    if matches.is_present("json") {
+        match json_files(io::stdin()) {
+            Ok(files) => contents.extend(files),
+            Err(err) => {
+                eprintln!("Error: can't read files from JSON: {}", err);
+                return 1;
+            },
+        }
+    }
+
+    for arg in matches.values_of("file").unwrap_or(clap::Values::default()) {
+        match file_arg(arg) {
+            Ok(file) => contents.push(file),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                return 1;
+            },
+        }
+    }
+
     let utc: DateTime<Utc> = Utc::now();
 
     let signing = matches.is_present("sign") || config.signing.enabled;
 
-    let files = record_files(matches, offset, utc, &config).expect("failed collecting files");
+    if let Err(err) = check_names(matches, offset, &contents, signing) {
+        eprintln!("Error: {}", err);
+        return 1;
+    }
 
-    let files = if signing {
-        use std::ffi::OsString;
-        let program = super::gnupg(matches, &config).expect("can't find GnuPG");
-        let key = match matches.value_of("signing-key").map(String::from).or_else(|| config.signing.key.clone()) {
-            Some(key) => Some(OsString::from(key)),
-            None => None,
+    // Encryption is not deterministic, so encrypted files are produced once
+    // and then used both for signing and recording
+    let encrypted = if matches.is_present("encrypt") {
+        let recipients = repo.recipients().expect("can't read recipients");
+        if recipients.is_empty() {
+            eprintln!("No recipients found in {}", repo.recipients_path().to_str().unwrap());
+            return 1;
+        }
+        let gnupg = match super::gnupg(matches, &config) {
+            Ok(program) => GnuPG::new(program),
+            Err(_) => {
+                eprintln!("Error: can't find GnuPG");
+                return 1;
+            },
         };
-        let mut command = ::std::process::Command::new(program);
-
-        command
-            .stdin(::std::process::Stdio::piped())
-            .stdout(::std::process::Stdio::piped())
-            .arg("--sign")
-            .arg("--armor")
-            .arg("--detach-sign")
-            .arg("-o")
-            .arg("-");
-
-        if key.is_some() {
-            let _ = command.arg("--default-key").arg(key.unwrap());
+        let files = record_files(matches, offset, utc, &config, &contents).expect("failed collecting files");
+        match gnupg.encrypt_files(&recipients, files) {
+            Err(err) => {
+                eprintln!("Error: can't encrypt record: {:?}", err);
+                return 1;
+            },
+            Ok(files) => Some(files),
         }
+    } else {
+        None
+    };
 
-        let mut child = command.spawn().expect("failed spawning gnupg");
-
-        {
-            let mut stdin = child.stdin.as_mut().expect("Failed to open stdin");
-            let mut hasher = repo.config().hashing_algorithm().hasher();
-            files.hash(&mut *hasher).expect("failed hashing files");
-            let hash = hasher.result_box();
-            let encoded_hash = repo.config().encoding().encode(&hash);
-            stdin.write_all(encoded_hash.as_bytes()).expect("Failed to write to stdin");
+    let collect_files = || -> BoxedOrderedFiles<'static> {
+        match encrypted {
+            Some(ref files) => {
+                let files: OrderedFiles<_> = files.iter()
+                    .map(|(name, content)| (name.clone(), Cursor::new(content.clone())))
+                    .into();
+                files.boxed()
+            },
+            None => record_files(matches, offset, utc, &config, &contents).expect("failed collecting files"),
         }
+    };
 
-        let output = child.wait_with_output().expect("failed to read stdout");
+    // parent links are added upfront, so that the signature covers them
+    let parents = repo.parent_links().expect("can't list parent records");
+    let parent_files = || -> OrderedFiles<(String, &[u8])> {
+        parents.iter().map(|name| (name.clone(), &b""[..])).into()
+    };
 
-        if !output.status.success() {
-            eprintln!("Error: {}", String::from_utf8_lossy(&output.stderr));
-            return 1;
-        } else {
-            let files = record_files(matches, offset, utc, &config).expect("failed collecting files");
-            let signature_file: OrderedFiles<(String, _)> = vec![(String::from(".signature"), Cursor::new(output.stdout))].into();
-            files + signature_file
-        }
+    let files = collect_files() + parent_files();
 
+    let files = if signing {
+        match signature(matches, repo, &config, collect_files() + parent_files()) {
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                return 1;
+            },
+            Ok(signature) => {
+                let signature_file: OrderedFiles<(String, _)> = vec![(String::from(".signature"), Cursor::new(signature))].into();
+                files + signature_file
+            }
+        }
     } else {
         files
     };
@@ -268,9 +526,9 @@ pub fn command<P: AsRef<Path>, P1: AsRef<Path>, MI>(matches: &ArgMatches, repo:
             .and_then(|id| repo.item(id))
             .unwrap();
 
-        item.new_record(files, true).expect("can't create a record")
+        item.new_record(files, false).expect("can't create a record")
     } else { // repo
-        repo.new_record(files, true).expect("can't create a record")
+        repo.new_record(files, false).expect("can't create a record")
     };
 
     println!("{}", record.encoded_hash());
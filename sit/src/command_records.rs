@@ -1,7 +1,8 @@
 use clap::ArgMatches;
-use sit_core::{Repository, Record, cfg::Configuration, record::RecordContainer, record::OrderedFiles, path::HasPath};
+use sit_core::{self, Repository, Record, cfg::Configuration, record::RecordContainer, record::OrderedFiles, path::HasPath};
 use serde_json;
-use super::get_named_expression;
+use super::{get_named_expression, paginate};
+use format::Printer;
 use jmespath;
 use super::gnupg;
 use serde;
@@ -42,9 +43,10 @@ fn list<R: RecordContainer, MI>(matches: &ArgMatches, iter: &R, repo: &Repositor
         .or_else(|| matches.value_of("query").or_else(|| Some("hash")).map(String::from))
         .unwrap();
 
-    let filter = jmespath::compile(&filter_expr).expect("can't compile filter expression");
-    let query = jmespath::compile(&query_expr).expect("can't compile query expression");
+    let filter = sit_core::query::compile(&filter_expr).expect("can't compile filter expression");
+    let query = sit_core::query::compile(&query_expr).expect("can't compile query expression");
 
+    let mut results = vec![];
     for record in records {
         for rec in record {
             // convert to JSON
@@ -105,14 +107,14 @@ fn list<R: RecordContainer, MI>(matches: &ArgMatches, iter: &R, repo: &Repositor
                 true
             };
             if result {
-                let view = query.search(&data).unwrap();
-                if view.is_string() {
-                    println!("{}", view.as_string().unwrap());
-                } else {
-                    println!("{}", serde_json::to_string_pretty(&view).unwrap());
-                }
+                results.push(data);
             }
         }
     }
+    let mut printer = Printer::new(matches.value_of("format").unwrap(), &query);
+    for data in paginate(matches, results, |data| data) {
+        printer.print(&query.search(&data).unwrap());
+    }
+    printer.finish();
     return 0;
 }
@@ -0,0 +1,74 @@
+use chrono::prelude::*;
+use clap::ArgMatches;
+use sit_core::{Record, Repository, RepositoryError, cfg::Configuration,
+               record::{BoxedOrderedFiles, OrderedFiles, RecordOwningContainer}, repository::Redaction};
+use std::io::Cursor;
+use command_record::signature;
+
+fn redaction_files(redaction: &Redaction, utc: DateTime<Utc>, config: &Configuration) -> BoxedOrderedFiles<'static> {
+    // .authors
+    let authorship_files: Option<OrderedFiles<(String, _)>> = config.author.as_ref().map(|author| {
+        vec![(String::from(".authors"), Cursor::new(format!("{}", author)))].into()
+    });
+
+    let timestamp: OrderedFiles<(String, _)> = vec![(String::from(".timestamp"), Cursor::new(format!("{:?}", utc)))].into();
+
+    redaction.files() + authorship_files + Some(timestamp)
+}
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>, config: Configuration) -> i32 {
+    let id = matches.value_of("record").unwrap();
+    let record = match repo.record(id) {
+        None => {
+            eprintln!("Record {} not found", id);
+            return 1;
+        },
+        Some(record) => record,
+    };
+
+    let names: Vec<_> = matches.values_of("file").unwrap().collect();
+
+    let redaction = match repo.redaction(&record, &names) {
+        Err(RepositoryError::NotFound) => {
+            eprintln!("Not all files ({}) were found in record {}", names.join(", "), id);
+            return 1;
+        },
+        Err(RepositoryError::OtherError(err)) => {
+            eprintln!("Error: {}", err);
+            return 1;
+        },
+        Err(err) => panic!("can't prepare redaction: {:?}", err),
+        Ok(redaction) => redaction,
+    };
+
+    let utc: DateTime<Utc> = Utc::now();
+
+    // parent links are added upfront, so that the signature covers them
+    let parents = repo.parent_links().expect("can't list parent records");
+    let parent_files = || -> OrderedFiles<(String, &[u8])> {
+        parents.iter().map(|name| (name.clone(), &b""[..])).into()
+    };
+
+    let files = redaction_files(&redaction, utc, &config) + parent_files();
+
+    // unsigned redaction records are ignored
+    let files = match signature(matches, repo, &config, redaction_files(&redaction, utc, &config) + parent_files()) {
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return 1;
+        },
+        Ok(signature) => {
+            let signature_file: OrderedFiles<(String, _)> = vec![(String::from(".signature"), Cursor::new(signature))].into();
+            files + signature_file
+        },
+    };
+
+    // Redaction record has to be in place before the tombstones are,
+    // otherwise the redacted record will not pass integrity check
+    let redaction_record = repo.new_record(files, false).expect("can't create a redaction record");
+    repo.redact(&redaction).expect("can't redact record");
+
+    println!("{}", redaction_record.encoded_hash());
+
+    return 0;
+}
@@ -1,13 +1,17 @@
 use clap::{ArgMatches, Values};
 use sit_core::{self, Repository, record::RecordContainerReduction, repository, cfg::Configuration,
-               reducers::duktape, path::{HasPath, ResolvePath}};
+               reducers::{duktape, order, SourceFiles, StatefulReducer}, path::{HasPath, ResolvePath}, encryption::{self, GnuPG},
+               policy::{Policy, Verifier, POLICY_FILE}, reducers::native::NativeReducers, Reducer, checkpoint};
 
-use serde_json;
-use super::get_named_expression;
+use serde_json::{self, Map};
+use super::{get_named_expression, decryptor, verifier, limited, console, JsReducer};
 use jmespath;
+use format::Printer;
+use std::fs;
+use std::io::Read;
 use std::path::PathBuf;
 
-pub fn command<MI>(matches: &ArgMatches, repo: Repository<MI>, config: Configuration) -> i32
+pub fn command<MI>(matches: &ArgMatches, repo: Repository<MI>, config: Configuration, verbosity: u64) -> i32
     where MI: repository::ModuleIterator<PathBuf, repository::Error> {
     if let Some(vals) = matches.values_of_os("reducer") {
         let reducers_path = repo.path().join("reducers");
@@ -20,17 +24,33 @@ pub fn command<MI>(matches: &ArgMatches, repo: Repository<MI>, config: Configura
             } else {
                 p
             });
-        command_impl(matches, &repo, config, reducers)
+        command_impl(matches, &repo, config, verbosity, reducers)
     } else {
-        command_impl(matches, &repo, config, &repo)
+        command_impl(matches, &repo, config, verbosity, &repo)
     }
 }
 
-fn command_impl<MI, SF>(matches: &ArgMatches, repo: &Repository<MI>, config: Configuration, source_files: SF) -> i32
+fn command_impl<MI, SF: Clone>(matches: &ArgMatches, repo: &Repository<MI>, config: Configuration, verbosity: u64, source_files: SF) -> i32
     where MI: repository::ModuleIterator<PathBuf, repository::Error>, SF: duktape::SourceFiles {
 
     let fixed_roots = matches.values_of("root");
+    let decryptor = decryptor(matches, &config);
+    let policy = match repo.policy() {
+        Ok(policy) => policy,
+        Err(err) => {
+            eprintln!("Can't read authorization policy: {:?}", err);
+            return 1;
+        },
+    };
+    let verifier = verifier(matches, &config, repo);
+    let native = NativeReducers::from_config(repo.config());
     let state = matches.value_of("state").map(serde_json::from_str).filter(Result::is_ok).map(Result::unwrap);
+    let trace = matches.is_present("trace");
+    let format = matches.value_of("format").unwrap();
+    if trace && cfg!(feature = "quickjs-reducers") {
+        eprintln!("Tracing is only supported by Duktape reducers");
+        return 1;
+    }
 
     #[cfg(feature = "deprecated-items")] {
         if let Some(id) = matches.value_of("id") {
@@ -46,7 +66,7 @@ fn command_impl<MI, SF>(matches: &ArgMatches, repo: &Repository<MI>, config: Con
                         .or_else(|| matches.value_of("query").or_else(|| Some("@")).map(String::from))
                         .unwrap();
 
-                    reduce(&query_expr, &item, source_files, fixed_roots, state);
+                    reduce(&query_expr, format, &item, source_files, fixed_roots, state, decryptor, native, policy, verifier, &config, verbosity, trace, None);
                     return 0;
                 }
             }
@@ -59,31 +79,122 @@ fn command_impl<MI, SF>(matches: &ArgMatches, repo: &Repository<MI>, config: Con
         .or_else(|| matches.value_of("query").or_else(|| Some("@")).map(String::from))
         .unwrap();
 
-    reduce(&query_expr, repo, source_files, fixed_roots, state);
+    let checkpoints = if matches.is_present("checkpoint") {
+        // checkpoints are stored unencrypted and would otherwise leak decrypted data
+        if decryptor.is_some() {
+            match has_encrypted_records(repo) {
+                Ok(false) => (),
+                Ok(true) => {
+                    eprintln!("Checkpoints can't be used when encrypted records are decrypted");
+                    return 1;
+                },
+                Err(err) => {
+                    eprintln!("Can't read records: {:?}", err);
+                    return 1;
+                },
+            }
+        }
+        match fingerprint(repo, source_files.clone(), &[decryptor.is_some(), verifier.is_some()]) {
+            Ok(fingerprint) => Some((repo.checkpoints_path(), fingerprint)),
+            Err(err) => {
+                eprintln!("Can't fingerprint reducers: {:?}", err);
+                return 1;
+            },
+        }
+    } else {
+        None
+    };
+
+    reduce(&query_expr, format, repo, source_files, fixed_roots, state, decryptor, native, policy, verifier, &config, verbosity, trace, checkpoints);
 
     return 0;
 }
 
-fn reduce<RCR: RecordContainerReduction<Record = repository::Record>, SF: duktape::SourceFiles>
-    (query_expr: &str, container: &RCR, source_files: SF, roots: Option<Values>, state: Option<serde_json::Value>) {
-    let mut reducer = sit_core::reducers::duktape::DuktapeReducer::new(source_files).unwrap();
-    let query = jmespath::compile(&query_expr).expect("can't compile query expression");
+/// Returns `true` if any of repository's records is encrypted
+fn has_encrypted_records<MI>(repo: &Repository<MI>) -> Result<bool, repository::Error> {
+    use sit_core::record::{RecordContainer, RecordExt};
+    Ok(repo.record_iter()?.flat_map(|generation| generation)
+        .any(|record| record.file(encryption::ENCRYPTED_FILE).is_some()))
+}
+
+/// Fingerprints everything that affects the outcome of the reduction:
+/// reducers, repository's configuration and policy, redactions and given flags
+fn fingerprint<MI, SF: SourceFiles + Clone>(repo: &Repository<MI>, source_files: SF, flags: &[bool]) -> Result<String, sit_core::RepositoryError> {
+    let mut parts = vec![];
+    for extension in &["js", "wasm"] {
+        let files = order::order(source_files.clone().source_files()?, source_files.repository_reducers(), extension)
+            .map_err(|err| sit_core::RepositoryError::OtherError(err.explain()))?;
+        for file in files {
+            let mut contents = vec![];
+            fs::File::open(&file)?.read_to_end(&mut contents)?;
+            parts.push(file.to_string_lossy().into_owned().into_bytes());
+            parts.push(contents);
+        }
+    }
+    for file in &["config.json", POLICY_FILE] {
+        let mut contents = vec![];
+        if let Ok(mut f) = fs::File::open(repo.path().join(file)) {
+            f.read_to_end(&mut contents)?;
+        }
+        parts.push(contents);
+    }
+    parts.extend(repo.redacted_hashes());
+    parts.push(flags.iter().map(|flag| if *flag { b'1' } else { b'0' }).collect());
+    Ok(checkpoint::fingerprint(repo.config().hashing_algorithm(), parts))
+}
+
+fn reduce<RCR: RecordContainerReduction<Record = repository::Record>, SF: duktape::SourceFiles + Clone>
+    (query_expr: &str, format: &str, container: &RCR, source_files: SF, roots: Option<Values>, state: Option<serde_json::Value>,
+     decryptor: Option<GnuPG>, native: NativeReducers<repository::Record>, policy: Policy, verifier: Option<Verifier>,
+     config: &Configuration, verbosity: u64, trace: bool, checkpoints: Option<(PathBuf, String)>) {
+    let reducer = console(limited(JsReducer::new(source_files.clone()).unwrap().with_decryptor(decryptor), config), verbosity);
+    if trace {
+        // trace goes to stderr to keep the output intact
+        #[cfg(not(feature = "quickjs-reducers"))] {
+            return reduce_with(duktape::TracingReducer::new(reducer, ::std::io::stderr()),
+                               query_expr, format, container, source_files, roots, state, native, policy, verifier, checkpoints);
+        }
+    }
+    reduce_with(reducer, query_expr, format, container, source_files, roots, state, native, policy, verifier, checkpoints)
+}
+
+// `source_files` are only needed to load WebAssembly reducers
+#[cfg_attr(not(feature = "wasm-reducers"), allow(unused_variables))]
+fn reduce_with<JR, RCR: RecordContainerReduction<Record = repository::Record>, SF: duktape::SourceFiles + Clone>
+    (reducer: JR, query_expr: &str, format: &str, container: &RCR, source_files: SF, roots: Option<Values>, state: Option<serde_json::Value>,
+     native: NativeReducers<repository::Record>, policy: Policy, verifier: Option<Verifier>,
+     checkpoints: Option<(PathBuf, String)>)
+    where JR: StatefulReducer<State = Map<String, serde_json::Value>, Item = repository::Record> {
+    // natively reduced types are handled before JavaScript reducers
+    let reducer = native.chain(reducer);
+    #[cfg(feature = "wasm-reducers")]
+    let reducer = reducer.chain(sit_core::reducers::wasm::WasmReducer::new(source_files).expect("can't load WebAssembly reducers"));
+    // records not authorized by the policy are skipped
+    let mut reducer = policy.reducer(reducer, verifier);
+    let query = sit_core::query::compile(&query_expr).expect("can't compile query expression");
     let state = container.initialize_state(match state {
         None => Default::default(),
         Some(s) => s.as_object().unwrap().to_owned(),
     });
-    let result = match roots {
-        None => container.reduce_with_reducer_and_state(&mut reducer, state).expect("can't reduce"),
-        Some(fixed_roots) => {
+    let result = match (roots, checkpoints) {
+        (None, Some((path, fingerprint))) => {
+            let checkpoints = checkpoint::load(&path, &fingerprint);
+            let reduction = checkpoint::reduce(container, &mut reducer, state, checkpoints, &fingerprint).expect("can't reduce");
+            if let Some(checkpoint) = reduction.checkpoint {
+                if let Err(err) = checkpoint::save(&path, &checkpoint) {
+                    eprintln!("Can't save checkpoint: {}", err);
+                }
+            }
+            reduction.state
+        },
+        (None, None) => container.reduce_with_reducer_and_state(&mut reducer, state).expect("can't reduce"),
+        (Some(fixed_roots), _) => {
             let container = container.fixed_roots(fixed_roots);
             container.reduce_with_reducer_and_state(&mut reducer, state).expect("can't reduce")
         },
     };
     let data = jmespath::Variable::from(serde_json::Value::Object(result));
-    let view = query.search(&data).unwrap();
-    if view.is_string() {
-        println!("{}", view.as_string().unwrap());
-    } else {
-        println!("{}", serde_json::to_string_pretty(&view).unwrap());
-    }
+    let mut printer = Printer::new(format, &query);
+    printer.print(&query.search(&data).unwrap());
+    printer.finish();
 }
@@ -0,0 +1,78 @@
+use clap::ArgMatches;
+use sit_core::{Repository, repository, cfg::Configuration, reducers::SourceFiles,
+               reducers::testing::{self, Difference}, path::{HasPath, ResolvePath}};
+use serde_json;
+use super::{limited, console, JsReducer};
+use std::path::PathBuf;
+
+pub fn command<MI>(matches: &ArgMatches, repo: Repository<MI>, config: Configuration, verbosity: u64) -> i32
+    where MI: repository::ModuleIterator<PathBuf, repository::Error> {
+    if let Some(matches) = matches.subcommand_matches("test") {
+        if let Some(vals) = matches.values_of_os("reducer") {
+            let reducers_path = repo.path().join("reducers");
+            let reducers: Vec<_> = vals.map(PathBuf::from)
+                .map(|p| if p.is_file() {
+                    p
+                } else if reducers_path.join(&p).resolve_dir().unwrap().is_dir() {
+                    reducers_path.join(&p).resolve_dir().unwrap()
+                } else {
+                    p
+                }).collect();
+            return test(matches, reducers, &config, verbosity);
+        } else {
+            return test(matches, &repo, &config, verbosity);
+        }
+    }
+    return 1;
+}
+
+fn test<SF: SourceFiles>(matches: &ArgMatches, source_files: SF, config: &Configuration, verbosity: u64) -> i32 {
+    let mut reducer = match JsReducer::new(source_files) {
+        Ok(reducer) => console(limited(reducer, config), verbosity),
+        Err(err) => {
+            eprintln!("Can't load reducers: {:?}", err);
+            return 1;
+        },
+    };
+    let mut failed = 0;
+    let mut passed = 0;
+    for path in matches.values_of_os("fixtures").unwrap() {
+        let fixtures = match testing::fixtures(path) {
+            Ok(fixtures) => fixtures,
+            Err(err) => {
+                eprintln!("Can't read fixtures in {}: {:?}", PathBuf::from(path).to_string_lossy(), err);
+                return 1;
+            },
+        };
+        for fixture in fixtures {
+            reducer.reset_state();
+            match fixture.check(&mut reducer) {
+                Ok(ref differences) if differences.is_empty() => {
+                    println!("ok {}", fixture.path().to_string_lossy());
+                    passed += 1;
+                },
+                Ok(differences) => {
+                    println!("FAIL {}", fixture.path().to_string_lossy());
+                    for Difference { path, expected, actual } in differences {
+                        println!("  {}: expected {}, got {}", path, render(expected), render(actual));
+                    }
+                    failed += 1;
+                },
+                Err(err) => {
+                    println!("FAIL {}", fixture.path().to_string_lossy());
+                    println!("  {:?}", err);
+                    failed += 1;
+                },
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 { 1 } else { 0 }
+}
+
+fn render(value: Option<serde_json::Value>) -> String {
+    match value {
+        None => "nothing".into(),
+        Some(value) => serde_json::to_string(&value).unwrap(),
+    }
+}
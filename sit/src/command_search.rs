@@ -0,0 +1,34 @@
+use clap::ArgMatches;
+use sit_core::{self, Repository, search::Index};
+use format::Printer;
+use jmespath;
+use serde_json;
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>) -> i32 {
+    let path = repo.search_index_path();
+    let mut index = if matches.is_present("rebuild") {
+        Index::default()
+    } else {
+        Index::load(&path)
+    };
+    if index.update(repo).expect("can't index records") > 0 {
+        if let Err(err) = index.save(&path) {
+            eprintln!("Can't save search index: {}", err);
+        }
+    }
+
+    let terms: Vec<_> = matches.values_of("terms").unwrap().collect();
+    let mut results = index.search(&terms.join(" "), |hash| repo.record(hash));
+    if let Some(limit) = matches.value_of("limit") {
+        results.truncate(limit.parse().unwrap());
+    }
+
+    let query = sit_core::query::compile("{record: record, file: file, snippet: snippet, roots: join(' ', roots)}").unwrap();
+    let mut printer = Printer::new(matches.value_of("format").unwrap(), &query);
+    for result in results {
+        let data = jmespath::Variable::from(serde_json::to_value(&result).unwrap());
+        printer.print(&query.search(&data).unwrap());
+    }
+    printer.finish();
+    0
+}
@@ -0,0 +1,107 @@
+use clap::ArgMatches;
+use sit_core::{Repository, Record, cfg::Configuration, record::RecordExt};
+use mime_guess;
+use super::verifier;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Metadata files shown in the header rather than as files
+fn is_metadata(name: &str) -> bool {
+    name.starts_with(".type/") || name.starts_with(".prev/") ||
+        name == ".authors" || name == ".timestamp" || name == ".signature"
+}
+
+/// Returns MIME type of a file, judging by its extension or, failing that, its content
+fn mime_type(name: &str, content: &[u8]) -> &'static str {
+    let guess = Path::new(name).extension().and_then(|ext| ext.to_str())
+        .and_then(mime_guess::get_mime_type_str);
+    if let Some(mime) = guess {
+        return mime;
+    }
+    let signatures: [(&[u8], &str); 6] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+    ];
+    signatures.iter().find(|&&(signature, _)| content.starts_with(signature))
+        .map(|&(_, mime)| mime)
+        .unwrap_or("application/octet-stream")
+}
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>, config: Configuration) -> i32 {
+    let hash = matches.value_of("record").unwrap();
+    let record = match repo.record(hash) {
+        Some(record) => record,
+        None => {
+            eprintln!("Record {} not found", hash);
+            return 1;
+        },
+    };
+
+    if let Some(name) = matches.value_of("raw") {
+        return match record.file(name) {
+            Some(mut file) => {
+                let stdout = io::stdout();
+                io::copy(&mut file, &mut stdout.lock()).expect("can't write file");
+                0
+            },
+            None => {
+                eprintln!("File {} not found in record {}", name, hash);
+                1
+            },
+        };
+    }
+
+    let mut types = vec![];
+    let mut parents = vec![];
+    let mut files = vec![];
+    for (name, mut reader) in record.file_iter() {
+        let mut content = vec![];
+        reader.read_to_end(&mut content).expect("can't read file");
+        if name.starts_with(".type/") {
+            types.push(name[".type/".len()..].to_owned());
+        } else if name.starts_with(".prev/") {
+            parents.push(name[".prev/".len()..].to_owned());
+        }
+        files.push((name, content));
+    }
+    let text = |name: &str| files.iter().find(|&&(ref n, _)| n == name)
+        .map(|&(_, ref content)| String::from_utf8_lossy(content).trim().to_owned());
+
+    let signature = if text(".signature").is_none() {
+        "none".to_owned()
+    } else {
+        match verifier(matches, &config, repo) {
+            None => "present (not verified, GnuPG not found)".to_owned(),
+            Some(verifier) => match verifier.signed_by(&record) {
+                Some(keys) => format!("good ({})", keys.join(", ")),
+                None => "BAD".to_owned(),
+            },
+        }
+    };
+
+    println!("record     {}", record.encoded_hash());
+    println!("types      {}", types.join(", "));
+    println!("authors    {}", text(".authors").map(|a| a.lines().map(str::trim).collect::<Vec<_>>().join(", ")).unwrap_or_default());
+    println!("timestamp  {}", text(".timestamp").unwrap_or_default());
+    println!("parents    {}", parents.join(", "));
+    println!("signature  {}", signature);
+
+    for &(ref name, ref content) in files.iter().filter(|&&(ref name, _)| !is_metadata(name)) {
+        println!();
+        match ::std::str::from_utf8(content) {
+            Ok(text) if !text.contains('\0') => {
+                println!("--- {}", name);
+                print!("{}", text);
+                if !text.ends_with('\n') {
+                    println!();
+                }
+            },
+            _ => println!("--- {} (binary, {} bytes, {})", name, content.len(), mime_type(name, content)),
+        }
+    }
+    0
+}
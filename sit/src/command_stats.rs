@@ -0,0 +1,47 @@
+use clap::ArgMatches;
+use sit_core::{self, Repository, cfg::Configuration, record::RecordContainer, stats};
+use format::Printer;
+use jmespath;
+use serde_json;
+use std::path::PathBuf;
+
+pub fn command<MI: Send + Sync>(matches: &ArgMatches, repo: &Repository<MI>, config: Configuration, verbosity: u64) -> i32
+    where MI: sit_core::repository::ModuleIterator<PathBuf, sit_core::repository::Error>
+{
+    let key = sit_core::query::compile(matches.value_of("group-by").unwrap()).expect("can't compile grouping expression");
+    let metric = matches.value_of("metric").map(|expr| sit_core::query::compile(expr).expect("can't compile metric expression"));
+    let filter = matches.value_of("filter").map(|expr| sit_core::query::compile(expr).expect("can't compile filter expression"));
+
+    let values = if cfg!(feature = "deprecated-items") && !matches.is_present("records") {
+        ::command_items::reduce_items(matches, repo, &config, verbosity)
+    } else {
+        repo.record_iter().expect("can't list records")
+            .flat_map(|records| records)
+            // records are treated as plain JSON, the same way `sit records` does
+            .map(|record| jmespath::Variable::from(serde_json::to_value(&record).unwrap()))
+            .collect()
+    };
+    let values: Vec<_> = values.into_iter()
+        .filter(|value| filter.as_ref().map(|filter| filter.search(value).unwrap().as_boolean().unwrap_or(false)).unwrap_or(true))
+        .collect();
+
+    let groups = stats::aggregate(&values, &key, metric.as_ref()).expect("can't aggregate");
+
+    let format = matches.value_of("format").unwrap();
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&groups).unwrap());
+        return 0;
+    }
+    let query = sit_core::query::compile(if metric.is_some() {
+        "{key: key, count: count, mean: metric.mean, median: metric.median, min: metric.min, max: metric.max}"
+    } else {
+        "{key: key, count: count}"
+    }).unwrap();
+    let mut printer = Printer::new(format, &query);
+    for group in groups {
+        let data = jmespath::Variable::from(serde_json::to_value(&group).unwrap());
+        printer.print(&query.search(&data).unwrap());
+    }
+    printer.finish();
+    0
+}
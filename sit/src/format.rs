@@ -0,0 +1,178 @@
+//! Output formats of query results
+//!
+//! * `json` (default) prints strings as is and everything else as pretty JSON
+//! * `table` prints objects as aligned columns
+//! * `csv` prints objects as comma-separated values (RFC 4180)
+//! * `ndjson` prints every result as JSON on its own line
+//! * `yaml` prints every result as a YAML document
+//!
+//! Columns of `table` and `csv` are taken from the query if it is a multiselect hash
+//! (`{id: id, summary: summary}`), otherwise from keys of the results.
+
+use clap::Arg;
+use jmespath::{Expression, Variable};
+use jmespath::ast::Ast;
+use serde_json::{self, Value as JsonValue};
+use yaml_rust::{Yaml, YamlEmitter};
+use yaml_rust::yaml::Hash as YamlHash;
+
+/// Supported formats
+pub const FORMATS: &[&str] = &["json", "table", "csv", "ndjson", "yaml"];
+
+/// Returns `--format` argument
+pub fn arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("format")
+        .long("format")
+        .takes_value(true)
+        .default_value("json")
+        .possible_values(FORMATS)
+        .help("Output format")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    Table,
+    Csv,
+    Ndjson,
+    Yaml,
+}
+
+/// Prints query results in the requested format
+///
+/// `table` and `csv` formats need all results to be known, so they are only
+/// printed upon [`finish`].
+///
+/// [`finish`]: #method.finish
+pub struct Printer {
+    format: Format,
+    columns: Vec<String>,
+    rows: Vec<JsonValue>,
+}
+
+impl Printer {
+    /// Creates a printer of `query` results in a given format (see [`FORMATS`])
+    ///
+    /// [`FORMATS`]: constant.FORMATS.html
+    pub fn new(format: &str, query: &Expression) -> Self {
+        let format = match format {
+            "table" => Format::Table,
+            "csv" => Format::Csv,
+            "ndjson" => Format::Ndjson,
+            "yaml" => Format::Yaml,
+            _ => Format::Json,
+        };
+        let columns = match query.as_ast() {
+            &Ast::MultiHash { ref elements, .. } => elements.iter().map(|kv| kv.key.clone()).collect(),
+            _ => vec![],
+        };
+        Printer { format, columns, rows: vec![] }
+    }
+
+    /// Prints (or collects) a result
+    pub fn print(&mut self, view: &Variable) {
+        match self.format {
+            Format::Json if view.is_string() => println!("{}", view.as_string().unwrap()),
+            Format::Json => println!("{}", serde_json::to_string_pretty(view).unwrap()),
+            Format::Ndjson => println!("{}", serde_json::to_string(view).unwrap()),
+            Format::Yaml => {
+                let mut out = String::new();
+                YamlEmitter::new(&mut out).dump(&yaml(serde_json::to_value(view).unwrap())).unwrap();
+                println!("{}", out);
+            },
+            Format::Table | Format::Csv => self.rows.push(serde_json::to_value(view).unwrap()),
+        }
+    }
+
+    /// Prints collected results
+    pub fn finish(mut self) {
+        if (self.format != Format::Table && self.format != Format::Csv) || self.rows.is_empty() {
+            return;
+        }
+        if self.columns.is_empty() {
+            for row in self.rows.iter() {
+                if let Some(object) = row.as_object() {
+                    for key in object.keys() {
+                        if !self.columns.contains(key) {
+                            self.columns.push(key.clone());
+                        }
+                    }
+                }
+            }
+        }
+        let columns = &self.columns;
+        let mut lines: Vec<Vec<String>> = vec![];
+        if !columns.is_empty() {
+            lines.push(columns.clone());
+        }
+        for row in self.rows.iter() {
+            lines.push(match row.as_object() {
+                Some(object) => columns.iter().map(|column| cell(object.get(column).unwrap_or(&JsonValue::Null))).collect(),
+                None => vec![cell(row)],
+            });
+        }
+        match self.format {
+            Format::Csv => for line in lines {
+                println!("{}", line.iter().map(|value| csv_field(value)).collect::<Vec<_>>().join(","));
+            },
+            _ => {
+                let n = columns.len();
+                let mut widths = vec![0; n];
+                for line in lines.iter().filter(|line| line.len() == n) {
+                    for (width, value) in widths.iter_mut().zip(line.iter()) {
+                        *width = (*width).max(value.chars().count());
+                    }
+                }
+                for line in lines {
+                    let last = line.len().saturating_sub(1);
+                    let line: Vec<_> = line.into_iter().enumerate().map(|(i, value)| if i == last {
+                        value
+                    } else {
+                        format!("{:width$}", value, width = widths[i])
+                    }).collect();
+                    println!("{}", line.join("  "));
+                }
+            },
+        }
+    }
+}
+
+/// Renders a value as a table cell
+fn cell(value: &JsonValue) -> String {
+    match value {
+        &JsonValue::Null => String::new(),
+        &JsonValue::String(ref s) => s.clone(),
+        value => serde_json::to_string(value).unwrap(),
+    }
+}
+
+/// Quotes a CSV field if necessary
+fn csv_field(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.into()
+    }
+}
+
+/// Converts JSON to YAML
+fn yaml(value: JsonValue) -> Yaml {
+    match value {
+        JsonValue::Null => Yaml::Null,
+        JsonValue::Bool(b) => Yaml::Boolean(b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(n.to_string()),
+        },
+        JsonValue::String(s) => Yaml::String(s),
+        JsonValue::Array(a) => Yaml::Array(a.into_iter().map(yaml).collect()),
+        JsonValue::Object(o) => {
+            let mut hash = YamlHash::new();
+            for (k, v) in o {
+                hash.insert(Yaml::String(k), yaml(v));
+            }
+            Yaml::Hash(hash)
+        },
+    }
+}
+
@@ -18,6 +18,8 @@ extern crate yaml_rust;
 
 extern crate config;
 use sit_core::cfg;
+use sit_core::encryption::GnuPG;
+use sit_core::policy::Verifier;
 
 mod rebuild;
 use rebuild::rebuild_repository;
@@ -28,10 +30,18 @@ mod command_item;
 mod command_record;
 mod command_items;
 mod command_reduce;
+mod command_reducer;
 mod command_records;
 mod command_external;
 mod command_jmespath;
 mod command_integrity;
+mod command_redact;
+mod command_search;
+mod command_stats;
+mod command_show;
+mod command_fsck;
+mod command_gc;
+mod format;
 
 mod cli;
 
@@ -58,6 +68,8 @@ extern crate thread_local;
 #[macro_use] extern crate derive_error;
 extern crate directories;
 extern crate itertools;
+extern crate mime_guess;
+extern crate base64;
 
 use std::collections::HashMap;
 pub fn get_named_expression<S: AsRef<str>, MI>(name: S, repo: &sit_core::Repository<MI>,
@@ -85,6 +97,107 @@ pub fn gnupg(matches: &ArgMatches, config: &cfg::Configuration) -> Result<OsStri
     Ok(program)
 }
 
+/// JavaScript reducer engine
+#[cfg(not(feature = "quickjs-reducers"))]
+pub type JsReducer<R> = sit_core::reducers::duktape::DuktapeReducer<R>;
+/// JavaScript reducer engine
+#[cfg(feature = "quickjs-reducers")]
+pub type JsReducer<R> = sit_core::reducers::quickjs::QuickJsReducer<R>;
+
+/// Applies configured execution limits to a JavaScript reducer
+#[cfg(not(feature = "quickjs-reducers"))]
+pub fn limited<R: sit_core::Record>(reducer: JsReducer<R>, config: &cfg::Configuration) -> JsReducer<R> {
+    reducer.with_limits(&config.reducers)
+}
+
+/// Applies configured execution limits to a JavaScript reducer
+///
+/// QuickJS reducers can't enforce limits, so if any are configured,
+/// this exits with an error instead of running reducers without them.
+#[cfg(feature = "quickjs-reducers")]
+pub fn limited<R: sit_core::Record>(reducer: JsReducer<R>, config: &cfg::Configuration) -> JsReducer<R> {
+    if !config.reducers.is_none() {
+        eprintln!("Reducer limits (reducers.timeout, reducers.heap_limit) are configured but not supported by QuickJS reducers");
+        exit(1);
+    }
+    reducer
+}
+
+/// Routes JavaScript reducers' console output to stderr if verbosity is requested
+#[cfg(not(feature = "quickjs-reducers"))]
+pub fn console<R: sit_core::Record>(reducer: JsReducer<R>, verbosity: u64) -> JsReducer<R> {
+    use sit_core::reducers::duktape::Logging;
+    if verbosity > 0 {
+        reducer.with_logging(Logging::Stderr)
+    } else {
+        reducer
+    }
+}
+
+/// Routes JavaScript reducers' console output to stderr if verbosity is requested
+///
+/// QuickJS reducers don't support routing console output, so only a warning is printed.
+#[cfg(feature = "quickjs-reducers")]
+pub fn console<R: sit_core::Record>(reducer: JsReducer<R>, verbosity: u64) -> JsReducer<R> {
+    if verbosity > 0 {
+        eprintln!("Warning: reducers' console output is not supported by QuickJS reducers");
+    }
+    reducer
+}
+
+/// Returns a decryptor for encrypted records (if GnuPG is available)
+pub fn decryptor(matches: &ArgMatches, config: &cfg::Configuration) -> Option<GnuPG> {
+    gnupg(matches, config).ok().map(GnuPG::new)
+}
+
+/// Returns a signature verifier for authorization policy checks (if GnuPG is available)
+pub fn verifier<MI>(matches: &ArgMatches, config: &cfg::Configuration, repo: &sit_core::Repository<MI>) -> Option<Verifier> {
+    decryptor(matches, config).map(|gnupg| Verifier::new(gnupg, repo.config().hashing_algorithm().clone(),
+                                                          repo.config().encoding().clone()))
+}
+
+/// Adds `--sort-by`, `--reverse`, `--offset` and `--limit` to a listing command
+fn with_pagination<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    fn validate_number(v: String) -> Result<(), String> {
+        v.parse::<usize>().map(|_| ()).map_err(|e| format!("{}: {}", v, e))
+    }
+    app.arg(Arg::with_name("sort-by")
+            .long("sort-by")
+            .takes_value(true)
+            .validator(|v| sit_core::query::compile(&v).map(|_| ()).map_err(|e| e.to_string()))
+            .help("Sort by the result of a JMESPath query (numbers first, then strings, booleans, and missing values last)"))
+       .arg(Arg::with_name("reverse")
+            .long("reverse")
+            .help("Reverse the order"))
+       .arg(Arg::with_name("offset")
+            .long("offset")
+            .takes_value(true)
+            .validator(validate_number)
+            .help("Skip first N results"))
+       .arg(Arg::with_name("limit")
+            .long("limit")
+            .takes_value(true)
+            .validator(validate_number)
+            .help("Limit the number of results to N"))
+}
+
+/// Sorts and paginates listed values according to `--sort-by`, `--reverse`, `--offset` and `--limit`
+pub fn paginate<T, F>(matches: &ArgMatches, mut values: Vec<T>, data: F) -> Vec<T>
+    where F: Fn(&T) -> &jmespath::Variable {
+    let reverse = matches.is_present("reverse");
+    match matches.value_of("sort-by") {
+        Some(expr) => {
+            let sort_by = sit_core::query::compile(expr).expect("can't compile sorting expression");
+            sit_core::query::sort_by(&mut values, &sort_by, reverse, data).expect("can't sort");
+        },
+        None if reverse => values.reverse(),
+        None => (),
+    }
+    let offset = matches.value_of("offset").map(|v| v.parse().unwrap()).unwrap_or(0);
+    let limit = matches.value_of("limit").map(|v| v.parse().unwrap()).unwrap_or(::std::usize::MAX);
+    values.into_iter().skip(offset).take(limit).collect()
+}
+
 mod module_iter;
 use module_iter::ScriptModule;
 
@@ -139,7 +252,102 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                  .help("Disables record integrity check (mostly for performance reasons)"))
         .subcommand(SubCommand::with_name("integrity")
             .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
-            .about("Checks the integrity of record hashes and lists invalid records"))
+            .about("Checks the integrity of record hashes and lists invalid records")
+            .arg(Arg::with_name("policy")
+                .long("policy")
+                .help("Also list records that violate repository's authorization policy (policy.json)")))
+        .subcommand(SubCommand::with_name("fsck")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Checks the repository for corrupt records, broken links and other problems")
+            .long_about("Lists corrupt records, link files that don't resolve, records with dangling \
+            parents, stray temporary directories, record names that don't decode and redactions \
+            that can't be verified")
+            .arg(Arg::with_name("repair")
+                .long("repair")
+                .long_help("Moves offending files into a new directory under .sit/quarantine, along with \
+                a report (report.json). Records with dangling parents and unverified redactions are only reported.")
+                .help("Move offending files into quarantine"))
+            .arg(Arg::with_name("older-than")
+                .long("older-than")
+                .takes_value(true)
+                .value_name("HOURS")
+                .default_value("24")
+                .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|e| format!("{}: {}", v, e)))
+                .help("Only report temporary directories that weren't modified for this long")))
+        .subcommand(SubCommand::with_name("gc")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Removes stale temporary directories and broken item links")
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .short("n")
+                .help("Only list what would be removed"))
+            .arg(Arg::with_name("older-than")
+                .long("older-than")
+                .takes_value(true)
+                .value_name("HOURS")
+                .default_value("24")
+                .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|e| format!("{}: {}", v, e)))
+                .help("Only remove temporary directories that weren't modified for this long"))
+            .conditionally(cfg!(feature = "deprecated-items"), |app|
+            app.arg(Arg::with_name("unreachable")
+                .long("unreachable")
+                .help("Also list records that are not reachable from any item (they are never removed)"))))
+        .subcommand(SubCommand::with_name("search")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Searches records' text (the index is updated as necessary)")
+            .arg(Arg::with_name("terms")
+                .required(true)
+                .multiple(true)
+                .help("Terms to search for (all of them have to be present)"))
+            .arg(Arg::with_name("rebuild")
+                .long("rebuild")
+                .help("Rebuild the search index from scratch"))
+            .arg(Arg::with_name("limit")
+                .long("limit")
+                .short("n")
+                .takes_value(true)
+                .validator(|v| v.parse::<usize>().map(|_| ()).map_err(|e| format!("{}: {}", v, e)))
+                .help("Limit the number of results to N"))
+            .arg(format::arg().default_value("table")))
+        .subcommand(SubCommand::with_name("stats")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Groups reduced items (or records) and computes statistics")
+            .arg(Arg::with_name("group-by")
+                .long("group-by")
+                .short("g")
+                .takes_value(true)
+                .required(true)
+                .help("Group by the result of a JMESPath query (arrays put values into every group listed)"))
+            .arg(Arg::with_name("metric")
+                .long("metric")
+                .short("m")
+                .takes_value(true)
+                .help("Aggregate a numeric JMESPath query for every group (sum, mean, median, min and max)"))
+            .arg(Arg::with_name("filter")
+                .long("filter")
+                .short("f")
+                .takes_value(true)
+                .help("Only include values matching a JMESPath query"))
+            .arg(Arg::with_name("records")
+                .long("records")
+                .help("Group records instead of reduced items (always the case without items support)"))
+            .arg(format::arg().default_value("table")))
+        .subcommand(SubCommand::with_name("show")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Shows a record: its metadata followed by its files")
+            .arg(Arg::with_name("record")
+                .required(true)
+                .takes_value(true)
+                .help("Record hash"))
+            .arg(Arg::with_name("raw")
+                .long("raw")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Output record's file as is"))
+            .arg(Arg::with_name("gnupg")
+                .long("gnupg")
+                .takes_value(true)
+                .help("Specify gnupg command (`gpg` by default or overridden by config's signing.gnupg)")))
         .subcommand(SubCommand::with_name("upgrade")
             .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
             .about("Upgrades the repository"))
@@ -193,7 +401,7 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                      .required(false)
                      .help("Specify item identifier, otherwise generate automatically"))))
         .conditionally(cfg!(feature = "deprecated-items"), |app|
-        app.subcommand(SubCommand::with_name("items")
+        app.subcommand(with_pagination(SubCommand::with_name("items")
                .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
                .about("Lists items (DEPRECATED)")
                .arg(Arg::with_name("filter")
@@ -219,7 +427,8 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                    .long("named-query")
                    .short("Q")
                    .takes_value(true)
-                   .help("Render a result of a named JMESPath query over the item"))))
+                   .help("Render a result of a named JMESPath query over the item"))
+               .arg(format::arg()))))
         .subcommand(SubCommand::with_name("record")
             .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
             .about("Creates a new record")
@@ -253,11 +462,58 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                 .requires("sign")
                 .takes_value(true)
                 .help("Specify gnupg command (`gpg` by default or overridden by config's signing.gnupg)"))
+            .arg(Arg::with_name("encrypt")
+                .long("encrypt")
+                .short("e")
+                .long_help("Encrypts record's content files with GnuPG to every public key in the repository's \
+                recipients directory. Files starting with a dot (like .type/*) are kept intact.")
+                .help("Encrypt record for repository's recipients"))
+            .arg(Arg::with_name("edit")
+                .long("edit")
+                .long_help("Treats FILES as names of files to compose in the editor ($VISUAL, $EDITOR or vi), \
+                one after another, instead of reading them from the working directory. \
+                Lines starting with '#' are ignored and an empty file aborts the record.")
+                .help("Compose files in the editor"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .long_help("Reads a JSON object mapping file names to their contents from stdin. \
+                Contents are either strings or {\"base64\": \"...\"} objects for binary files.")
+                .help("Read files from JSON on stdin"))
+            .arg(Arg::with_name("file")
+                .long("file")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=VALUE|NAME=@PATH")
+                .help("Add a file with a given value or contents of a file at PATH"))
             .arg(Arg::with_name(command_record::FILES_ARG)
                      .multiple(true)
                      .takes_value(true)
                      .help(command_record::FILES_ARG_HELP)))
-        .subcommand(SubCommand::with_name("records")
+        .subcommand(SubCommand::with_name("redact")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Redacts files in a record")
+            .long_about("Replaces contents of the files with tombstones and creates a redaction record \
+            that keeps the redacted record valid under its original hash. Redaction records are signed \
+            with GnuPG, as unsigned ones are ignored")
+            .arg(Arg::with_name("record")
+                .required(true)
+                .takes_value(true)
+                .help("Record to redact"))
+            .arg(Arg::with_name("file")
+                .required(true)
+                .multiple(true)
+                .takes_value(true)
+                .help("Files to redact"))
+            .arg(Arg::with_name("signing-key")
+                .long("signing-key")
+                .takes_value(true)
+                .help("Specify non-default signing key (overrides config's signing.key)"))
+            .arg(Arg::with_name("gnupg")
+                .long("gnupg")
+                .takes_value(true)
+                .help("Specify gnupg command (`gpg` by default or overridden by config's signing.gnupg)")))
+        .subcommand(with_pagination(SubCommand::with_name("records")
             .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
             .about("Lists records")
             .conditionally(cfg!(feature = "deprecated-items"), |app|
@@ -296,7 +552,8 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                      .long("named-query")
                      .short("Q")
                      .takes_value(true)
-                     .help("Render a result of a named JMESPath query over the record")))
+                     .help("Render a result of a named JMESPath query over the record"))
+            .arg(format::arg())))
         .subcommand(SubCommand::with_name("reduce")
             .about("Reduce records")
             .conditionally(cfg!(feature = "deprecated-items"), |app|
@@ -315,12 +572,14 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                  .takes_value(true)
                  .multiple(true)
                  .help("Specifies fixed roots to begin the reduction from"))
-            .arg(Arg::with_name("format")
-                 .short("f")
-                 .long("format")
-                 .default_value("json")
-                 .possible_values(&["json"])
-                 .help("State's format"))
+            .arg(Arg::with_name("trace")
+                 .long("trace")
+                 .help("Prints input and output states of every reducer for every record to stderr"))
+            .arg(Arg::with_name("checkpoint")
+                 .long("checkpoint")
+                 .conflicts_with_all(&["root", "state"])
+                 .help("Resumes the reduction from the most recent applicable checkpoint and saves a new one"))
+            .arg(format::arg().short("f"))
             .arg(Arg::with_name("state")
                  .short("s")
                  .long("state")
@@ -340,6 +599,24 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                      .short("Q")
                      .takes_value(true)
                      .help("Render a result of a named JMESPath query")))
+        .subcommand(SubCommand::with_name("reducer")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto,
+                        clap::AppSettings::SubcommandRequiredElseHelp])
+            .about("Reducer tools")
+            .subcommand(SubCommand::with_name("test")
+                .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+                .about("Runs reducers against fixtures (records/* and expected.json) and reports differences")
+                .arg(Arg::with_name("reducer")
+                     .short("r")
+                     .long("reducer")
+                     .takes_value(true)
+                     .multiple(true)
+                     .number_of_values(1)
+                     .help("Specifies custom reducers to be used (instead of default ones in reducers/)"))
+                .arg(Arg::with_name("fixtures")
+                     .required(true)
+                     .multiple(true)
+                     .help("Fixture or a directory of fixtures"))))
         .subcommand(SubCommand::with_name("config")
             .about("Prints configuration file")
             .arg(Arg::with_name("kind")
@@ -353,7 +630,10 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                      .help("JMESPath query (none by default)")))
         .subcommand(SubCommand::with_name("modules")
             .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
-            .about("Prints out resolved modules"))
+            .about("Prints out resolved modules")
+            .arg(Arg::with_name("reducers")
+                .long("reducers")
+                .help("Prints out reducers in the order they are executed in")))
         .subcommand(SubCommand::with_name("jmespath")
             .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
             .arg(Arg::with_name("expr")
@@ -452,6 +732,9 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
             .expect("can't open repository");
         let integrity_check = !matches.is_present("disable-integrity-check") && !env::var("SIT_DISABLE_INTEGRITY_CHECK").is_ok();
         repo.set_integrity_check(integrity_check);
+        // redaction records are only honoured if their signatures can be verified
+        let verifier = verifier(matches.subcommand().1.unwrap_or(&matches), &config, &repo);
+        repo.set_verifier(verifier);
         return match repo.config().clone().extra().get("external_module_manager") {
             Some(serde_json::Value::String(name)) => {
                 let original_repo = repo.clone();
@@ -464,7 +747,30 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
             where MI: sit_core::repository::ModuleIterator<PathBuf, sit_core::repository::Error> {
             let working_dir = PathBuf::from(matches.value_of("working_directory").unwrap());
             let canonical_working_dir = dunce::canonicalize(&working_dir).expect("can't canonicalize working directory");
-            if let Some(_) = matches.subcommand_matches("modules") {
+            let verbosity = matches.occurrences_of("verbosity");
+            if let Some(matches) = matches.subcommand_matches("modules") {
+                if matches.is_present("reducers") {
+                    use sit_core::reducers::{SourceFiles, order};
+                    let files = match (&repo).source_files() {
+                        Ok(files) => files.collect::<Vec<_>>(),
+                        Err(e) => {
+                            eprintln!("Error: {:?}", e);
+                            return 1;
+                        },
+                    };
+                    match order::order(files, (&repo).repository_reducers(), "js") {
+                        Ok(reducers) => {
+                            for reducer in reducers {
+                                println!("{}", ::dunce::canonicalize(&reducer).unwrap_or(reducer).to_str().unwrap());
+                            }
+                            return 0;
+                        },
+                        Err(e) => {
+                            eprintln!("Can't order reducers: {}", e.explain());
+                            return 1;
+                        },
+                    }
+                }
                 match repo.module_iter() {
                     Ok(iter) => {
                         for module_path in iter {
@@ -508,19 +814,27 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
             }
 
             if let Some(matches) = matches.subcommand_matches("items") {
-                return command_items::command(matches, &repo, config);
+                return command_items::command(matches, &repo, config, verbosity);
             }
 
             if let Some(matches) = matches.subcommand_matches("record") {
                 return command_record::command(matches, &repo, config.clone(), canonical_working_dir, config_path);
             }
 
+            if let Some(matches) = matches.subcommand_matches("redact") {
+                return command_redact::command(matches, &repo, config);
+            }
+
             if let Some(matches) = matches.subcommand_matches("records") {
                 return command_records::command(matches, repo, config);
             }
 
             if let Some(matches) = matches.subcommand_matches("reduce") {
-                return command_reduce::command(matches, repo, config);
+                return command_reduce::command(matches, repo, config, verbosity);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("reducer") {
+                return command_reducer::command(matches, repo, config, verbosity);
             }
 
             if let Some(matches) = matches.subcommand_matches("config") {
@@ -530,8 +844,28 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                 return 0;
             }
 
-            if let Some(_) = matches.subcommand_matches("integrity") {
-                return command_integrity::command(repo);
+            if let Some(matches) = matches.subcommand_matches("search") {
+                return command_search::command(matches, &repo);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("stats") {
+                return command_stats::command(matches, &repo, config, verbosity);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("show") {
+                return command_show::command(matches, &repo, config);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("fsck") {
+                return command_fsck::command(matches, &repo);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("gc") {
+                return command_gc::command(matches, &repo);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("integrity") {
+                return command_integrity::command(matches, repo, config);
             }
 
             match command_external::command(&matches, repo, &cwd) {
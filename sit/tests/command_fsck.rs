@@ -0,0 +1,52 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use sit_core::{Record, Repository, path::HasPath, record::RecordOwningContainer};
+
+use cli_test_dir::*;
+use std::fs;
+
+/// Should succeed on a healthy repository
+#[test]
+fn fsck_healthy() {
+    let dir = TestDir::new("sit", "fsck_healthy");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![("text", &b"Hello"[..])].into_iter(), false).unwrap();
+    let output = String::from_utf8(dir.cmd().arg("fsck").expect_success().stdout).unwrap();
+    assert_eq!(output, "");
+}
+
+/// Should detect problems and move offenders into quarantine upon repair
+#[test]
+fn fsck_repair() {
+    let dir = TestDir::new("sit", "fsck_repair");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let record = repo.new_record(vec![("text", &b"Hello"[..])].into_iter(), false).unwrap();
+    dir.create_file(record.path().join("text"), "Tampered");
+    fs::create_dir_all(dir.path(".sit").join("sit.Xy12Zw")).unwrap();
+
+    // recently modified temporary directories are not reported by default
+    let output = String::from_utf8(dir.cmd().arg("fsck").expect_failure().stdout).unwrap();
+    assert!(output.lines().any(|line| line.starts_with("corrupt record records") && line.ends_with(&record.encoded_hash())));
+    assert!(!output.contains("sit.Xy12Zw"));
+
+    let output = String::from_utf8(dir.cmd().args(&["fsck", "--older-than", "0"]).expect_failure().stdout).unwrap();
+    assert!(output.contains("stray temporary directory sit.Xy12Zw"));
+
+    dir.cmd().args(&["fsck", "--repair", "--older-than", "0"]).expect_success();
+    assert!(!record.path().exists());
+    assert!(!dir.path(".sit").join("sit.Xy12Zw").exists());
+    let quarantine: Vec<_> = fs::read_dir(dir.path(".sit").join("quarantine")).unwrap()
+        .filter_map(Result::ok).filter(|entry| entry.path().is_dir()).collect();
+    assert_eq!(quarantine.len(), 1);
+    assert!(quarantine[0].path().join("report.json").is_file());
+    assert!(quarantine[0].path().join("sit.Xy12Zw").is_dir());
+
+    dir.cmd().arg("fsck").expect_success();
+}
@@ -0,0 +1,53 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use cli_test_dir::*;
+use std::fs;
+
+/// Should only remove temporary directories older than the threshold
+#[test]
+fn gc_temp_dirs() {
+    let dir = TestDir::new("sit", "gc_temp_dirs");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let temp = dir.path(".sit").join("sit.Ab12Cd");
+    fs::create_dir_all(&temp).unwrap();
+
+    let output = String::from_utf8(dir.cmd().arg("gc").expect_success().stdout).unwrap();
+    assert_eq!(output, "");
+    assert!(temp.is_dir());
+
+    let output = String::from_utf8(dir.cmd().args(&["gc", "--older-than", "0", "--dry-run"]).expect_success().stdout).unwrap();
+    assert_eq!(output, "would remove stale temporary directory sit.Ab12Cd\n");
+    assert!(temp.is_dir());
+
+    let output = String::from_utf8(dir.cmd().args(&["gc", "--older-than", "0"]).expect_success().stdout).unwrap();
+    assert_eq!(output, "removing stale temporary directory sit.Ab12Cd\n");
+    assert!(!temp.exists());
+}
+
+/// Should remove broken item links and list unreachable records
+#[test]
+#[cfg(feature = "deprecated-items")]
+fn gc_items() {
+    use sit_core::{Item, Record, Repository, path::HasPath, record::RecordOwningContainer};
+    let dir = TestDir::new("sit", "gc_items");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let item = repo.new_item().unwrap();
+    let record = item.new_record(vec![("text", &b"Hello"[..])].into_iter(), false).unwrap();
+    let orphan = repo.new_record(vec![("text", &b"Orphan"[..])].into_iter(), false).unwrap();
+    let link = dir.path(".sit").join("items").join(item.id()).join(record.encoded_hash());
+    fs::remove_dir_all(record.path()).unwrap();
+
+    let output = String::from_utf8(dir.cmd().args(&["gc", "--unreachable"]).expect_success().stdout).unwrap();
+    let relative_link = ::std::path::Path::new("items").join(item.id()).join(record.encoded_hash());
+    assert!(output.contains(&format!("removing broken item link {}\n", relative_link.display())));
+    assert!(output.contains(&format!("unreachable record {}\n", orphan.encoded_hash())));
+    assert!(!link.exists());
+    // records are never removed
+    assert!(orphan.path().is_dir());
+}
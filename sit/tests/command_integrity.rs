@@ -116,3 +116,39 @@ fn integrity_check_flag() {
      let output = String::from_utf8(dir.cmd().env("SIT_DISABLE_INTEGRITY_CHECK", "1").args(&["records"]).expect_success().stdout).unwrap();
     assert_eq!(output, format!("{}\n", record.trim()));
 }
+
+/// Should list records that violate authorization policy
+#[test]
+fn integrity_policy_violation() {
+    let dir = TestDir::new("sit", "integrity_policy_violation");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/policy.json", r#"{"types": {"Closed": {"authors": ["Maintainer <m@example.com>"]}}}"#);
+    dir.create_file(".authors", "Someone <s@example.com>");
+    // unrestricted type
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .args(&["record", "--no-author", "-t", "Commented", ".authors"])
+        .expect_success();
+    dir.cmd().args(&["integrity", "--policy"]).expect_success();
+    // restricted type
+    let record = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .args(&["record", "--no-author", "-t", "Closed", ".authors"])
+        .expect_success().stdout).unwrap();
+    // integrity alone is not concerned with the policy
+    dir.cmd().arg("integrity").expect_success();
+    let output = String::from_utf8(dir.cmd().args(&["integrity", "--policy"]).expect_failure().stdout).unwrap();
+    assert_eq!(output, format!("{}\n", record.trim()));
+    // authorized author
+    dir.create_file(".authors", "Maintainer <m@example.com>");
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .args(&["record", "--no-author", "-t", "Closed", ".authors"])
+        .expect_success();
+    let output = String::from_utf8(dir.cmd().args(&["integrity", "--policy"]).expect_failure().stdout).unwrap();
+    assert_eq!(output, format!("{}\n", record.trim()));
+    assert_eq!(repo.policy().unwrap().types.len(), 1);
+}
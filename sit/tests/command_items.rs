@@ -4,6 +4,8 @@ extern crate sit_core;
 #[cfg(feature = "deprecated-items")]
 use sit_core::{Repository, record::RecordOwningContainer};
 #[cfg(feature = "deprecated-items")]
+use sit_core::serde_json::Value;
+#[cfg(feature = "deprecated-items")]
 use cli_test_dir::*;
 
 #[cfg(feature = "deprecated-items")]
@@ -199,3 +201,46 @@ fn item_repo_over_named_user_query() {
     assert_eq!(output.trim(), format!("item {} hello", id.trim()));
 }
 
+/// Should skip records not authorized by the policy
+#[test]
+#[cfg(feature = "deprecated-items")]
+fn item_policy() {
+    let dir = TestDir::new("sit", "item_policy");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/policy.json", r#"{"types": {"Closed": {"authors": ["Maintainer <m@example.com>"]}}}"#);
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        if (typeof record.files[".type/Closed"] !== 'undefined') {
+          return Object.assign(state, {closed: true});
+        }
+        return state;
+    }
+    "#);
+    let id = String::from_utf8(dir.cmd().arg("item").expect_success().stdout).unwrap();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.item(id.trim()).unwrap().new_record(vec![(".type/Closed", &b""[..]), (".authors", &b"Someone <s@example.com>"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["items", "-f", "closed == `true`"]).expect_success().stdout).unwrap();
+    assert_eq!(output, "");
+    repo.item(id.trim()).unwrap().new_record(vec![(".type/Closed", &b""[..]), (".authors", &b"Maintainer <m@example.com>"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["items", "-f", "closed == `true`"]).expect_success().stdout).unwrap();
+    assert_eq!(output, id);
+}
+
+/// Should reduce natively handled types when repository opts into native reducers
+#[test]
+#[cfg(feature = "deprecated-items")]
+fn item_native_reducers() {
+    let dir = TestDir::new("sit", "item_native_reducers");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let mut repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.config_mut().set_extra_properties(vec![("native_reducers", Value::Bool(true))]);
+    repo.save().unwrap();
+    let id = String::from_utf8(dir.cmd().arg("item").expect_success().stdout).unwrap();
+    repo.item(id.trim()).unwrap().new_record(vec![(".type/Closed", &b""[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["items", "-f", "state == 'closed'"]).expect_success().stdout).unwrap();
+    assert_eq!(output, id);
+}
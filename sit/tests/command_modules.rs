@@ -78,3 +78,29 @@ fn modules_convention_ext_invalid() {
     let output = String::from_utf8(dir.cmd().arg("modules").expect_failure().stderr).unwrap();
     assert!(output.contains("Can't find external module manager `sit-modman`"));
 }
+
+/// Tests that `sit modules --reducers` lists reducers in the order declared in `reducers/order`
+#[test]
+fn modules_reducers_order() {
+    let dir = TestDir::new("sit", "modules_reducers_order");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+
+    dir.create_file(".sit/reducers/a.js", "module.exports = function(state) { return state; }");
+    dir.create_file(".sit/reducers/b.js", "module.exports = function(state) { return state; }");
+    dir.create_file(".sit/reducers/order", "b.js: test/c.js\n");
+    dir.create_file(".sit/modules/test/reducers/c.js", "module.exports = function(state) { return state; }");
+
+    let output = String::from_utf8(dir.cmd().args(&["modules", "--reducers"]).expect_success().stdout).unwrap();
+    let expected: Vec<_> = vec![".sit/reducers/a.js", ".sit/modules/test/reducers/c.js", ".sit/reducers/b.js"].into_iter()
+        .map(|p| dunce::canonicalize(dir.path(p)).unwrap().to_str().unwrap().to_string())
+        .collect();
+    assert_eq!(output.trim().lines().collect::<Vec<_>>(), expected);
+
+    // cycle: b.js -> c.js -> d.js -> b.js
+    dir.create_file(".sit/modules/test/reducers/d.js", "module.exports = function(state) { return state; }");
+    dir.create_file(".sit/modules/test/reducers/order", "c.js: d.js\nd.js: /b.js\n");
+    let output = String::from_utf8(dir.cmd().args(&["modules", "--reducers"]).expect_failure().stderr).unwrap();
+    assert!(output.contains("dependency cycle"));
+}
@@ -8,6 +8,9 @@ use cli_test_dir::*;
 use sit_core::{Repository, record::RecordContainer, record::RecordExt};
 use std::process;
 
+mod helpers;
+use helpers::*;
+
 include!("includes/config.rs");
 
 /// Should allow recording for an item
@@ -430,6 +433,281 @@ fn record_should_sign_if_instructed_cmdline() {
 }
 
 
+/// Should encrypt content files for repository's recipients
+#[test]
+fn record_should_encrypt() {
+    let dir = TestDir::new("sit", "rse"); // workaround for "File name too long" error
+    no_user_config(&dir);
+
+    let gpg = which::which("gpg2").or_else(|_| which::which("gpg")).expect("should have gpg installed");
+
+    let mut genkey = process::Command::new(&gpg)
+        .args(&["--batch", "--gen-key","-"])
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .stdin(::std::process::Stdio::piped())
+        .stdout(::std::process::Stdio::null())
+        .stderr(::std::process::Stdio::null())
+        .spawn().unwrap();
+
+    {
+        use std::io::Write;
+        let stdin = genkey.stdin.as_mut().expect("Failed to open stdin");
+        stdin.write_all(r#"
+        Key-Type: default
+        Subkey-Type: default
+        Name-Real: Test
+        Name-Comment: Test
+        Name-Email: test@test.com
+        Expire-Date: 0
+        %no-protection
+        %commit
+        "#.as_bytes()).expect("Failed to write to stdin");
+    }
+    genkey.expect_success();
+
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+
+    // no recipients yet
+    dir.create_file("text", "secret");
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .args(&["record", "--encrypt", "--no-author", "-t", "Commented", "text"])
+        .expect_failure();
+
+    let key = process::Command::new(&gpg)
+        .args(&["--armor", "--export", "test@test.com"])
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .expect_success().stdout;
+    ::std::fs::create_dir_all(dir.path(".sit").join("recipients")).unwrap();
+    dir.create_file(".sit/recipients/test.asc", String::from_utf8(key).unwrap());
+
+    dir.create_file(".sit/reducers/test.js", r#"
+    module.exports = function(state, record) {
+      return {text: new TextDecoder('utf-8').decode(record.files.text)};
+    }
+    "#);
+
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .args(&["record", "--encrypt", "--no-author", "-t", "Commented", "text"])
+        .expect_success();
+
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let mut records = repo.record_iter().unwrap();
+    let record = records.next().unwrap().pop().unwrap();
+    assert!(record.file(".encrypted").is_some());
+    assert!(record.file(".type/Commented").is_some());
+    let mut s = vec![];
+    use std::io::Read;
+    record.file("text").unwrap().read_to_end(&mut s).unwrap();
+    assert_ne!(s, b"secret");
+
+    // reducers see decrypted content if the key is available
+    let output = String::from_utf8(dir.cmd()
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .args(&["reduce", "-q", "text"])
+        .expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "secret");
+}
+
+/// Should compose files in the editor without touching the working directory
+#[test]
+fn record_edit() {
+    let dir = TestDir::new("sit", "record_edit");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    create_script(&dir, "editor", "editor.bat", r#"#! /usr/bin/env bash
+        echo "Edited comment" >> "$1"
+    "#, r#"
+    @echo off
+    echo Edited comment>> %1
+    "#);
+    let editor = if cfg!(windows) { dir.path("editor.bat") } else { dir.path("editor") };
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env_remove("VISUAL")
+        .env("EDITOR", editor.to_str().unwrap())
+        .args(&["record", "--no-author", "--edit", "-t", "Commented", "text"])
+        .expect_success();
+    assert!(!dir.path("text").exists());
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let mut records = repo.record_iter().unwrap();
+    let record = records.next().unwrap().pop().unwrap();
+    assert!(record.file(".type/Commented").is_some());
+    let mut s = String::new();
+    use std::io::Read;
+    record.file("text").unwrap().read_to_string(&mut s).unwrap();
+    assert_eq!(s.trim(), "Edited comment");
+}
+
+/// Should not record if a file composed in the editor is empty
+#[test]
+fn record_edit_empty() {
+    let dir = TestDir::new("sit", "record_edit_empty");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    create_script(&dir, "editor", "editor.bat", r#"#! /usr/bin/env bash
+        true
+    "#, r#"
+    @echo off
+    "#);
+    let editor = if cfg!(windows) { dir.path("editor.bat") } else { dir.path("editor") };
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env_remove("VISUAL")
+        .env("EDITOR", editor.to_str().unwrap())
+        .args(&["record", "--no-author", "--edit", "-t", "Commented", "text"])
+        .expect_failure();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    assert_eq!(repo.record_iter().unwrap().count(), 0);
+}
+
+/// Should record files from JSON on stdin
+#[test]
+fn record_json() {
+    use std::io::{Read, Write};
+    let dir = TestDir::new("sit", "record_json");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let mut child = dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "--json", "-t", "Commented"])
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .spawn().unwrap();
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin.write_all(br#"{"text": "Hello", "data": {"base64": "AAEC"}}"#).expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("failed to read stdout");
+    assert!(output.status.success());
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let record = repo.record(String::from_utf8(output.stdout).unwrap().trim()).unwrap();
+    let mut s = String::new();
+    record.file("text").unwrap().read_to_string(&mut s).unwrap();
+    assert_eq!(s, "Hello");
+    let mut data = vec![];
+    record.file("data").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, vec![0, 1, 2]);
+    assert!(record.file(".type/Commented").is_some());
+}
+
+/// Should not record if JSON is not an object of strings or base64 contents
+#[test]
+fn record_json_invalid() {
+    use std::io::Write;
+    let dir = TestDir::new("sit", "record_json_invalid");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let mut child = dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "--json", "-t", "Commented"])
+        .stdin(process::Stdio::piped())
+        .spawn().unwrap();
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin.write_all(br#"{"text": 1}"#).expect("Failed to write to stdin");
+    }
+    assert!(!child.wait().unwrap().success());
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    assert_eq!(repo.record_iter().unwrap().count(), 0);
+}
+
+/// Should record files given as values or paths with --file
+#[test]
+fn record_file_args() {
+    use std::io::Read;
+    let dir = TestDir::new("sit", "record_file_args");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file("scratch/comment.txt", "From a file");
+    dir.create_file("file1", "file1");
+    let output = dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "-t", "Commented", "--file", "text=Hello=World",
+                "--file", "comment=@scratch/comment.txt", "file1"])
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let record = repo.record(String::from_utf8(output.stdout).unwrap().trim()).unwrap();
+    let mut s = String::new();
+    record.file("text").unwrap().read_to_string(&mut s).unwrap();
+    assert_eq!(s, "Hello=World");
+    s.clear();
+    record.file("comment").unwrap().read_to_string(&mut s).unwrap();
+    assert_eq!(s, "From a file");
+    s.clear();
+    record.file("file1").unwrap().read_to_string(&mut s).unwrap();
+    assert_eq!(s, "file1");
+    assert!(record.file("scratch/comment.txt").is_none());
+
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "-t", "Commented", "--file", "text"])
+        .expect_failure();
+}
+
+/// Should not record files with the same name or names of auxiliary files
+#[test]
+fn record_duplicate_names() {
+    use std::io::Write;
+    let dir = TestDir::new("sit", "record_duplicate_names");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file("text", "text");
+    let record = |args: &[&str]| dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "-t", "Commented"])
+        .args(args)
+        .expect_failure();
+    let stderr = String::from_utf8(record(&["--file", "text=a", "--file", "text=b"]).stderr).unwrap();
+    assert!(stderr.contains("text is given more than once"));
+    let stderr = String::from_utf8(record(&["--file", "text=a", "text"]).stderr).unwrap();
+    assert!(stderr.contains("text is given more than once"));
+    let stderr = String::from_utf8(record(&["--file", ".type/Commented=", "text"]).stderr).unwrap();
+    assert!(stderr.contains(".type/Commented is produced by sit"));
+    let stderr = String::from_utf8(record(&["--file", ".prev/abc=", "text"]).stderr).unwrap();
+    assert!(stderr.contains(".prev/abc is produced by sit"));
+    let stderr = String::from_utf8(record(&["--file", "./.prev/abc=", "text"]).stderr).unwrap();
+    assert!(stderr.contains("./.prev/abc is produced by sit"));
+
+    let mut child = dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "--json", "-t", "Commented"])
+        .stdin(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn().unwrap();
+    {
+        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+        stdin.write_all(br#"{"text": "Hello", ".timestamp": "2018-01-01T00:00:00Z"}"#).expect("Failed to write to stdin");
+    }
+    let output = child.wait_with_output().expect("failed to read stderr");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(".timestamp is produced by sit"));
+
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    assert_eq!(repo.record_iter().unwrap().count(), 0);
+}
+
 fn verify_authors<S: AsRef<str>>(dir: &TestDir, expected: S) {
     let repo = Repository::open(dir.path(".sit")).unwrap();
     let mut records = repo.record_iter().unwrap();
@@ -134,6 +134,68 @@ fn query() {
 }
 
 
+/// Should support SIT functions in filters and queries
+#[test]
+fn sit_functions() {
+    let dir = TestDir::new("sit", "rec_sit_functions");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![("text", &b"Crash on startup"[..])].into_iter(), true).unwrap();
+    repo.new_record(vec![("text", &b"Typo"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["records", "-f", "matches(lower(file_text(@, 'text')), '^crash')",
+                                                   "-q", "file_text(@, 'text')"]).expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "Crash on startup");
+}
+
+
+/// Should sort and paginate records
+#[test]
+fn sort_and_paginate() {
+    let dir = TestDir::new("sit", "rec_sort_and_paginate");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    for n in &["2", "10", "1"] {
+        repo.new_record(vec![("n", n.as_bytes())].into_iter(), true).unwrap();
+    }
+    let output = String::from_utf8(dir.cmd().args(&["records", "-q", "files.n", "--sort-by", "to_number(files.n)"])
+                                   .expect_success().stdout).unwrap();
+    assert_eq!(output, "1\n2\n10\n");
+    let output = String::from_utf8(dir.cmd().args(&["records", "-q", "files.n", "--sort-by", "to_number(files.n)", "--reverse"])
+                                   .expect_success().stdout).unwrap();
+    assert_eq!(output, "10\n2\n1\n");
+    let output = String::from_utf8(dir.cmd().args(&["records", "-q", "files.n", "--offset", "1", "--limit", "1"])
+                                   .expect_success().stdout).unwrap();
+    assert_eq!(output, "10\n");
+    dir.cmd().args(&["records", "--limit", "many"]).expect_failure();
+}
+
+
+/// Should print results in requested formats
+#[test]
+fn formats() {
+    let dir = TestDir::new("sit", "rec_formats");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![("text", &b"Hello, world"[..])].into_iter(), true).unwrap();
+    repo.new_record(vec![("text", &b"Hi"[..])].into_iter(), true).unwrap();
+    let query = "{text: files.text, size: length(files.text)}";
+    let output = String::from_utf8(dir.cmd().args(&["records", "-q", query, "--format", "table"]).expect_success().stdout).unwrap();
+    assert_eq!(output, "text          size\nHello, world  12\nHi            2\n");
+    let output = String::from_utf8(dir.cmd().args(&["records", "-q", query, "--format", "csv"]).expect_success().stdout).unwrap();
+    assert_eq!(output, "text,size\n\"Hello, world\",12\nHi,2\n");
+    let output = String::from_utf8(dir.cmd().args(&["records", "-q", query, "--format", "ndjson"]).expect_success().stdout).unwrap();
+    assert_eq!(output, "{\"size\":12,\"text\":\"Hello, world\"}\n{\"size\":2,\"text\":\"Hi\"}\n");
+    let output = String::from_utf8(dir.cmd().args(&["records", "-q", "{size: length(files.text)}", "--format", "yaml"]).expect_success().stdout).unwrap();
+    assert_eq!(output, "---\nsize: 12\n---\nsize: 2\n");
+}
+
+
 /// Should apply named query
 #[test]
 fn named_query() {
@@ -0,0 +1,178 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+extern crate which;
+
+use sit_core::{Repository, record::{RecordOwningContainer, RecordExt}, path::HasPath,
+               repository::TOMBSTONE_PREFIX};
+use cli_test_dir::*;
+use std::process;
+
+include!("includes/config.rs");
+
+fn generate_key(dir: &TestDir) {
+    let gpg = which::which("gpg2").or_else(|_| which::which("gpg")).expect("should have gpg installed");
+
+    let mut genkey = process::Command::new(&gpg)
+        .args(&["--batch", "--gen-key","-"])
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .stdin(::std::process::Stdio::piped())
+        .stdout(::std::process::Stdio::null())
+        .stderr(::std::process::Stdio::null())
+        .spawn().unwrap();
+
+    {
+        use std::io::Write;
+        let stdin = genkey.stdin.as_mut().expect("Failed to open stdin");
+        stdin.write_all(r#"
+        Key-Type: default
+        Subkey-Type: default
+        Name-Real: Test
+        Name-Comment: Test
+        Name-Email: test@test.com
+        Expire-Date: 0
+        %no-protection
+        %commit
+        "#.as_bytes()).expect("Failed to write to stdin");
+    }
+    genkey.expect_success();
+}
+
+/// Should replace redacted file with a tombstone and keep the record valid
+#[test]
+fn redact() {
+    let dir = TestDir::new("sit", "redact");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    no_user_config(&dir);
+    generate_key(&dir);
+    dir.create_file("text", "secret");
+    let record = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "-t", "Commented", "text"])
+        .expect_success().stdout).unwrap();
+    let redaction = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .args(&["redact", "--signing-key", "test@test.com", record.trim(), "text"])
+        .expect_success().stdout).unwrap();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec = repo.record(record.trim()).unwrap();
+    let mut s = String::new();
+    use std::io::Read;
+    ::std::fs::File::open(rec.path().join("text")).unwrap().read_to_string(&mut s).unwrap();
+    assert!(s.starts_with(TOMBSTONE_PREFIX));
+    assert!(repo.record(redaction.trim()).unwrap().file(".signature").is_some());
+    // both records are listed (when the signature can be verified)
+    let mut records: Vec<_> = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .arg("records")
+        .expect_success().stdout).unwrap().lines().map(String::from).collect();
+    records.sort();
+    let mut expected = vec![String::from(record.trim()), String::from(redaction.trim())];
+    expected.sort();
+    assert_eq!(records, expected);
+    // and integrity check passes
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .arg("integrity")
+        .expect_success();
+}
+
+/// Should not resume reduction from checkpoints taken before the redaction
+#[test]
+fn redact_checkpoint() {
+    let dir = TestDir::new("sit", "redact_checkpoint");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    no_user_config(&dir);
+    generate_key(&dir);
+    dir.create_file(".sit/reducers/test.js", r#"
+    module.exports = function(state, record) {
+      if (typeof record.files.text === 'undefined') return state;
+      return Object.assign(state, {text: new TextDecoder('utf-8').decode(record.files.text)});
+    }
+    "#);
+    dir.create_file("text", "secret");
+    let record = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "-t", "Commented", "text"])
+        .expect_success().stdout).unwrap();
+    let output = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .args(&["reduce", "--checkpoint", "-q", "text"])
+        .expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "secret");
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .args(&["redact", "--signing-key", "test@test.com", record.trim(), "text"])
+        .expect_success();
+    let output = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .args(&["reduce", "--checkpoint", "-q", "text"])
+        .expect_success().stdout).unwrap();
+    assert!(!output.contains("secret"));
+}
+
+/// Should ignore redaction records that are not signed
+#[test]
+fn redact_unsigned() {
+    let dir = TestDir::new("sit", "redact_unsigned");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    no_user_config(&dir);
+    dir.create_file("text", "secret");
+    let record = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "-t", "Commented", "text"])
+        .expect_success().stdout).unwrap();
+    // redact it without signing the redaction record
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec = repo.record(record.trim()).unwrap();
+    let redaction = repo.redaction(&rec, &["text"]).unwrap();
+    repo.new_record(redaction.files(), true).unwrap();
+    repo.redact(&redaction).unwrap();
+    // the redacted record doesn't pass integrity check
+    let output = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .arg("integrity")
+        .expect_failure().stdout).unwrap();
+    assert_eq!(output.trim(), record.trim());
+}
+
+/// Should fail if the file is not in the record
+#[test]
+fn redact_missing_file() {
+    let dir = TestDir::new("sit", "redact_missing_file");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    no_user_config(&dir);
+    let record = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["record", "--no-author", "-t", "Commented"])
+        .expect_success().stdout).unwrap();
+    dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["redact", record.trim(), "text"])
+        .expect_failure();
+}
@@ -244,3 +244,144 @@ fn reduce_repo_initial_state() {
     expect.insert("value".into(), serde_json::Value::String("01".into()));
     assert_eq!(serde_json::from_str::<serde_json::Value>(output.trim()).unwrap(), serde_json::Value::Object(expect));
 }
+
+/// Should skip records not authorized by the policy
+#[test]
+fn reduce_policy() {
+    let dir = TestDir::new("sit", "reduce_policy");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/policy.json", r#"{"types": {"Closed": {"authors": ["Maintainer <m@example.com>"]}}}"#);
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        if (typeof record.files[".type/Closed"] !== 'undefined') {
+          return Object.assign(state, {closed: true});
+        }
+        return state;
+    }
+    "#);
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![(".type/Closed", &b""[..]), (".authors", &b"Someone <s@example.com>"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "closed"]).expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "null");
+    repo.new_record(vec![(".type/Closed", &b""[..]), (".authors", &b"Maintainer <m@example.com>"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "closed"]).expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "true");
+}
+
+/// Should not pass records claimed by native reducers to JavaScript reducers
+#[test]
+fn reduce_native_claimed() {
+    let dir = TestDir::new("sit", "reduce_native_claimed");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        return Object.assign(state, {seen: (state.seen || 0) + 1});
+    }
+    "#);
+    let mut repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.config_mut().set_extra_properties(vec![("native_reducers", serde_json::Value::Array(vec!["Closed".into()]))]);
+    repo.save().unwrap();
+    repo.new_record(vec![(".type/Closed", &b""[..])].into_iter(), true).unwrap();
+    repo.new_record(vec![(".type/Test", &b""[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "[state, seen]"]).expect_success().stdout).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&output).unwrap(), serde_json::from_str::<serde_json::Value>(r#"["closed", 1]"#).unwrap());
+}
+
+/// Should print a trace of every reducer's execution to stderr
+#[test]
+#[cfg(not(feature = "quickjs-reducers"))]
+fn reduce_trace() {
+    let dir = TestDir::new("sit", "reduce_trace");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        return Object.assign(state, {value: "hello"});
+    }
+    "#);
+    let record = Repository::open(dir.path(".sit")).unwrap().new_record(vec![(".type/Test", &b""[..])].into_iter(), true).unwrap();
+    let output = dir.cmd().args(&["reduce", "--trace", "-q", "value"]).expect_success();
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "hello");
+    let trace = String::from_utf8(output.stderr).unwrap();
+    assert!(trace.contains(&format!("record {} [Test]", record.encoded_hash())));
+    assert!(trace.contains("test.js"));
+    assert!(trace.contains("    input: {}"));
+    assert!(trace.contains(r#"    output: {"value":"hello"}"#));
+}
+
+/// Should refuse to run QuickJS reducers when limits are configured
+#[test]
+#[cfg(feature = "quickjs-reducers")]
+fn reduce_quickjs_limits() {
+    let dir = TestDir::new("sit", "reduce_quickjs_limits");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        return Object.assign(state, {value: "hello"});
+    }
+    "#);
+    Repository::open(dir.path(".sit")).unwrap().new_record(vec![(".type/Test", &b""[..])].into_iter(), true).unwrap();
+    user_config(&dir, r#"{"reducers": {"timeout": 100}}"#);
+    let output = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap())
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["reduce", "-q", "value"]).expect_failure().stderr).unwrap();
+    assert!(output.contains("not supported by QuickJS reducers"));
+}
+
+/// Tests that `sit reduce --checkpoint` resumes from a checkpoint
+#[test]
+fn reduce_checkpoint() {
+    let dir = TestDir::new("sit", "reduce_checkpoint");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        this.count = (this.count || 0) + 1;
+        return Object.assign(state, {count: this.count});
+    }
+    "#);
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![(".type/Test", &b""[..])].into_iter(), true).unwrap();
+    let record = repo.new_record(vec![(".type/Test", &b""[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "--checkpoint", "-q", "count"]).expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "2");
+
+    let checkpoint_path = dir.path(".sit/checkpoints").join(format!("{}.json", record.encoded_hash()));
+    let mut checkpoint: serde_json::Value = serde_json::from_reader(::std::fs::File::open(&checkpoint_path).unwrap()).unwrap();
+    checkpoint["state"]["marker"] = serde_json::Value::Bool(true);
+    serde_json::to_writer(::std::fs::File::create(&checkpoint_path).unwrap(), &checkpoint).unwrap();
+
+    let record = repo.new_record(vec![(".type/Test", &b""[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "--checkpoint", "-q", "[count, marker]"]).expect_success().stdout).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&output).unwrap(), serde_json::from_str::<serde_json::Value>("[3, true]").unwrap());
+    // the new checkpoint supersedes the old one
+    assert!(!checkpoint_path.exists());
+    assert!(dir.path(".sit/checkpoints").join(format!("{}.json", record.encoded_hash())).is_file());
+
+    // without checkpoints, everything is reduced
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "[count, marker]"]).expect_success().stdout).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&output).unwrap(), serde_json::from_str::<serde_json::Value>("[3, null]").unwrap());
+}
+
+/// Tests that `sit reduce --checkpoint` refuses to store decrypted state
+#[test]
+fn reduce_checkpoint_encrypted() {
+    let dir = TestDir::new("sit", "reduce_checkpoint_encrypted");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![(".type/Test", &b""[..]), (".encrypted", &b""[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "--checkpoint"]).expect_failure().stderr).unwrap();
+    assert!(output.contains("Checkpoints can't be used"));
+    assert!(!dir.path(".sit/checkpoints").exists());
+}
@@ -0,0 +1,39 @@
+extern crate cli_test_dir;
+
+use cli_test_dir::*;
+
+fn create_fixture(dir: &TestDir, name: &str, expected: &str) {
+    dir.create_file(format!("fixtures/{}/records/01/.type/SummaryChanged", name), "");
+    dir.create_file(format!("fixtures/{}/records/01/text", name), "Title");
+    dir.create_file(format!("fixtures/{}/records/02/.type/Closed", name), "");
+    dir.create_file(format!("fixtures/{}/expected.json", name), expected);
+}
+
+/// Should report passing and failing fixtures
+#[test]
+fn reducer_test() {
+    let dir = TestDir::new("sit", "reducer_test");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        if (typeof record.files[".type/SummaryChanged"] !== 'undefined') {
+          return Object.assign(state, {summary: new TextDecoder().decode(record.files.text)});
+        }
+        if (typeof record.files[".type/Closed"] !== 'undefined') {
+          return Object.assign(state, {state: "closed"});
+        }
+    }
+    "#);
+    create_fixture(&dir, "passing", r#"{"summary": "Title", "state": "closed"}"#);
+    let output = String::from_utf8(dir.cmd().args(&["reducer", "test", "fixtures/passing"]).expect_success().stdout).unwrap();
+    assert!(output.contains("ok "));
+    assert!(output.trim().ends_with("1 passed, 0 failed"));
+
+    create_fixture(&dir, "failing", r#"{"summary": "Another title", "state": "closed"}"#);
+    let output = String::from_utf8(dir.cmd().args(&["reducer", "test", "fixtures"]).expect_failure().stdout).unwrap();
+    assert!(output.contains("FAIL "));
+    assert!(output.contains(r#"  summary: expected "Another title", got "Title""#));
+    assert!(output.trim().ends_with("1 passed, 1 failed"));
+}
@@ -0,0 +1,29 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use sit_core::{Repository, Record, record::RecordOwningContainer};
+
+use cli_test_dir::*;
+
+/// Should find records containing all terms
+#[test]
+fn search() {
+    let dir = TestDir::new("sit", "search");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let root = repo.new_record(vec![("text", &b"Tests are flaky"[..])].into_iter(), false).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["search", "Flaky", "--format", "ndjson"]).expect_success().stdout).unwrap();
+    assert_eq!(output, format!("{{\"file\":\"text\",\"record\":\"{hash}\",\"roots\":\"{hash}\",\"snippet\":\"Tests are flaky\"}}\n",
+                               hash = root.encoded_hash()));
+    assert!(dir.path(".sit/search/index.json").is_file());
+
+    // the index is updated with new records
+    let comment = repo.new_record(vec![("text", &b"That flaky test again"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["search", "flaky", "again", "--format", "csv"]).expect_success().stdout).unwrap();
+    assert_eq!(output, format!("record,file,snippet,roots\n{},text,That flaky test again,{}\n", comment.encoded_hash(), root.encoded_hash()));
+
+    let output = String::from_utf8(dir.cmd().args(&["search", "missing", "--rebuild"]).expect_success().stdout).unwrap();
+    assert_eq!(output, "");
+}
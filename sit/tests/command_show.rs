@@ -0,0 +1,44 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use sit_core::{Repository, Record, record::RecordOwningContainer};
+
+use cli_test_dir::*;
+
+/// Should show record's metadata and files
+#[test]
+fn show() {
+    let dir = TestDir::new("sit", "show");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let parent = repo.new_record(vec![("text", &b"First"[..])].into_iter(), false).unwrap();
+    let record = repo.new_record(vec![(".type/Commented", &b""[..]),
+                                      (".authors", &b"John Doe <john@doe>\n"[..]),
+                                      (".timestamp", &b"2018-06-14T09:35:11.123Z"[..]),
+                                      ("text", &b"Hello\nworld"[..]),
+                                      ("image.png", &b"\x89PNG\r\n\x1a\n\x00"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["show", &record.encoded_hash()]).expect_success().stdout).unwrap();
+    assert!(output.starts_with(&format!("record     {}\ntypes      Commented\nauthors    John Doe <john@doe>\n\
+                                         timestamp  2018-06-14T09:35:11.123Z\nparents    {}\nsignature  none\n",
+                                        record.encoded_hash(), parent.encoded_hash())));
+    assert!(output.contains("\n--- text\nHello\nworld\n"));
+    assert!(output.contains("\n--- image.png (binary, 9 bytes, image/png)\n"));
+    assert!(!output.contains("--- .authors"));
+}
+
+/// Should output a file as is
+#[test]
+fn show_raw() {
+    let dir = TestDir::new("sit", "show_raw");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let record = repo.new_record(vec![("data", &b"\x00\x01binary"[..])].into_iter(), false).unwrap();
+    let output = dir.cmd().args(&["show", &record.encoded_hash(), "--raw", "data"]).expect_success().stdout;
+    assert_eq!(output, b"\x00\x01binary");
+    dir.cmd().args(&["show", &record.encoded_hash(), "--raw", "missing"]).expect_failure();
+    dir.cmd().args(&["show", "NOSUCHRECORD"]).expect_failure();
+}
@@ -0,0 +1,31 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+extern crate serde_json;
+
+use sit_core::{Repository, record::RecordOwningContainer};
+
+use cli_test_dir::*;
+
+/// Should group records and aggregate metrics
+#[test]
+fn records_per_week() {
+    let dir = TestDir::new("sit", "stats_records_per_week");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    for &(timestamp, size) in &[("2018-01-01T10:00:00Z", &b"a"[..]), ("2018-01-03T10:00:00Z", &b"abc"[..]), ("2018-01-08T10:00:00Z", &b"ab"[..])] {
+        repo.new_record(vec![(".timestamp", timestamp.as_bytes()), ("text", size)].into_iter(), true).unwrap();
+    }
+    let output = String::from_utf8(dir.cmd().args(&["stats", "--records", "-g", "week(files.\".timestamp\")"])
+                                   .expect_success().stdout).unwrap();
+    assert_eq!(output, "key       count\n2018-W01  2\n2018-W02  1\n");
+
+    let output = String::from_utf8(dir.cmd().args(&["stats", "--records", "-g", "week(files.\".timestamp\")",
+                                                   "-m", "length(files.text)", "-f", "files.text != 'ab'", "--format", "json"])
+                                   .expect_success().stdout).unwrap();
+    let groups: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(groups, serde_json::from_str::<serde_json::Value>(r#"[
+      {"key": "2018-W01", "count": 2, "metric": {"count": 2, "sum": 4.0, "mean": 2.0, "median": 2.0, "min": 1.0, "max": 3.0}}
+    ]"#).unwrap());
+}